@@ -81,7 +81,11 @@ impl aabb {
             }
         }
     }
-    pub fn hit(&self, r: &Ray, mut t: Interval) -> bool {
+    pub fn hit(&self, r: &Ray, t: Interval) -> bool {
+        self.clip(r, t).is_some()
+    }
+    // the part of t where r is inside the box
+    pub fn clip(&self, r: &Ray, mut t: Interval) -> Option<Interval> {
         let ray_orig = r.origin();
         let ray_dir = r.direction();
 
@@ -99,8 +103,8 @@ impl aabb {
                 if t0 < t.max { t.max = t0; }
             }
         }
-        if t.min >= t.max { return false; }
-        return true;
+        if t.min >= t.max { return None; }
+        Some(t)
     }
 }
 
@@ -0,0 +1,114 @@
+use crate::texture::{image_data, image_texture};
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+pub enum asset_error {
+    NotFound(PathBuf),
+    Unreadable(PathBuf, String),
+}
+
+impl fmt::Display for asset_error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            asset_error::NotFound(path) => write!(f, "asset not found: {}", path.display()),
+            asset_error::Unreadable(path, reason) => write!(f, "cannot read asset {}: {}", path.display(), reason),
+        }
+    }
+}
+
+impl fmt::Debug for asset_error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl std::error::Error for asset_error {}
+
+// Decodes every image once and hands out shared handles. Relative paths are
+// resolved against base_dir, which is the directory of the scene file if there is one.
+pub struct asset_manager {
+    pub base_dir: PathBuf,
+    // keyed by canonical path and whether the pixels were converted from srgb
+    images: Mutex<HashMap<(PathBuf, bool), Arc<image_data>>>,
+}
+
+impl asset_manager {
+    pub fn new() -> Self {
+        Self { base_dir: PathBuf::from("."), images: Mutex::new(HashMap::new()) }
+    }
+    pub fn for_scene_file(scene_file: &str) -> Self {
+        let base_dir = match Path::new(scene_file).parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+            _ => PathBuf::from("."),
+        };
+        Self { base_dir, images: Mutex::new(HashMap::new()) }
+    }
+    pub fn resolve(&self, path: &str) -> PathBuf {
+        let path = Path::new(path);
+        if path.is_absolute() {
+            path.to_path_buf()
+        } else {
+            self.base_dir.join(path)
+        }
+    }
+    // srgb = true for color images, false for data such as normal or height maps
+    pub fn load_image(&self, path: &str, srgb: bool) -> Result<Arc<image_data>, asset_error> {
+        let resolved = self.resolve(path);
+        let canonical = match resolved.canonicalize() {
+            Ok(p) => p,
+            Err(_) => return Err(asset_error::NotFound(resolved)),
+        };
+        if let Some(data) = self.images.lock().unwrap().get(&(canonical.clone(), srgb)) {
+            return Ok(data.clone());
+        }
+        // decode outside the lock, a racing load of the same file just loses the insert
+        let data = Arc::new(image_data::load(&canonical, srgb)?);
+        let mut images = self.images.lock().unwrap();
+        Ok(images.entry((canonical, srgb)).or_insert(data).clone())
+    }
+    pub fn texture(&self, path: &str) -> Result<Arc<image_texture>, asset_error> {
+        Ok(Arc::new(image_texture::new_from_data(self.load_image(path, true)?)))
+    }
+    pub fn data_texture(&self, path: &str) -> Result<Arc<image_texture>, asset_error> {
+        Ok(Arc::new(image_texture::new_from_data(self.load_image(path, false)?)))
+    }
+    pub fn cached_images(&self) -> usize {
+        self.images.lock().unwrap().len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_missing_file() {
+        let assets = asset_manager::new();
+        match assets.load_image("no_such_texture.png", true) {
+            Err(asset_error::NotFound(_)) => {}
+            _ => panic!("expected NotFound"),
+        }
+    }
+
+    #[test]
+    fn test_cache_shares_data() {
+        let assets = asset_manager::for_scene_file("mc.scene");
+        let a = assets.load_image("leg_front.png", true).unwrap();
+        let b = assets.load_image("./leg_front.png", true).unwrap();
+        assert!(Arc::ptr_eq(&a, &b));
+        assert_eq!(assets.cached_images(), 1);
+    }
+
+    #[test]
+    fn test_direct_load_matches_manager() {
+        use crate::texture::texture;
+        use crate::vec3::Vec3;
+        let direct = image_texture::new("leg_front.png");
+        let managed = asset_manager::new().texture("leg_front.png").unwrap();
+        for (u, v) in [(0.1, 0.2), (0.5, 0.5), (0.9, 0.7)] {
+            assert_eq!(direct.value(u, v, &Vec3::zero()), managed.value(u, v, &Vec3::zero()));
+        }
+    }
+}
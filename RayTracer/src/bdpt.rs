@@ -0,0 +1,781 @@
+use crate::camera::Camera;
+use crate::hittable::{hit_record, Hittable};
+use crate::integrator::{integrator, power_heuristic, splat};
+use crate::interval::Interval;
+use crate::light::light;
+use crate::light_sampler::contains;
+use crate::ray::Ray;
+use crate::raytracer::random_double;
+use crate::vec3::Vec3;
+use std::sync::Arc;
+
+// Bidirectional path tracing (Veach 1997, in pbrt's formulation). Each camera sample
+// also traces a path from a light, and every prefix of one is joined to every prefix
+// of the other. The strategies are weighted by the balance heuristic, so light that is
+// hard to find from the camera (caustics, lights behind glass or in small openings)
+// comes from the light side. Joins that reach the camera (t = 1) land anywhere on the
+// image and are splatted there.
+//
+// Paths start only at lights with bounds. Lights at infinity are reached by camera
+// paths alone, through escaped rays and next event estimation. Fog is ignored.
+pub struct bdpt {
+    lights: Vec<Arc<dyn light + Send + Sync>>,
+    infinite: Vec<Arc<dyn light + Send + Sync>>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum vertex_kind {
+    Camera,
+    Light,
+    Surface,
+    Medium,
+}
+
+#[derive(Clone)]
+struct vertex {
+    kind: vertex_kind,
+    p: Vec3,
+    // zero where there is no surface to take cosines with: the camera, point lights, media
+    n: Vec3,
+    rec: Option<hit_record>,
+    // the ray that reached this vertex
+    r_in: Ray,
+    beta: Vec3,
+    // left by a delta lobe, whose density is 0; connectible() says if it can be joined
+    delta: bool,
+    // a Light vertex on a point or spot light
+    delta_light: bool,
+    // area densities of reaching this vertex from its predecessor on the path, and
+    // from its successor had the path been traced the other way
+    pdf_fwd: f64,
+    pdf_rev: f64,
+    // index into bdpt::lights of the light a Light vertex is on, or of the emitter a
+    // camera path hit
+    light: Option<usize>,
+}
+
+impl vertex {
+    fn new(kind: vertex_kind, p: Vec3, n: Vec3, beta: Vec3) -> Self {
+        Self {
+            kind,
+            p,
+            n,
+            rec: None,
+            r_in: Ray::new(p, Vec3::zero()),
+            beta,
+            delta: false,
+            delta_light: false,
+            pdf_fwd: 0.0,
+            pdf_rev: 0.0,
+            light: None,
+        }
+    }
+    fn on_surface(&self) -> bool {
+        !self.n.near_zero()
+    }
+    // a solid angle density at this vertex toward next, per unit area at next
+    fn convert_density(&self, pdf: f64, next: &vertex) -> f64 {
+        let w = next.p - self.p;
+        let d2 = w.squared_length();
+        if d2 <= 0.0 {
+            return 0.0;
+        }
+        let mut pdf = pdf / d2;
+        if next.on_surface() {
+            pdf *= next.n.dot(w).abs() / d2.sqrt();
+        }
+        pdf
+    }
+    // f * cos toward next
+    fn f(&self, next: &vertex) -> Vec3 {
+        match &self.rec {
+            Some(rec) => rec.mat.bsdf_cos(
+                &self.r_in,
+                rec,
+                &Ray::new_with_time(self.p, next.p - self.p, self.r_in.time()),
+            ),
+            None => Vec3::zero(),
+        }
+    }
+    // has a smooth lobe to join at; delta only tells which lobe the path left by
+    fn connectible(&self) -> bool {
+        self.rec
+            .as_ref()
+            .map_or(false, |rec| rec.mat.has_smooth_lobe(&self.r_in, rec))
+    }
+}
+
+fn pick(n: usize) -> usize {
+    ((random_double() * n as f64) as usize).min(n - 1)
+}
+
+impl bdpt {
+    // without lights until prepare() hands it the camera's
+    pub fn new() -> Self {
+        Self::new_with_lights(&[])
+    }
+    pub fn new_with_lights(lights: &[Arc<dyn light + Send + Sync>]) -> Self {
+        let (lights, infinite) = lights.iter().cloned().partition(|l| l.bounds().is_some());
+        Self { lights, infinite }
+    }
+}
+
+impl integrator for bdpt {
+    fn prepare(
+        &self,
+        cam: &Camera,
+        world: &Arc<dyn Hittable + Send + Sync>,
+    ) -> Option<Arc<dyn integrator + Send + Sync>> {
+        Some(Arc::new(Self::new_with_lights(
+            cam.light_sampler().lights(),
+        )))
+    }
+
+    // Radiance through the camera ray r. Contributions that land on other pixels are
+    // pushed to splats, to be added without the pixel's own samples.
+    fn li(
+        &self,
+        cam: &Camera,
+        r: &Ray,
+        world: &Arc<dyn Hittable + Send + Sync>,
+        splats: &mut Vec<splat>,
+    ) -> Vec3 {
+        let mut eye = vertex::new(vertex_kind::Camera, r.origin(), Vec3::zero(), Vec3::ones());
+        eye.r_in = r.clone();
+        let mut camera_path = vec![eye];
+        let escaped = self.random_walk(
+            world,
+            r.clone(),
+            Vec3::ones(),
+            cam.direction_pdf(r.direction()),
+            cam.max_depth + 1,
+            true,
+            &mut camera_path,
+        );
+        let light_path = self.light_path(world, r.time(), cam.max_depth);
+
+        let mut c = Vec3::zero();
+        if let Some((ray, beta, pdf)) = escaped {
+            // rays straight from the camera can only be found this way
+            let bsdf_pdf = if camera_path.len() > 1 { pdf } else { 0.0 };
+            c += self.escaped_radiance(cam, &ray, bsdf_pdf) * beta;
+        }
+        for t in 1..=camera_path.len() {
+            if t >= 2 {
+                c += self.sample_infinite(world, &camera_path[t - 1]);
+            }
+            for s in 0..=light_path.len() {
+                if (s == 1 && t == 1) || s + t < 2 || s + t - 2 > cam.max_depth {
+                    continue;
+                }
+                c += self.connect(cam, world, &light_path, &camera_path, s, t, splats);
+            }
+        }
+        c
+    }
+}
+
+impl bdpt {
+    fn light_path(
+        &self,
+        world: &Arc<dyn Hittable + Send + Sync>,
+        time: f64,
+        max_depth: usize,
+    ) -> Vec<vertex> {
+        let mut path = Vec::new();
+        if self.lights.is_empty() {
+            return path;
+        }
+        let i = pick(self.lights.len());
+        let pmf = 1.0 / self.lights.len() as f64;
+        let Some(e) = self.lights[i].sample_emission() else {
+            return path;
+        };
+        if e.pdf_pos <= 0.0 || e.pdf_dir <= 0.0 || e.radiance.near_zero() {
+            return path;
+        }
+        let mut v = vertex::new(vertex_kind::Light, e.p, e.n, e.radiance / (e.pdf_pos * pmf));
+        v.light = Some(i);
+        v.delta_light = e.delta_position;
+        v.pdf_fwd = e.pdf_pos * pmf;
+        path.push(v);
+        let cosine = if e.n.near_zero() {
+            1.0
+        } else {
+            e.n.dot(e.direction.normalize()).abs()
+        };
+        let beta = e.radiance * (cosine / (pmf * e.pdf_pos * e.pdf_dir));
+        self.random_walk(
+            world,
+            Ray::new_with_time(e.p, e.direction, time),
+            beta,
+            e.pdf_dir,
+            max_depth,
+            false,
+            &mut path,
+        );
+        path
+    }
+
+    // Extends path, whose last vertex shot r with solid angle density pdf_dir, up to
+    // max_vertices. Returns the ray that left the scene with its throughput and
+    // density, if one did.
+    fn random_walk(
+        &self,
+        world: &Arc<dyn Hittable + Send + Sync>,
+        mut r: Ray,
+        mut beta: Vec3,
+        mut pdf_dir: f64,
+        max_vertices: usize,
+        from_camera: bool,
+        path: &mut Vec<vertex>,
+    ) -> Option<(Ray, Vec3, f64)> {
+        while path.len() < max_vertices {
+            let mut rec = hit_record::new();
+            let mut hit = world.hit(&r, Interval::new(0.001, f64::INFINITY), &mut rec);
+            // camera rays go through emitters hidden from the camera
+            while hit && from_camera && path.len() == 1 && !rec.mat.visible_to_camera() {
+                let t = rec.t;
+                hit = world.hit(&r, Interval::new(t + 0.001, f64::INFINITY), &mut rec);
+            }
+            if !hit {
+                return Some((r, beta, pdf_dir));
+            }
+            rec.footprint = r.footprint(rec.t);
+            let volumetric = rec.mat.is_volumetric();
+            let kind = if volumetric {
+                vertex_kind::Medium
+            } else {
+                vertex_kind::Surface
+            };
+            let mut v = vertex::new(
+                kind,
+                rec.p,
+                if volumetric { Vec3::zero() } else { rec.normal },
+                beta,
+            );
+            v.r_in = r.clone();
+            v.pdf_fwd = path[path.len() - 1].convert_density(pdf_dir, &v);
+            if from_camera && !rec.mat.emitted_at(&r, &rec).near_zero() {
+                v.light = self.find_light(&r, &rec.p);
+            }
+            v.rec = Some(rec.clone());
+            path.push(v);
+            if path.len() >= max_vertices {
+                break;
+            }
+
+            let mut attenuation = Vec3::zero();
+            let mut scattered = Ray::new(Vec3::zero(), Vec3::zero());
+            let mut pdf = 0.0;
+            if !rec
+                .mat
+                .scatter_with_pdf(&r, &rec, &mut attenuation, &mut scattered, &mut pdf)
+            {
+                break;
+            }
+            scattered.inherit_footprint(&r, rec.footprint);
+            let n = path.len();
+            let mut pdf_rev = 0.0;
+            if pdf > 0.0 {
+                // the same vertex, entered along scattered and leaving back along r
+                let reversed =
+                    Ray::new_with_time(scattered.at(1.0), scattered.direction() * -1.0, r.time());
+                let back = Ray::new_with_time(rec.p, r.direction() * -1.0, r.time());
+                pdf_rev = rec.mat.scattering_pdf(&reversed, &rec, &back);
+            } else {
+                path[n - 1].delta = true;
+            }
+            path[n - 2].pdf_rev = path[n - 1].convert_density(pdf_rev, &path[n - 2]);
+            beta = beta * attenuation;
+            if beta.near_zero() {
+                break;
+            }
+            r = scattered;
+            pdf_dir = pdf;
+        }
+        None
+    }
+
+    // the light whose surface a camera ray r hit at p, None for emitters that aren't lights
+    fn find_light(&self, r: &Ray, p: &Vec3) -> Option<usize> {
+        (0..self.lights.len()).find(|&i| {
+            let l = &self.lights[i];
+            l.pdf(&r.origin(), &r.direction()) > 0.0
+                && l.bounds().map_or(false, |b| contains(&b.bounds, p))
+        })
+    }
+
+    fn unoccluded(
+        &self,
+        world: &Arc<dyn Hittable + Send + Sync>,
+        a: Vec3,
+        b: Vec3,
+        time: f64,
+    ) -> bool {
+        let d = b - a;
+        let distance = d.length();
+        let mut rec = hit_record::new();
+        !world.hit(
+            &Ray::new_with_time(a, d / distance, time),
+            Interval::new(0.001, distance - 0.001),
+            &mut rec,
+        )
+    }
+
+    fn escaped_radiance(&self, cam: &Camera, r: &Ray, bsdf_pdf: f64) -> Vec3 {
+        let mut c = if cam.environment.is_none() {
+            cam.background
+        } else {
+            Vec3::zero()
+        };
+        let pmf = 1.0 / self.infinite.len() as f64;
+        for l in &self.infinite {
+            let radiance = l.background(&r.direction());
+            if radiance.near_zero() {
+                continue;
+            }
+            let weight = if bsdf_pdf > 0.0 {
+                power_heuristic(bsdf_pdf, l.pdf(&r.origin(), &r.direction()) * pmf)
+            } else {
+                1.0
+            };
+            c += radiance * weight;
+        }
+        c
+    }
+
+    // next event estimation toward a light at infinity, against escaped rays
+    fn sample_infinite(&self, world: &Arc<dyn Hittable + Send + Sync>, v: &vertex) -> Vec3 {
+        let Some(rec) = &v.rec else {
+            return Vec3::zero();
+        };
+        if !v.connectible() || self.infinite.is_empty() {
+            return Vec3::zero();
+        }
+        let pmf = 1.0 / self.infinite.len() as f64;
+        let Some(s) = self.infinite[pick(self.infinite.len())].sample(&v.p) else {
+            return Vec3::zero();
+        };
+        let shadow = Ray::new_with_time(v.p, s.direction, v.r_in.time());
+        let bsdf_pdf = rec.mat.scattering_pdf(&v.r_in, rec, &shadow);
+        if bsdf_pdf <= 0.0 {
+            return Vec3::zero();
+        }
+        let mut blocker = hit_record::new();
+        if world.hit(&shadow, Interval::new(0.001, f64::INFINITY), &mut blocker) {
+            return Vec3::zero();
+        }
+        let light_pdf = s.pdf * pmf;
+        let weight = if s.delta {
+            1.0
+        } else {
+            power_heuristic(light_pdf, bsdf_pdf)
+        };
+        v.beta * rec.mat.bsdf_cos(&v.r_in, rec, &shadow) * s.radiance * (weight / light_pdf)
+    }
+
+    // the strategy with s light and t camera vertices, already weighted
+    fn connect(
+        &self,
+        cam: &Camera,
+        world: &Arc<dyn Hittable + Send + Sync>,
+        light_path: &[vertex],
+        camera_path: &[vertex],
+        s: usize,
+        t: usize,
+        splats: &mut Vec<splat>,
+    ) -> Vec3 {
+        let time = camera_path[0].r_in.time();
+        let mut sampled = None;
+        let mut raster = None;
+        let l;
+        if s == 0 {
+            // the camera path hit an emitter
+            let pt = &camera_path[t - 1];
+            let Some(rec) = &pt.rec else {
+                return Vec3::zero();
+            };
+            l = pt.beta * rec.mat.emitted_at(&pt.r_in, rec);
+        } else if t == 1 {
+            // the light path seen by the camera
+            let qs = &light_path[s - 1];
+            if !qs.connectible() {
+                return Vec3::zero();
+            }
+            let lens = cam.sample_lens();
+            let Some(position) = cam.raster(lens, qs.p) else {
+                return Vec3::zero();
+            };
+            let d2 = (qs.p - lens).squared_length();
+            let cosine = (qs.p - lens).normalize().dot(cam.w * -1.0);
+            let mut v = vertex::new(vertex_kind::Camera, lens, Vec3::zero(), Vec3::ones());
+            v.r_in = Ray::new_with_time(lens, Vec3::zero(), time);
+            // importance over the density of picking lens as seen from qs
+            v.beta = Vec3::ones() * (cam.importance(qs.p - lens) * cosine * cam.lens_area() / d2);
+            l = qs.beta * qs.f(&v) * v.beta;
+            if l.near_zero() || !self.unoccluded(world, qs.p, lens, time) {
+                return Vec3::zero();
+            }
+            raster = Some(position);
+            sampled = Some(v);
+        } else if s == 1 {
+            // next event estimation
+            let pt = &camera_path[t - 1];
+            if !pt.connectible() || self.lights.is_empty() {
+                return Vec3::zero();
+            }
+            let i = pick(self.lights.len());
+            let pmf = 1.0 / self.lights.len() as f64;
+            let light = &self.lights[i];
+            let Some(ls) = light.sample(&pt.p) else {
+                return Vec3::zero();
+            };
+            if ls.pdf <= 0.0 {
+                return Vec3::zero();
+            }
+            let p = pt.p + ls.direction.normalize() * ls.distance;
+            let mut v = vertex::new(
+                vertex_kind::Light,
+                p,
+                light.normal(&p),
+                ls.radiance / (ls.pdf * pmf),
+            );
+            v.light = Some(i);
+            v.delta_light = ls.delta;
+            v.pdf_fwd = pmf * light.emission_pdf(&p, &(pt.p - p)).0;
+            l = pt.beta * pt.f(&v) * v.beta;
+            if l.near_zero() || !self.unoccluded(world, pt.p, p, time) {
+                return Vec3::zero();
+            }
+            sampled = Some(v);
+        } else {
+            let (qs, pt) = (&light_path[s - 1], &camera_path[t - 1]);
+            if !qs.connectible() || !pt.connectible() {
+                return Vec3::zero();
+            }
+            l = qs.beta * qs.f(pt) * pt.f(qs) * pt.beta / (qs.p - pt.p).squared_length();
+            if l.near_zero() || !self.unoccluded(world, pt.p, qs.p, time) {
+                return Vec3::zero();
+            }
+        }
+        if l.near_zero() {
+            return Vec3::zero();
+        }
+        let weighted = l * self.mis_weight(cam, light_path, camera_path, sampled, s, t);
+        if let Some((x, y)) = raster {
+            splats.push((x as usize, y as usize, weighted));
+            return Vec3::zero();
+        }
+        weighted
+    }
+
+    // density of v sampling next, having been reached from prev, per unit area at next
+    fn pdf(&self, cam: &Camera, v: &vertex, prev: Option<&vertex>, next: &vertex) -> f64 {
+        match v.kind {
+            vertex_kind::Light => self.pdf_light(v, next),
+            vertex_kind::Camera => v.convert_density(cam.direction_pdf(next.p - v.p), next),
+            _ => {
+                let (Some(rec), Some(prev)) = (&v.rec, prev) else {
+                    return 0.0;
+                };
+                let r_in = Ray::new_with_time(prev.p, v.p - prev.p, v.r_in.time());
+                let out = Ray::new_with_time(v.p, next.p - v.p, v.r_in.time());
+                v.convert_density(rec.mat.scattering_pdf(&r_in, rec, &out), next)
+            }
+        }
+    }
+
+    // density of a light path leaving v, a point on a light, toward next
+    fn pdf_light(&self, v: &vertex, next: &vertex) -> f64 {
+        let Some(i) = v.light else {
+            return 0.0;
+        };
+        let (_, pdf_dir) = self.lights[i].emission_pdf(&v.p, &(next.p - v.p));
+        v.convert_density(pdf_dir, next)
+    }
+
+    // density of a light path starting at v, emitting toward next
+    fn pdf_light_origin(&self, v: &vertex, next: &vertex) -> f64 {
+        let Some(i) = v.light else {
+            return 0.0;
+        };
+        let (pdf_pos, _) = self.lights[i].emission_pdf(&v.p, &(next.p - v.p));
+        pdf_pos / self.lights.len() as f64
+    }
+
+    // balance heuristic over every strategy that could have made the same path
+    fn mis_weight(
+        &self,
+        cam: &Camera,
+        light_path: &[vertex],
+        camera_path: &[vertex],
+        sampled: Option<vertex>,
+        s: usize,
+        t: usize,
+    ) -> f64 {
+        if s + t == 2 {
+            return 1.0;
+        }
+        // an emitter that isn't a light, no light path could have reached it
+        if s == 0 && camera_path[t - 1].light.is_none() {
+            return 1.0;
+        }
+        let mut lv = light_path[..s].to_vec();
+        let mut cv = camera_path[..t].to_vec();
+        if let Some(v) = sampled {
+            if s == 1 {
+                lv[0] = v
+            } else {
+                cv[0] = v
+            }
+        }
+
+        // the densities at and next to the join, which the walks didn't know
+        let pt_rev = if s > 0 {
+            self.pdf(
+                cam,
+                &lv[s - 1],
+                if s > 1 { Some(&lv[s - 2]) } else { None },
+                &cv[t - 1],
+            )
+        } else {
+            self.pdf_light_origin(&cv[t - 1], &cv[t - 2])
+        };
+        let pt_minus_rev = if t > 1 {
+            if s > 0 {
+                self.pdf(cam, &cv[t - 1], Some(&lv[s - 1]), &cv[t - 2])
+            } else {
+                self.pdf_light(&cv[t - 1], &cv[t - 2])
+            }
+        } else {
+            0.0
+        };
+        let qs_rev = if s > 0 {
+            self.pdf(
+                cam,
+                &cv[t - 1],
+                if t > 1 { Some(&cv[t - 2]) } else { None },
+                &lv[s - 1],
+            )
+        } else {
+            0.0
+        };
+        let qs_minus_rev = if s > 1 {
+            self.pdf(cam, &lv[s - 1], Some(&cv[t - 1]), &lv[s - 2])
+        } else {
+            0.0
+        };
+        cv[t - 1].pdf_rev = pt_rev;
+        cv[t - 1].delta = false;
+        if t > 1 {
+            cv[t - 2].pdf_rev = pt_minus_rev;
+        }
+        if s > 0 {
+            lv[s - 1].pdf_rev = qs_rev;
+            lv[s - 1].delta = false;
+        }
+        if s > 1 {
+            lv[s - 2].pdf_rev = qs_minus_rev;
+        }
+
+        // each step moves the join one vertex along the path; a density of 0 comes
+        // from a delta lobe, which the delta flags already rule out
+        let remap = |f: f64| if f != 0.0 { f } else { 1.0 };
+        let mut sum = 0.0;
+        let mut ri = 1.0;
+        for i in (1..t).rev() {
+            ri *= remap(cv[i].pdf_rev) / remap(cv[i].pdf_fwd);
+            if !cv[i].delta && !cv[i - 1].delta {
+                sum += ri;
+            }
+        }
+        ri = 1.0;
+        for i in (0..s).rev() {
+            ri *= remap(lv[i].pdf_rev) / remap(lv[i].pdf_fwd);
+            let delta_before = if i > 0 {
+                lv[i - 1].delta
+            } else {
+                lv[0].delta_light
+            };
+            if !lv[i].delta && !delta_before {
+                sum += ri;
+            }
+        }
+        1.0 / (1.0 + sum)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hittable_list::HittableList;
+    use crate::integrator::path_tracer;
+    use crate::light::quad_light;
+    use crate::material::{
+        coated, diffuse_light, emission_side, lambertian, metal, mix_material, Material,
+    };
+    use crate::quad::Quad;
+    use crate::texture::solid_color;
+
+    // a closed grey box lit by a panel under its ceiling, seen from inside
+    fn closed_box() -> (Camera, Arc<dyn Hittable + Send + Sync>) {
+        closed_box_of(Arc::new(lambertian::new(Vec3::new(0.5, 0.5, 0.5))))
+    }
+
+    fn closed_box_of(
+        walls: Arc<dyn Material + Send + Sync>,
+    ) -> (Camera, Arc<dyn Hittable + Send + Sync>) {
+        let mut world = HittableList::new();
+        world.add(Quad::boxx(
+            Vec3::new(-1.0, -1.0, -1.0),
+            Vec3::new(1.0, 1.0, 1.0),
+            walls,
+        ));
+        let panel = Arc::new(diffuse_light::new_with_strength(
+            Arc::new(solid_color::new(Vec3::ones())),
+            2.0,
+            emission_side::Front,
+        ));
+        let (lamp, lamp_light) = quad_light::new_with_quad(
+            Vec3::new(-0.5, 0.99, -0.5),
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 1.0),
+            panel,
+        );
+        world.add(lamp);
+        let mut cam = Camera::new();
+        cam.width = 8;
+        cam.height = 8;
+        cam.samples_per_pixel = 1;
+        cam.max_depth = 8;
+        cam.vfov = 90.0;
+        cam.lookfrom = Vec3::new(0.0, 0.0, 0.9);
+        cam.lookat = Vec3::new(0.0, 0.0, -1.0);
+        cam.vup = Vec3::new(0.0, 1.0, 0.0);
+        cam.lights.push(Arc::new(lamp_light));
+        cam.initialize();
+        (cam, Arc::new(world))
+    }
+
+    fn image_means(cam: &Camera, world: &Arc<dyn Hittable + Send + Sync>, n: usize) -> (f64, f64) {
+        // the lights as render() would hand them over
+        let bidirectional = bdpt::new().prepare(cam, world).unwrap();
+        let unidirectional = path_tracer::new();
+        let mut splats = Vec::new();
+        let (mut b, mut u) = (0.0, 0.0);
+        for k in 0..n {
+            let (i, j) = (k % cam.width, (k / cam.width) % cam.height);
+            b += bidirectional
+                .li(cam, &cam.get_ray(i, j), world, &mut splats)
+                .y;
+            u += unidirectional
+                .li(cam, &cam.get_ray(i, j), world, &mut splats)
+                .y;
+        }
+        // the image mean, with the light paths that reached the camera
+        b += splats.iter().map(|s| s.2.y).sum::<f64>();
+        (b / n as f64, u / n as f64)
+    }
+
+    #[test]
+    fn test_agrees_with_path_tracer() {
+        let (cam, world) = closed_box();
+        let (b, u) = image_means(&cam, &world, 20000);
+        assert!((b - u).abs() < 0.05 * u, "bdpt {} path {}", b, u);
+    }
+
+    #[test]
+    fn test_agrees_on_mixed_lobes() {
+        // half mirror, half diffuse walls under a clear coat: joins through the
+        // smooth lobes of vertices that left by a delta one
+        let diffuse = Arc::new(lambertian::new(Vec3::new(0.6, 0.6, 0.6)));
+        let mix = Arc::new(mix_material::new(
+            diffuse,
+            Arc::new(metal::new(Vec3::new(0.6, 0.6, 0.6), 0.0)),
+            0.5,
+        ));
+        let (cam, world) = closed_box_of(Arc::new(coated::new(mix, 1.5)));
+        let (b, u) = image_means(&cam, &world, 40000);
+        assert!((b - u).abs() < 0.05 * u, "bdpt {} path {}", b, u);
+    }
+
+    #[test]
+    fn test_mis_weights_sum_to_one() {
+        let (cam, world) = closed_box();
+        let bdpt = bdpt::new_with_lights(&cam.lights);
+        let surface = |from: Vec3, toward: Vec3| {
+            let r = Ray::new(from, toward - from);
+            let mut rec = hit_record::new();
+            assert!(world.hit(&r, Interval::new(0.001, f64::INFINITY), &mut rec));
+            let mut v = vertex::new(vertex_kind::Surface, rec.p, rec.normal, Vec3::ones());
+            v.r_in = r;
+            v.rec = Some(rec);
+            v
+        };
+        // camera, back wall, right wall, floor, panel
+        let eye = vertex::new(
+            vertex_kind::Camera,
+            cam.lookfrom,
+            Vec3::zero(),
+            Vec3::ones(),
+        );
+        let y1 = surface(eye.p, Vec3::new(-0.3, -0.2, -1.0));
+        let y2 = surface(y1.p, Vec3::new(1.0, 0.3, 0.2));
+        let y3 = surface(y2.p, Vec3::new(-0.2, -1.0, 0.4));
+        let p = Vec3::new(0.1, 0.99, 0.05);
+        let mut lamp = vertex::new(
+            vertex_kind::Light,
+            p,
+            bdpt.lights[0].normal(&p),
+            Vec3::ones(),
+        );
+        lamp.light = Some(0);
+        let full = [eye, y1, y2, y3, lamp];
+        let n = full.len();
+        let mut total = 0.0;
+        for s in 0..n {
+            let t = n - s;
+            // densities as the two walks would have recorded them
+            let mut cv = full[..t].to_vec();
+            for i in 1..t {
+                cv[i].pdf_fwd = bdpt.pdf(
+                    &cam,
+                    &full[i - 1],
+                    if i > 1 { Some(&full[i - 2]) } else { None },
+                    &full[i],
+                );
+            }
+            for i in 0..t.saturating_sub(2) {
+                cv[i].pdf_rev = bdpt.pdf(&cam, &full[i + 1], Some(&full[i + 2]), &full[i]);
+            }
+            let reversed: Vec<vertex> = full.iter().rev().cloned().collect();
+            let mut lv = reversed[..s].to_vec();
+            for j in 0..s {
+                lv[j].pdf_fwd = if j == 0 {
+                    bdpt.pdf_light_origin(&reversed[0], &reversed[1])
+                } else {
+                    bdpt.pdf(
+                        &cam,
+                        &reversed[j - 1],
+                        if j > 1 { Some(&reversed[j - 2]) } else { None },
+                        &reversed[j],
+                    )
+                };
+            }
+            for j in 0..s.saturating_sub(2) {
+                lv[j].pdf_rev =
+                    bdpt.pdf(&cam, &reversed[j + 1], Some(&reversed[j + 2]), &reversed[j]);
+            }
+            let sampled = if s == 1 {
+                Some(lv[0].clone())
+            } else if t == 1 {
+                Some(cv[0].clone())
+            } else {
+                None
+            };
+            total += bdpt.mis_weight(&cam, &lv, &cv, sampled, s, t);
+        }
+        assert!((total - 1.0).abs() < 1e-9, "{}", total);
+    }
+}
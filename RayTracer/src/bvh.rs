@@ -5,6 +5,7 @@ use crate::ray::Ray;
 use crate::aabb::aabb;
 use crate::interval::*;
 use crate::vec3::Vec3;
+use crate::light::light;
 // use crate::raytracer:
 use crate::raytracer::random_int_range;
 use std::rc::Rc;
@@ -112,4 +113,17 @@ impl Hittable for bvh_node {
     fn bounding_box(&self) -> aabb {
         return self.bbox;
     }
+    fn hit_counted(&self, r: &Ray, ray_t: Interval, rec: &mut hit_record, nodes: &mut usize) -> bool {
+        *nodes += 1;
+        if !self.bbox.hit(r, ray_t) {
+            return false;
+        }
+        let hit_left = self.left.hit_counted(r, ray_t, rec, nodes);
+        let hit_right = self.right.hit_counted(r, Interval::new(ray_t.min, if hit_left {rec.t} else {ray_t.max}), rec, nodes);
+        hit_left || hit_right
+    }
+    fn emitters(&self, lights: &mut Vec<Arc<dyn light + Send + Sync>>) {
+        self.left.emitters(lights);
+        self.right.emitters(lights);
+    }
 }
\ No newline at end of file
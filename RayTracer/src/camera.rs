@@ -7,6 +7,13 @@ use crate::interval::Interval;
 use crate::hittable::{Hittable, hit_record};
 use crate::material::Material;
 use crate::raytracer::random_double;
+use crate::fog::fog;
+use crate::con_medium::medium_stack;
+use crate::environment::environment_map;
+use crate::light::light;
+use crate::light_sampler::{light_sampler, light_sampling};
+use crate::integrator::{by_name, integrator, path_tracer};
+use crate::raytracer::pi;
 use std::fs::File;
 use std::rc::Rc;
 use std::sync::Arc;
@@ -19,7 +26,6 @@ const HEIGHT_PARTITION: usize = 20;
 const WIDTH_PARTITION: usize = 20;
 const THREAD_LIMIT: usize = 16;
 
-
 pub struct Camera {
     pub aspect_ratio: f64,
     pub width: usize,
@@ -43,6 +49,19 @@ pub struct Camera {
     pub defocus_disk_u: Vec3,
     pub defocus_disk_v: Vec3,
     pub background: Vec3,
+    pub fog: Option<Arc<fog>>,
+    // media the camera starts in, outermost first, e.g. medium_volume::entry() of the
+    // water for an underwater shot
+    pub camera_media: medium_stack,
+    // replaces background when set, and is sampled as a light
+    pub environment: Option<Arc<environment_map>>,
+    pub lights: Vec<Arc<dyn light + Send + Sync>>,
+    pub light_sampling: light_sampling,
+    // what the camera's rays see, see integrator.rs; INTEGRATOR in the environment
+    // overrides it by name
+    pub integrator: Arc<dyn integrator + Send + Sync>,
+    // lights plus the environment, rebuilt by initialize()
+    light_sampler: light_sampler,
 }
 
 impl Camera {
@@ -70,6 +89,13 @@ impl Camera {
             defocus_disk_u: Vec3::zero(),
             defocus_disk_v: Vec3::zero(),
             background: Vec3::zero(),
+            fog: None,
+            camera_media: medium_stack::new(),
+            environment: None,
+            lights: Vec::new(),
+            light_sampling: light_sampling::Tree,
+            integrator: Arc::new(path_tracer::new()),
+            light_sampler: light_sampler::new(Vec::new(), light_sampling::Tree),
         }
     }
     pub fn defocus_disk_sample(&self) -> Vec3 {
@@ -77,7 +103,7 @@ impl Camera {
         return self.defocus_disk_u * p.x + self.defocus_disk_v * p.y + self.camera_center;
     }
     pub fn sample_square(&self) -> Vec3 {
-        return Vec3::new(rand::random::<f64>()-0.5, rand::random::<f64>()-0.5, 0.0);
+        return Vec3::new(random_double()-0.5, random_double()-0.5, 0.0);
     }
     pub fn get_ray(&self, i: usize, j: usize) -> Ray {
         let mut offset = self.sample_square();
@@ -85,36 +111,69 @@ impl Camera {
         let ray_origin = if self.defocus_angle > 0.0 {self.defocus_disk_sample()} else {self.camera_center};
         let ray_direction = pixel_sample - ray_origin;
         let ray_time = random_double();
-        return Ray::new_with_time(ray_origin, ray_direction, ray_time);
+        let mut r = Ray::new_with_time(ray_origin, ray_direction, ray_time);
+        // one pixel is |pixel_delta_u| wide where ray_direction ends
+        r.spread = self.pixel_delta_u.length() / ray_direction.length();
+        return r;
+    }
+    // a point on the lens, where get_ray() starts its rays
+    pub fn sample_lens(&self) -> Vec3 {
+        if self.defocus_angle > 0.0 { self.defocus_disk_sample() } else { self.camera_center }
+    }
+    // The camera as seen from the scene, for paths traced from the lights. The image
+    // spans film_area() on a plane at unit distance, and rays leave the lens with
+    // importance 1 / (film_area * lens_area * cos^4) toward it.
+    pub fn film_area(&self) -> f64 {
+        let width = self.pixel_delta_u.length() * self.width as f64;
+        let height = self.pixel_delta_v.length() * self.height as f64;
+        width * height / (self.focus_dist * self.focus_dist)
+    }
+    pub fn lens_area(&self) -> f64 {
+        if self.defocus_angle > 0.0 { pi * self.defocus_disk_u.squared_length() } else { 1.0 }
     }
-    pub fn ray_color(&self, r: &Ray, depth: usize, world: Arc<dyn Hittable + Send + Sync>) -> [f64; 3] {
-        if depth <= 0 {
-            return [0.0, 0.0, 0.0];
+    // where the ray from lens through p crosses the image, in pixels, None off the image
+    pub fn raster(&self, lens: Vec3, p: Vec3) -> Option<(f64, f64)> {
+        let d = p - lens;
+        let depth = d.dot(self.w * -1.0);
+        if depth <= 0.0 {
+            return None;
         }
-        let mut rec = hit_record {
-            p: Vec3::zero(),
-            normal: Vec3::zero(),
-            t: 0.0,
-            front_face: false,
-            mat: Arc::new(crate::material::lambertian::new(Vec3::zero())),
-            u: 0.0,
-            v: 0.0,
-        };
-        if !world.hit(r, Interval::new(0.001, f64::INFINITY), &mut rec) {
-            return [self.background.x, self.background.y, self.background.z];
+        let on_focus_plane = lens + d * (self.focus_dist / depth);
+        let offset = on_focus_plane - (self.pixel00_loc - (self.pixel_delta_u + self.pixel_delta_v) / 2.0);
+        let x = offset.dot(self.pixel_delta_u) / self.pixel_delta_u.squared_length();
+        let y = offset.dot(self.pixel_delta_v) / self.pixel_delta_v.squared_length();
+        if x < 0.0 || y < 0.0 || x >= self.width as f64 || y >= self.height as f64 {
+            return None;
         }
-        let mut scattered = Ray::new(Vec3::zero(), Vec3::zero());
-        let mut attenuation = Vec3::zero(); 
-        let mut color_from_emission = rec.mat.emitted(rec.u, rec.v, &rec.p);
-        if !rec.mat.scatter(r, &rec, &mut attenuation, &mut scattered) {
-            return [color_from_emission.x, color_from_emission.y, color_from_emission.z];
+        Some((x, y))
+    }
+    pub fn importance(&self, direction: Vec3) -> f64 {
+        let cosine = direction.normalize().dot(self.w * -1.0);
+        if cosine <= 0.0 {
+            return 0.0;
+        }
+        1.0 / (self.film_area() * self.lens_area() * cosine.powi(4))
+    }
+    // solid angle density of get_ray() leaving the lens along direction
+    pub fn direction_pdf(&self, direction: Vec3) -> f64 {
+        let cosine = direction.normalize().dot(self.w * -1.0);
+        if cosine <= 0.0 {
+            return 0.0;
         }
-        let color_from_scatter = Vec3::from(self.ray_color(&scattered, depth-1, world)) * attenuation;
-        return [color_from_emission.x + color_from_scatter.x, color_from_emission.y + color_from_scatter.y, color_from_emission.z + color_from_scatter.z];
+        1.0 / (self.film_area() * cosine.powi(3))
+    }
+    // lights plus the environment, for integrators to sample
+    pub fn light_sampler(&self) -> &light_sampler {
+        &self.light_sampler
     }
 
     pub fn initialize(&mut self) -> RgbImage {
         self.pixel_samples_scale = 1.0 / self.samples_per_pixel as f64;
+        let mut lights = self.lights.clone();
+        if let Some(env) = &self.environment {
+            lights.push(env.clone());
+        }
+        self.light_sampler = light_sampler::new(lights, self.light_sampling);
         let mut img: RgbImage = ImageBuffer::new(self.width as u32, self.height as u32);
 
         self.aspect_ratio = self.width as f64 / self.height as f64;
@@ -196,14 +255,28 @@ impl Camera {
         // } else {
         //     ProgressBar::new((self.height * self.width) as u64)
         // };
-        let bar = Self::get_ProgressBar(self.height, self.width);
-
-
-        let path = "output2/test.jpg";
-        let AUTHOR = "name";
-
         let mut img = self.initialize(); 
-        let img_mtx = Arc::new(Mutex::new(&mut img));
+        if let Ok(name) = std::env::var("INTEGRATOR") {
+            match by_name(&name) {
+                Some(chosen) => self.integrator = chosen,
+                None => eprintln!("Unknown integrator \"{}\", keeping the scene's", name),
+            }
+        }
+        if let Some(prepared) = self.integrator.prepare(self, &world) {
+            self.integrator = prepared;
+        }
+        if let Some(film) = self.integrator.render(self, &world) {
+            for (k, color) in film.iter().enumerate() {
+                write_color(*color, &mut img, k % self.width, k / self.width);
+            }
+            Self::save(img);
+            return;
+        }
+        let bar = Self::get_ProgressBar(self.height, self.width);
+        // linear radiance, written to img once every thread is done
+        let film = Arc::new(Mutex::new(vec![Vec3::zero(); self.width * self.height]));
+        let film_wrapper = Arc::clone(&film);
+        let width = self.width;
         let camera_wrapper = Arc::new(self);
         let bar = Arc::new(bar);
         let bar_wrapper = Arc::clone(&bar);
@@ -229,14 +302,14 @@ impl Camera {
                 let camera = Arc::clone(&camera_wrapper);
                 let world = Arc::clone(&world);
                 let bar = Arc::clone(&bar_wrapper);
-                let img_mtx = Arc::clone(&img_mtx);
+                let film = Arc::clone(&film_wrapper);
                 
                 // move "thread_count++" out of child thread, so that it's sequential with thread number control code
                 thread_count.fetch_add(1, Ordering::SeqCst);
                 bar.set_message(format!("|{} threads outstanding|", thread_count.load(Ordering::SeqCst))); // set "thread_count" information to progress bar
       
                 let _ = thd_spawner.spawn(move |_| {
-                  camera.render_sub(&world, &img_mtx, &bar, 
+                  camera.render_sub(&world, &film, &bar, 
                     i * chunk_width, (i + 1) * chunk_width, 
                     j * chunk_height, (j + 1) * chunk_height);
       
@@ -255,7 +328,14 @@ impl Camera {
         // let bar = Arc::clone(&bar);
         // bar_wrapper.finish();
         bar.finish();
-
+        for (k, color) in film.lock().unwrap().iter().enumerate() {
+            write_color(*color, &mut img, k % width, k / width);
+        }
+        Self::save(img);
+    }
+    fn save(img: RgbImage) {
+        let path = "output2/test.jpg";
+        let AUTHOR = "name";
         let quality = 60;
         println!("Ouput image as \"{}\"\n Author: {}", path, AUTHOR);
         let output_image: image::DynamicImage = image::DynamicImage::ImageRgb8(img);
@@ -265,29 +345,34 @@ impl Camera {
             Err(_) => println!("Outputting image fails."),
         }
     }
-    pub fn render_sub(&self, world: &Arc<dyn Hittable + Send + Sync>, img_mtx: &Arc<Mutex<&mut RgbImage>>, bar: &Arc<ProgressBar>, x_min: usize, x_max: usize, y_min: usize, y_max: usize) {
+    pub fn render_sub(&self, world: &Arc<dyn Hittable + Send + Sync>, film: &Arc<Mutex<Vec<Vec3>>>, bar: &Arc<ProgressBar>, x_min: usize, x_max: usize, y_min: usize, y_max: usize) {
         let x_max = x_max.min(self.width);
         let y_max = y_max.min(self.height);
 
         let mut buff = Vec::new();
+        let mut splats = Vec::new();
 
         for y in y_min..y_max {
             for x in x_min..x_max {
                 let mut pixel_color = Vec3::zero();
                 for sample in 0..self.samples_per_pixel {
                     let r = self.get_ray(x, y);
-                    pixel_color += Vec3::from(self.ray_color(&r, self.max_depth, world.clone()));
+                    pixel_color += self.integrator.li(self, &r, world, &mut splats);
                 } 
                 // write_color(pixel_color * self.pixel_samples_scale, &mut buff, x as usize, y as usize);
                 buff.push((x, y, pixel_color * self.pixel_samples_scale));
             }
             bar.inc((x_max - x_min) as u64);
         }
-        let mut img = img_mtx.lock().unwrap();
+        let mut film = film.lock().unwrap();
         for (x, y, color) in buff {
-            write_color(color, &mut img, x, y);
+            film[y * self.width + x] += color;
             // bar.inc(1);
         }
+        // every pixel traced samples_per_pixel light paths, which land all over the image
+        for (x, y, color) in splats {
+            film[y * self.width + x] += color * self.pixel_samples_scale;
+        }
 
 
     }
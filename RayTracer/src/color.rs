@@ -10,6 +10,60 @@ pub fn linear_to_gamma_corrected(linear_component: f64) -> f64 {
     }
 }
 
+pub fn srgb_to_linear(srgb_component: f64) -> f64 {
+    if srgb_component <= 0.04045 {
+        srgb_component / 12.92
+    } else {
+        ((srgb_component + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+pub fn luminance(c: Vec3) -> f64 {
+    0.2126 * c.x + 0.7152 * c.y + 0.0722 * c.z
+}
+
+// piecewise gaussian fit of the CIE 1931 matching functions (Wyman et al. 2013)
+fn cie_xyz(lambda: f64) -> Vec3 {
+    let g = |mu: f64, s1: f64, s2: f64| {
+        let t = (lambda - mu) / if lambda < mu { s1 } else { s2 };
+        (-0.5 * t * t).exp()
+    };
+    Vec3::new(
+        1.056 * g(599.8, 37.9, 31.0) + 0.362 * g(442.0, 16.0, 26.7) - 0.065 * g(501.1, 20.4, 26.2),
+        0.821 * g(568.8, 46.9, 40.5) + 0.286 * g(530.9, 16.3, 31.1),
+        1.217 * g(437.0, 11.8, 36.0) + 0.681 * g(459.0, 26.0, 13.8),
+    )
+}
+
+/// Linear rgb color of a blackbody at `kelvin`, scaled to unit luminance.
+pub fn blackbody(kelvin: f64) -> Vec3 {
+    if kelvin <= 0.0 {
+        return Vec3::zero();
+    }
+    // h c / k in nm * K; the constant in front of planck's law cancels in the normalization
+    let c2 = 1.4387769e7;
+    let mut xyz = Vec3::zero();
+    let mut lambda: f64 = 380.0;
+    while lambda <= 780.0 {
+        let planck = 1.0 / (lambda.powi(5) * ((c2 / (lambda * kelvin)).exp() - 1.0));
+        xyz += cie_xyz(lambda) * planck;
+        lambda += 5.0;
+    }
+    if xyz.y <= 0.0 {
+        return Vec3::zero();
+    }
+    xyz_to_linear_rgb(xyz / xyz.y)
+}
+
+/// CIE XYZ to linear srgb, clamping out of gamut colors at zero.
+pub fn xyz_to_linear_rgb(xyz: Vec3) -> Vec3 {
+    Vec3::new(
+        (3.2406 * xyz.x - 1.5372 * xyz.y - 0.4986 * xyz.z).max(0.0),
+        (-0.9689 * xyz.x + 1.8758 * xyz.y + 0.0415 * xyz.z).max(0.0),
+        (0.0557 * xyz.x - 0.2040 * xyz.y + 1.0570 * xyz.z).max(0.0),
+    )
+}
+
 /// the multi-sample write_color() function
 pub fn write_color(pixel_color: Vec3, img: &mut RgbImage, i: usize, j: usize) {
     // Write the translated [0,255] value of each color component.
@@ -35,3 +89,18 @@ pub fn write_color(pixel_color: Vec3, img: &mut RgbImage, i: usize, j: usize) {
     let pixel = img.get_pixel_mut(i.try_into().unwrap(), j.try_into().unwrap());
     *pixel = image::Rgb([r, g, b]);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_blackbody_colors() {
+        let warm = blackbody(1500.0);
+        assert!(warm.x > warm.y && warm.y > warm.z);
+        let daylight = blackbody(6500.0);
+        assert!((daylight.x - daylight.z).abs() < 0.15 && (daylight.y - 1.0).abs() < 0.1);
+        let hot = blackbody(15000.0);
+        assert!(hot.z > hot.x);
+    }
+}
@@ -8,7 +8,8 @@ use crate::vec3::Vec3;
 use crate::aabb::aabb;
 use crate::ray::Ray;
 use crate::interval::Interval;
-use crate::material::Isotropic;
+use crate::material::{Isotropic, Anisotropic};
+use crate::phase::phase;
 use crate::raytracer;
 
 pub struct constant_medium {
@@ -25,6 +26,14 @@ impl constant_medium {
             phase_function: Arc::new(Isotropic::new(a)),
         }
     }
+    // phase e.g. henyey_greenstein::new(0.7) for haze that glows around lights
+    pub fn new_with_phase(b: Arc<dyn Hittable + Send + Sync>, d: f64, a: Arc<dyn texture + Send + Sync>, phase: Arc<dyn phase + Send + Sync>) -> Self {
+        constant_medium {
+            boundary: b,
+            neg_inv_density: -1.0 / d,
+            phase_function: Arc::new(Anisotropic::new(a, phase)),
+        }
+    }
     pub fn new_from_color(b: Arc<dyn Hittable + Send + Sync>, d: f64, c: Vec3) -> Self {
         // println!("constant_medium::new_from_color");
         constant_medium {
@@ -35,71 +44,344 @@ impl constant_medium {
         
     }
 }
-impl Hittable for constant_medium {
-    fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut hit_record) -> bool {
-        // println!("constant_medium::hit");
-
-        // let rec1 = hit_record::new();
-        let mut rec1 = hit_record {
-            p: Vec3::zero(),
-            normal: Vec3::zero(),
-            t: 0.0,
-            front_face: false,
-            mat: Arc::new(crate::material::lambertian::new(Vec3::zero())),
-            u: 0.0,
-            v: 0.0,
-        };
-        // let rec2 = hit_record::new();
-        let mut rec2 = hit_record {
-            p: Vec3::zero(),
-            normal: Vec3::zero(),
-            t: 0.0,
-            front_face: false,
-            mat: Arc::new(crate::material::lambertian::new(Vec3::zero())),
-            u: 0.0,
-            v: 0.0,
+// Spans of r within ray_t that are inside the boundary, in order. Inside is tracked
+// by walking every crossing and counting front faces as entries and back faces as
+// exits, so the boundary may be non-convex or made of several pieces, and a ray that
+// starts inside (first crossing is an exit) is handled. Boundaries need consistently
+// outward facing normals; Sphere and Quad::boxx have them. Each step moves at least
+// 0.0001 along the ray, so the walk ends on any bounded boundary.
+pub fn inside_segments(boundary: &dyn Hittable, r: &Ray, ray_t: Interval) -> Vec<(f64, f64)> {
+    let mut segments = Vec::new();
+    let mut start = ray_t.min.max(0.0);
+    let mut t = start;
+    let mut depth: Option<i32> = None;
+    let step = 0.0001 / r.direction().length();
+    loop {
+        let mut crossing = hit_record::new();
+        let found = boundary.hit(r, Interval::new(t, f64::INFINITY), &mut crossing);
+        let end = if found { crossing.t.min(ray_t.max) } else { ray_t.max };
+        let inside = match depth {
+            Some(d) => d > 0,
+            // nothing behind us to count, so judge by the first crossing
+            None => found && !crossing.front_face,
         };
+        if inside && end > start {
+            segments.push((start, end));
+        }
+        if !found || crossing.t >= ray_t.max {
+            break;
+        }
+        let d = depth.unwrap_or(if inside { 1 } else { 0 });
+        depth = Some(if crossing.front_face { d + 1 } else { (d - 1).max(0) });
+        start = crossing.t;
+        t = crossing.t + step;
+    }
+    segments
+}
 
-        if !self.boundary.hit(r, Interval::universe, &mut rec1) {
-            return false;
+impl constant_medium {
+    fn collide(&self, r: &Ray, t: f64, rec: &mut hit_record) {
+        rec.t = t;
+        rec.p = r.at(t);
+        rec.normal = Vec3::new(1.0, 0.0, 0.0);  // arbitrary
+        rec.front_face = true;     // also arbitrary
+        rec.mat = self.phase_function.clone();
+    }
+}
+
+impl Hittable for constant_medium {
+    fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut hit_record) -> bool {
+        let ray_length = r.direction().length();
+        for (t0, t1) in inside_segments(self.boundary.as_ref(), r, ray_t) {
+            // distances are memoryless, so each span can draw its own
+            let distance_inside_boundary = (t1 - t0) * ray_length;
+            let hit_distance = self.neg_inv_density * raytracer::random_double().ln();
+            if hit_distance > distance_inside_boundary {
+                continue;
+            }
+            self.collide(r, t0 + hit_distance / ray_length, rec);
+            return true;
         }
-        // println!("rec1.t modified: {}", rec1.t + 0.0001);
-        if !self.boundary.hit(r, Interval::new(rec1.t + 0.0001, f64::INFINITY), &mut rec2) {
+        false
+    }
+    fn bounding_box(&self) -> aabb {
+        self.boundary.bounding_box()
+    }
+}
+impl medium for constant_medium {
+    fn free_flight(&self, r: &Ray, ray_t: Interval, rec: &mut hit_record) -> bool {
+        let t = ray_t.min + self.neg_inv_density * raytracer::random_double().ln() / r.direction().length();
+        if t >= ray_t.max {
             return false;
         }
-        // println!("rec1.t: {}, rec2.t: {}", rec1.t, rec2.t);
-        // println!("test2 rec1.t: {} rec2.t: {}", rec1.t, rec2.t);
-        if rec1.t < ray_t.min {
-            rec1.t = ray_t.min;
+        self.collide(r, t, rec);
+        true
+    }
+    fn transmittance_between(&self, r: &Ray, ray_t: Interval) -> f64 {
+        if ray_t.max <= ray_t.min {
+            return 1.0;
         }
-        if rec2.t > ray_t.max {
-            rec2.t = ray_t.max;
+        ((ray_t.max - ray_t.min) * r.direction().length() / self.neg_inv_density).exp()
+    }
+    fn boundary(&self) -> Arc<dyn Hittable + Send + Sync> {
+        self.boundary.clone()
+    }
+}
+
+// A medium by itself, for integrators that follow which media a path is in rather than
+// finding them by hitting them: it fills the inside of a medium_volume, and the path
+// enters and leaves it by crossing that volume's surface.
+pub trait medium {
+    // where a ray travelling over ray_t collides in the medium, filling rec like a hit
+    // on the phase function; false if it gets through
+    fn free_flight(&self, r: &Ray, ray_t: Interval, rec: &mut hit_record) -> bool;
+    // fraction of light that gets across ray_t, possibly a random unbiased estimate
+    fn transmittance_between(&self, r: &Ray, ray_t: Interval) -> f64;
+    // the surface the medium fills
+    fn boundary(&self) -> Arc<dyn Hittable + Send + Sync>;
+}
+
+// A medium and how it ranks where volumes overlap. A path inside several media is in
+// the highest priority one, and the surfaces of the lower ones aren't there for it,
+// e.g. the water's surface inside a glass with a higher priority than the water.
+#[derive(Clone)]
+pub struct medium_entry {
+    pub medium: Arc<dyn medium + Send + Sync>,
+    pub priority: i32,
+}
+
+// The surface of a medium_volume. Without a surface material rays pass it untouched.
+pub struct medium_interface {
+    pub interior: medium_entry,
+    pub surface: Option<Arc<dyn Material + Send + Sync>>,
+}
+
+impl Material for medium_interface {
+    fn scatter(&self, r_in: &Ray, rec: &hit_record, attenuation: &mut Vec3, scattered: &mut Ray) -> bool {
+        if let Some(surface) = &self.surface {
+            return surface.scatter(r_in, rec, attenuation, scattered);
         }
-        // println!("test2.5 rec1.t: {} rec2.t: {}, ray_t.min: {}, ray_t.max: {}", rec1.t, rec2.t, ray_t.min, ray_t.max);
-        if rec1.t >= rec2.t {
-            return false;
+        *attenuation = Vec3::ones();
+        *scattered = Ray::new_with_time(rec.p, r_in.direction(), r_in.time());
+        true
+    }
+    fn scatter_with_pdf(&self, r_in: &Ray, rec: &hit_record, attenuation: &mut Vec3, scattered: &mut Ray, pdf: &mut f64) -> bool {
+        if let Some(surface) = &self.surface {
+            return surface.scatter_with_pdf(r_in, rec, attenuation, scattered, pdf);
         }
-        // println!("test3 rec1.t: {}, rec2.t: {}", rec1.t, rec2.t);
+        *pdf = 0.0;
+        self.scatter(r_in, rec, attenuation, scattered)
+    }
+    fn emitted(&self, u: f64, v: f64, p: &Vec3) -> Vec3 {
+        self.surface.as_ref().map_or(Vec3::zero(), |s| s.emitted(u, v, p))
+    }
+    fn emitted_at(&self, r_in: &Ray, rec: &hit_record) -> Vec3 {
+        self.surface.as_ref().map_or(Vec3::zero(), |s| s.emitted_at(r_in, rec))
+    }
+    fn visible_to_camera(&self) -> bool {
+        self.surface.as_ref().is_none_or(|s| s.visible_to_camera())
+    }
+    fn scattering_pdf(&self, r_in: &Ray, rec: &hit_record, scattered: &Ray) -> f64 {
+        self.surface.as_ref().map_or(0.0, |s| s.scattering_pdf(r_in, rec, scattered))
+    }
+    fn bsdf_cos(&self, r_in: &Ray, rec: &hit_record, scattered: &Ray) -> Vec3 {
+        self.surface.as_ref().map_or(Vec3::zero(), |s| s.bsdf_cos(r_in, rec, scattered))
+    }
+    fn alpha(&self, u: f64, v: f64, p: &Vec3) -> f64 {
+        self.surface.as_ref().map_or(1.0, |s| s.alpha(u, v, p))
+    }
+    fn interior(&self) -> Option<&medium_entry> {
+        Some(&self.interior)
+    }
+    fn is_interface(&self) -> bool {
+        self.surface.is_none()
+    }
+}
 
-        if rec1.t < 0.0 {
-            rec1.t = 0.0;
+// A medium inside its boundary, entered and left along the path by integrators that
+// keep a medium_stack (the path tracer); to the others it is just its surface. The
+// boundary may be non-convex, volumes may nest, and the camera may start inside one
+// (Camera::camera_media). Needs outward facing normals, like inside_segments.
+pub struct medium_volume {
+    pub boundary: Arc<dyn Hittable + Send + Sync>,
+    pub interface: Arc<medium_interface>,
+}
+
+impl medium_volume {
+    pub fn new(m: Arc<dyn medium + Send + Sync>, priority: i32) -> Self {
+        Self {
+            boundary: m.boundary(),
+            interface: Arc::new(medium_interface { interior: medium_entry { medium: m, priority }, surface: None }),
         }
-        let ray_length = r.direction().length();
-        let distance_inside_boundary = (rec2.t - rec1.t) * ray_length;
-        let hit_distance = self.neg_inv_density * raytracer::random_double().ln();
-        if hit_distance > distance_inside_boundary {
-            return false;
+    }
+    // surface e.g. a dielectric for a liquid in glass; it replaces the boundary's material
+    pub fn new_with_surface(m: Arc<dyn medium + Send + Sync>, priority: i32, surface: Arc<dyn Material + Send + Sync>) -> Self {
+        Self {
+            boundary: m.boundary(),
+            interface: Arc::new(medium_interface { interior: medium_entry { medium: m, priority }, surface: Some(surface) }),
         }
-        rec.t = rec1.t + hit_distance / ray_length;
-        rec.p = r.at(rec.t);
+    }
+    pub fn entry(&self) -> medium_entry {
+        self.interface.interior.clone()
+    }
+}
 
-        rec.normal = Vec3::new(1.0, 0.0, 0.0);  // arbitrary
-        rec.front_face = true;     // also arbitrary
-        rec.mat = self.phase_function.clone();
-        // println!("constant_medium::hit returning true");
+impl Hittable for medium_volume {
+    fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut hit_record) -> bool {
+        if !self.boundary.hit(r, ray_t, rec) {
+            return false;
+        }
+        rec.mat = self.interface.clone();
         true
     }
     fn bounding_box(&self) -> aabb {
         self.boundary.bounding_box()
     }
-}
\ No newline at end of file
+}
+
+// The media a path is in, innermost last.
+#[derive(Clone, Default)]
+pub struct medium_stack {
+    pub entries: Vec<medium_entry>,
+}
+
+impl medium_stack {
+    pub fn new() -> Self {
+        Self { entries: Vec::new() }
+    }
+    pub fn push(&mut self, entry: medium_entry) {
+        self.entries.push(entry);
+    }
+    // the medium the path travels through: the highest priority one, innermost on ties
+    pub fn active(&self) -> Option<&medium_entry> {
+        let mut best: Option<&medium_entry> = None;
+        for entry in &self.entries {
+            if best.is_none_or(|b| entry.priority >= b.priority) {
+                best = Some(entry);
+            }
+        }
+        best
+    }
+    // whether the surface hit in rec lets the path straight through: the surface of a
+    // medium without a surface material, or of one ranked below the active medium
+    pub fn passes(&self, rec: &hit_record) -> bool {
+        let Some(entry) = rec.mat.interior() else {
+            return false;
+        };
+        rec.mat.is_interface() || self.active().is_some_and(|a| a.priority > entry.priority)
+    }
+    // the path went through the surface hit in rec, into or out of the medium inside
+    pub fn cross(&mut self, rec: &hit_record) {
+        let Some(entry) = rec.mat.interior() else {
+            return;
+        };
+        if rec.front_face {
+            self.entries.push(entry.clone());
+        } else if let Some(i) = self.entries.iter().rposition(|e| Arc::ptr_eq(&e.medium, &entry.medium)) {
+            self.entries.remove(i);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hittable_list::HittableList;
+    use crate::quad::Quad;
+    use crate::sphere::Sphere;
+
+    fn white() -> Arc<Isotropic> {
+        Arc::new(Isotropic::new_from_color(Vec3::ones()))
+    }
+
+    #[test]
+    fn test_segments_of_non_convex_boundary() {
+        // two unit spheres 10 apart, the gap between them is outside
+        let mut pair = HittableList::new();
+        pair.add(Arc::new(Sphere::new(Vec3::zero(), 1.0, white())));
+        pair.add(Arc::new(Sphere::new(Vec3::new(0.0, 0.0, 10.0), 1.0, white())));
+        let r = Ray::new(Vec3::new(0.0, 0.0, -5.0), Vec3::new(0.0, 0.0, 1.0));
+        let segments = inside_segments(&pair, &r, Interval::new(0.001, f64::INFINITY));
+        assert_eq!(segments.len(), 2);
+        assert!((segments[0].0 - 4.0).abs() < 1e-6 && (segments[0].1 - 6.0).abs() < 1e-6);
+        assert!((segments[1].0 - 14.0).abs() < 1e-6 && (segments[1].1 - 16.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_segments_starting_inside() {
+        let cube = Quad::boxx(Vec3::new(-1.0, -1.0, -1.0), Vec3::new(1.0, 1.0, 1.0), white());
+        let r = Ray::new(Vec3::zero(), Vec3::new(1.0, 0.2, 0.1));
+        let segments = inside_segments(cube.as_ref(), &r, Interval::new(0.001, f64::INFINITY));
+        assert_eq!(segments.len(), 1);
+        assert!((segments[0].0 - 0.001).abs() < 1e-9 && (segments[0].1 - 1.0).abs() < 1e-6);
+        // clipped by a closer surface
+        let segments = inside_segments(cube.as_ref(), &r, Interval::new(0.001, 0.5));
+        assert!((segments[0].1 - 0.5).abs() < 1e-9);
+    }
+
+    fn volume(boundary: Arc<dyn Hittable + Send + Sync>, density: f64, priority: i32) -> Arc<medium_volume> {
+        Arc::new(medium_volume::new(Arc::new(constant_medium::new_from_color(boundary, density, Vec3::ones())), priority))
+    }
+
+    // the media along r after each surface it goes through
+    fn walk(world: &dyn Hittable, r: &Ray, media: &mut medium_stack) -> Vec<usize> {
+        let mut depths = Vec::new();
+        let mut t = 0.001;
+        let mut rec = hit_record::new();
+        while world.hit(r, Interval::new(t, f64::INFINITY), &mut rec) {
+            assert!(media.passes(&rec));
+            media.cross(&rec);
+            depths.push(media.entries.len());
+            t = rec.t + 0.001;
+        }
+        depths
+    }
+
+    #[test]
+    fn test_stack_from_inside_a_rotated_box() {
+        use crate::hittable::rotate_y;
+        let cube = Quad::boxx(Vec3::new(-1.0, -1.0, -1.0), Vec3::new(1.0, 1.0, 1.0), white());
+        let fog = volume(Arc::new(rotate_y::new(cube, 30.0)), 0.5, 0);
+        let mut world = HittableList::new();
+        world.add(fog.clone());
+        world.add(volume(Arc::new(Sphere::new(Vec3::new(5.0, 0.0, 0.0), 1.0, white())), 0.5, 0));
+        // the camera starts in the box, leaves it, then goes through the sphere
+        let mut media = medium_stack::new();
+        media.push(fog.entry());
+        let r = Ray::new(Vec3::zero(), Vec3::new(1.0, 0.0, 0.0));
+        assert_eq!(walk(&world, &r, &mut media), vec![0, 1, 0]);
+        // 1 - e^-1 of the rays collide within unit density over a unit distance
+        let entry = fog.entry();
+        assert!((entry.medium.transmittance_between(&r, Interval::new(0.0, 2.0)) - (-1.0f64).exp()).abs() < 1e-12);
+        let n = 20000;
+        let collided = (0..n).filter(|_| entry.medium.free_flight(&r, Interval::new(0.0, 2.0), &mut hit_record::new())).count();
+        assert!((collided as f64 / n as f64 - (1.0 - (-1.0f64).exp())).abs() < 0.02);
+    }
+
+    #[test]
+    fn test_nested_priority() {
+        let outer = volume(Arc::new(Sphere::new(Vec3::zero(), 3.0, white())), 0.1, 1);
+        let inner = volume(Arc::new(Sphere::new(Vec3::zero(), 1.0, white())), 2.0, 0);
+        let mut media = medium_stack::new();
+        let mut rec = hit_record::new();
+        rec.mat = outer.interface.clone();
+        rec.front_face = true;
+        media.cross(&rec);
+        // the inner medium ranks lower, so inside the outer one its surface isn't there
+        rec.mat = inner.interface.clone();
+        assert!(media.passes(&rec));
+        media.cross(&rec);
+        assert!(Arc::ptr_eq(&media.active().unwrap().medium, &outer.entry().medium));
+        rec.front_face = false;
+        media.cross(&rec);
+        rec.mat = outer.interface.clone();
+        media.cross(&rec);
+        assert!(media.active().is_none());
+        // a surface material makes the boundary a real surface, unless ranked below
+        let glass = medium_volume::new_with_surface(inner.entry().medium, 0, Arc::new(crate::material::dielectric::new(1.5)));
+        rec.mat = glass.interface.clone();
+        assert!(!media.passes(&rec));
+        media.push(outer.entry());
+        assert!(media.passes(&rec));
+    }
+}
@@ -0,0 +1,254 @@
+use std::sync::Arc;
+use crate::vec3::Vec3;
+use crate::color::luminance;
+use crate::onb::onb;
+use crate::light::{light, light_sample};
+use crate::texture::image_data;
+use crate::asset::{asset_manager, asset_error};
+use crate::raytracer::{pi, random_double};
+
+// Lat-long (equirectangular) environment seen by rays that miss everything, which can
+// also be sampled as a light in proportion to its luminance.
+pub struct environment_map {
+    width: usize,
+    height: usize,
+    pixels: Vec<Vec3>,
+    // row selection, height + 1 entries from 0 to 1
+    marginal_cdf: Vec<f64>,
+    // column selection per row, width + 1 entries each
+    conditional_cdf: Vec<f64>,
+    // sum of luminance * sin(theta) over all texels
+    total: f64,
+    pub intensity: f64,
+    // degrees around up
+    pub rotation: f64,
+    // the image's top row points along up
+    pub up: Vec3,
+}
+
+impl environment_map {
+    pub fn new(width: usize, height: usize, pixels: Vec<Vec3>) -> Self {
+        assert_eq!(pixels.len(), width * height);
+        let mut conditional_cdf = Vec::with_capacity(height * (width + 1));
+        let mut marginal_cdf = Vec::with_capacity(height + 1);
+        marginal_cdf.push(0.0);
+        let mut total = 0.0;
+        for j in 0..height {
+            // rows near the poles cover less solid angle
+            let sin_theta = ((j as f64 + 0.5) / height as f64 * pi).sin();
+            let mut row = 0.0;
+            conditional_cdf.push(0.0);
+            for i in 0..width {
+                row += luminance(pixels[j * width + i]) * sin_theta;
+                conditional_cdf.push(row);
+            }
+            let start = j * (width + 1);
+            for c in &mut conditional_cdf[start..start + width + 1] {
+                *c = if row > 0.0 { *c / row } else { 0.0 };
+            }
+            total += row;
+            marginal_cdf.push(total);
+        }
+        for c in &mut marginal_cdf {
+            *c = if total > 0.0 { *c / total } else { 0.0 };
+        }
+        Self {
+            width,
+            height,
+            pixels,
+            marginal_cdf,
+            conditional_cdf,
+            total,
+            intensity: 1.0,
+            rotation: 0.0,
+            up: Vec3::new(0.0, 1.0, 0.0),
+        }
+    }
+    pub fn new_from_data(data: &image_data) -> Self {
+        let level = &data.base;
+        let mut pixels = Vec::with_capacity(level.width * level.height);
+        for y in 0..level.height {
+            for x in 0..level.width {
+                let t = level.texel(x, y);
+                pixels.push(Vec3::new(t[0], t[1], t[2]));
+            }
+        }
+        Self::new(level.width, level.height, pixels)
+    }
+    // .hdr and .exr are read as linear radiance, 8 bit images are converted from srgb
+    pub fn load(assets: &asset_manager, path: &str) -> Result<Self, asset_error> {
+        let lower = path.to_lowercase();
+        let srgb = !(lower.ends_with(".hdr") || lower.ends_with(".exr"));
+        let data = assets.load_image(path, srgb)?;
+        Ok(Self::new_from_data(&data))
+    }
+    // bakes radiance(direction) into a width x height map, up being +y
+    pub fn new_from_fn(width: usize, height: usize, radiance: impl Fn(Vec3) -> Vec3) -> Self {
+        let mut pixels = Vec::with_capacity(width * height);
+        for j in 0..height {
+            for i in 0..width {
+                pixels.push(radiance(direction_at((i as f64 + 0.5) / width as f64, (j as f64 + 0.5) / height as f64)));
+            }
+        }
+        Self::new(width, height, pixels)
+    }
+
+    fn to_local(&self, direction: &Vec3) -> Vec3 {
+        world_to_local(self.up, direction)
+    }
+    fn to_world(&self, local: &Vec3) -> Vec3 {
+        local_to_world(self.up, local)
+    }
+    // world direction to image coordinates in [0,1)
+    fn to_uv(&self, direction: &Vec3) -> (f64, f64) {
+        let local = self.to_local(&direction.normalize());
+        let theta = local.y.max(-1.0).min(1.0).acos();
+        let phi = local.z.atan2(local.x) + self.rotation.to_radians();
+        ((phi / (2.0 * pi)).rem_euclid(1.0), theta / pi)
+    }
+    fn from_uv(&self, u: f64, v: f64) -> Vec3 {
+        self.to_world(&direction_at(u - self.rotation / 360.0, v))
+    }
+    fn texel(&self, i: usize, j: usize) -> Vec3 {
+        self.pixels[j.min(self.height - 1) * self.width + i % self.width]
+    }
+    // bilinear, wrapping around horizontally
+    pub fn radiance(&self, direction: &Vec3) -> Vec3 {
+        let (u, v) = self.to_uv(direction);
+        let x = u * self.width as f64 - 0.5;
+        let y = (v * self.height as f64 - 0.5).max(0.0);
+        let x0 = x.floor();
+        let y0 = y.floor();
+        let (fx, fy) = (x - x0, y - y0);
+        let i = (x0 as i64).rem_euclid(self.width as i64) as usize;
+        let j = y0 as usize;
+        let top = self.texel(i, j) * (1.0 - fx) + self.texel(i + 1, j) * fx;
+        let bottom = self.texel(i, j + 1) * (1.0 - fx) + self.texel(i + 1, j + 1) * fx;
+        (top * (1.0 - fy) + bottom * fy) * self.intensity
+    }
+}
+
+// Between world directions and the frame of a map whose top row points along up,
+// where up is +y. Rotation is not included.
+pub fn world_to_local(up: Vec3, direction: &Vec3) -> Vec3 {
+    let frame = onb::new(up);
+    // onb puts up in w; the signs make up = +y the identity mapping
+    Vec3::new(-direction.dot(frame.u()), direction.dot(frame.w()), -direction.dot(frame.v()))
+}
+pub fn local_to_world(up: Vec3, local: &Vec3) -> Vec3 {
+    let frame = onb::new(up);
+    frame.u() * -local.x + frame.w() * local.y + frame.v() * -local.z
+}
+
+// image coordinates to a direction with +y up, u = 0 along +x
+fn direction_at(u: f64, v: f64) -> Vec3 {
+    let theta = v * pi;
+    let phi = u * 2.0 * pi;
+    Vec3::new(theta.sin() * phi.cos(), theta.cos(), theta.sin() * phi.sin())
+}
+
+// index of the cdf interval containing xi
+fn find_interval(cdf: &[f64], xi: f64) -> usize {
+    let i = cdf.partition_point(|&c| c <= xi);
+    i.max(1).min(cdf.len() - 1) - 1
+}
+
+impl light for environment_map {
+    fn sample(&self, p: &Vec3) -> Option<light_sample> {
+        if self.total <= 0.0 {
+            return None;
+        }
+        let j = find_interval(&self.marginal_cdf, random_double());
+        let row = &self.conditional_cdf[j * (self.width + 1)..(j + 1) * (self.width + 1)];
+        let i = find_interval(row, random_double());
+        let u = (i as f64 + random_double()) / self.width as f64;
+        let v = (j as f64 + random_double()) / self.height as f64;
+        let direction = self.from_uv(u, v);
+        let pdf = self.pdf(p, &direction);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some(light_sample { direction, distance: f64::INFINITY, radiance: self.radiance(&direction), pdf, delta: false })
+    }
+    fn pdf(&self, p: &Vec3, direction: &Vec3) -> f64 {
+        if self.total <= 0.0 {
+            return 0.0;
+        }
+        let (u, v) = self.to_uv(direction);
+        let sin_theta = (v * pi).sin();
+        if sin_theta <= 0.0 {
+            return 0.0;
+        }
+        let i = ((u * self.width as f64) as usize).min(self.width - 1);
+        let j = ((v * self.height as f64) as usize).min(self.height - 1);
+        let row_sin = ((j as f64 + 0.5) / self.height as f64 * pi).sin();
+        let texel_weight = luminance(self.pixels[j * self.width + i]) * row_sin;
+        // density over the unit square, then per solid angle
+        let pdf_uv = texel_weight / self.total * (self.width * self.height) as f64;
+        pdf_uv / (2.0 * pi * pi * sin_theta)
+    }
+    fn background(&self, direction: &Vec3) -> Vec3 {
+        self.radiance(direction)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_map() -> environment_map {
+        // a dim sky with one bright spot
+        let mut map = environment_map::new_from_fn(64, 32, |d| {
+            if d.dot(Vec3::new(0.5, 0.6, -0.62).normalize()) > 0.98 { Vec3::new(50.0, 40.0, 30.0) } else { Vec3::new(0.2, 0.3, 0.5) }
+        });
+        map.rotation = 40.0;
+        map.up = Vec3::new(0.0, 0.0, 1.0);
+        map
+    }
+
+    #[test]
+    fn test_uv_round_trip() {
+        let map = test_map();
+        for (u, v) in [(0.1, 0.2), (0.75, 0.5), (0.95, 0.9)] {
+            let (u2, v2) = map.to_uv(&map.from_uv(u, v));
+            assert!((u - u2).abs() < 1e-9 && (v - v2).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_pdf_integrates_to_one() {
+        let map = test_map();
+        let n = 200000;
+        let sum: f64 = (0..n).map(|_| map.pdf(&Vec3::zero(), &Vec3::random_unit_vector())).sum();
+        // uniform directions have density 1 / (4 pi)
+        assert!((sum / n as f64 * 4.0 * pi - 1.0).abs() < 0.03);
+    }
+
+    #[test]
+    fn test_importance_sampling_estimates_irradiance() {
+        // integral of radiance over the sphere, by light sampling and by uniform sampling
+        let map = test_map();
+        let n = 100000;
+        let mut sampled = 0.0;
+        for _ in 0..n {
+            let s = map.sample(&Vec3::zero()).unwrap();
+            sampled += s.radiance.y / s.pdf;
+        }
+        let mut uniform = 0.0;
+        for _ in 0..n * 4 {
+            uniform += map.radiance(&Vec3::random_unit_vector()).y * 4.0 * pi;
+        }
+        let (sampled, uniform) = (sampled / n as f64, uniform / (n * 4) as f64);
+        assert!((sampled - uniform).abs() / uniform < 0.03);
+    }
+
+    #[test]
+    fn test_committed_sky_loads() {
+        use crate::asset::asset_manager;
+        // the earth scene's sky, with the sun well above the horizon
+        let sky = environment_map::load(&asset_manager::new(), "sky.hdr").unwrap();
+        let sun = sky.radiance(&Vec3::new((0.6 * pi).cos(), 35f64.to_radians().tan(), (0.6 * pi).sin()));
+        let zenith = sky.radiance(&Vec3::new(0.0, 1.0, 0.0));
+        assert!(sun.x > 20.0 && zenith.x < 1.0);
+    }
+}
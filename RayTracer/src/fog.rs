@@ -0,0 +1,156 @@
+use std::sync::Arc;
+use crate::vec3::Vec3;
+use crate::ray::Ray;
+use crate::phase::{phase, isotropic_phase};
+use crate::raytracer::random_double;
+
+// What happened to a ray travelling through the fog up to the next surface.
+pub enum fog_event {
+    // scattered in the fog; the new ray's color is multiplied by the weight
+    Scattered(Ray, Vec3),
+    // reached the surface (or the background); its color is multiplied by the weight
+    Passed(Vec3),
+}
+
+// Scene wide fog attached to the camera, so it also covers rays that escape to the
+// background. Coefficients are per unit length at base_height; with a height_falloff
+// the density drops as exp(-height_falloff * (height - base_height)) along up.
+pub struct fog {
+    pub sigma_a: Vec3,
+    pub sigma_s: Vec3,
+    pub height_falloff: f64,
+    pub base_height: f64,
+    pub up: Vec3,
+    // beyond this distance from the ray origin there is no fog
+    pub max_distance: f64,
+    pub phase: Arc<dyn phase + Send + Sync>,
+}
+
+impl fog {
+    pub fn new(sigma_a: Vec3, sigma_s: Vec3) -> Self {
+        Self {
+            sigma_a,
+            sigma_s,
+            height_falloff: 0.0,
+            base_height: 0.0,
+            up: Vec3::new(0.0, 1.0, 0.0),
+            max_distance: f64::INFINITY,
+            phase: Arc::new(isotropic_phase::new()),
+        }
+    }
+    pub fn new_height(sigma_a: Vec3, sigma_s: Vec3, base_height: f64, height_falloff: f64, up: Vec3) -> Self {
+        Self { height_falloff, base_height, up: up.normalize(), ..Self::new(sigma_a, sigma_s) }
+    }
+
+    // integral of the relative density along the ray over distance s, and its inverse
+    fn optical_depth(&self, origin: Vec3, dir: Vec3, s: f64) -> f64 {
+        if self.height_falloff == 0.0 {
+            return s;
+        }
+        let k = self.height_falloff;
+        let a = (-k * (origin.dot(self.up) - self.base_height)).exp();
+        let kd = k * dir.dot(self.up);
+        if kd.abs() < 1e-9 {
+            return a * s;
+        }
+        if s.is_infinite() {
+            return if kd > 0.0 { a / kd } else { f64::INFINITY };
+        }
+        a * (1.0 - (-kd * s).exp()) / kd
+    }
+    fn distance_at_depth(&self, origin: Vec3, dir: Vec3, tau: f64) -> f64 {
+        if self.height_falloff == 0.0 {
+            return tau;
+        }
+        let k = self.height_falloff;
+        let a = (-k * (origin.dot(self.up) - self.base_height)).exp();
+        let kd = k * dir.dot(self.up);
+        if kd.abs() < 1e-9 {
+            return tau / a;
+        }
+        let x = 1.0 - tau * kd / a;
+        // going up, the fog can run out before reaching tau
+        if x <= 0.0 {
+            return f64::INFINITY;
+        }
+        -x.ln() / kd
+    }
+
+    // t_max is the ray parameter of the surface hit, or infinity for the background
+    pub fn sample(&self, r: &Ray, t_max: f64) -> fog_event {
+        let length = r.direction().length();
+        let dir = r.direction() / length;
+        let s_max = (t_max * length).min(self.max_distance);
+        let sigma_t = self.sigma_a + self.sigma_s;
+        // free flights use the mean extinction, the weights fix up each channel
+        let sigma_bar = (sigma_t.x + sigma_t.y + sigma_t.z) / 3.0;
+        if sigma_bar <= 0.0 {
+            return fog_event::Passed(Vec3::ones());
+        }
+        let tau_sampled = -(1.0 - random_double()).ln() / sigma_bar;
+        let s = self.distance_at_depth(r.origin(), dir, tau_sampled);
+        if s < s_max {
+            let tau = tau_sampled;
+            let weight = Vec3::new(
+                (-(sigma_t.x - sigma_bar) * tau).exp() * self.sigma_s.x,
+                (-(sigma_t.y - sigma_bar) * tau).exp() * self.sigma_s.y,
+                (-(sigma_t.z - sigma_bar) * tau).exp() * self.sigma_s.z,
+            ) / sigma_bar;
+            let p = r.origin() + dir * s;
+            let scattered = Ray::new_with_time(p, self.phase.sample(dir), r.time());
+            return fog_event::Scattered(scattered, weight);
+        }
+        let tau = self.optical_depth(r.origin(), dir, s_max);
+        if tau.is_infinite() {
+            return fog_event::Passed(Vec3::zero());
+        }
+        fog_event::Passed(Vec3::new(
+            (-(sigma_t.x - sigma_bar) * tau).exp(),
+            (-(sigma_t.y - sigma_bar) * tau).exp(),
+            (-(sigma_t.z - sigma_bar) * tau).exp(),
+        ))
+    }
+
+    // exact transmittance over [0, t_max] of r
+    pub fn transmittance(&self, r: &Ray, t_max: f64) -> Vec3 {
+        let length = r.direction().length();
+        let s_max = (t_max * length).min(self.max_distance);
+        let tau = self.optical_depth(r.origin(), r.direction() / length, s_max);
+        let sigma_t = self.sigma_a + self.sigma_s;
+        Vec3::new((-sigma_t.x * tau).exp(), (-sigma_t.y * tau).exp(), (-sigma_t.z * tau).exp())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_depth_inverts() {
+        let f = fog::new_height(Vec3::zero(), Vec3::ones(), 0.0, 0.3, Vec3::new(0.0, 1.0, 0.0));
+        let origin = Vec3::new(0.0, 2.0, 0.0);
+        for dir in [Vec3::new(1.0, 0.5, 0.0), Vec3::new(1.0, -0.5, 0.0), Vec3::new(1.0, 0.0, 0.0)] {
+            let dir = dir.normalize();
+            let tau = f.optical_depth(origin, dir, 3.7);
+            assert!((f.distance_at_depth(origin, dir, tau) - 3.7).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_escaping_weight_matches_transmittance() {
+        // with nothing to scatter into, the mean weight of a ray to the background
+        // is the transmittance of the whole fog above the origin
+        let f = fog::new_height(Vec3::new(0.1, 0.2, 0.3), Vec3::new(0.3, 0.2, 0.1), 0.0, 0.5, Vec3::new(0.0, 1.0, 0.0));
+        let r = Ray::new(Vec3::zero(), Vec3::new(1.0, 1.0, 0.0));
+        let n = 80000;
+        let mut sum = Vec3::zero();
+        for _ in 0..n {
+            if let fog_event::Passed(w) = f.sample(&r, f64::INFINITY) {
+                sum += w;
+            }
+        }
+        let mean = sum / n as f64;
+        let expected = f.transmittance(&r, f64::INFINITY);
+        assert!((mean.x - expected.x).abs() < 0.01 && (mean.z - expected.z).abs() < 0.01);
+    }
+}
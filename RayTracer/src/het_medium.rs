@@ -0,0 +1,445 @@
+use crate::aabb::aabb;
+use crate::asset::asset_error;
+use crate::color::blackbody;
+use crate::con_medium::{inside_segments, medium};
+use crate::hittable::{hit_record, Hittable};
+use crate::interval::Interval;
+use crate::material::{diffuse_light, Isotropic, Material};
+use crate::perlin::perlin;
+use crate::ray::Ray;
+use crate::raytracer;
+use crate::texture::texture;
+use crate::vec3::Vec3;
+use std::path::Path;
+use std::sync::Arc;
+
+// density of a participating medium at a world position, never above max_density
+pub trait density_field {
+    fn density(&self, p: &Vec3) -> f64;
+    fn max_density(&self) -> f64;
+}
+
+// voxel densities spread over a world space box, trilinearly interpolated
+pub struct grid_density {
+    pub nx: usize,
+    pub ny: usize,
+    pub nz: usize,
+    // x varies fastest
+    pub data: Vec<f32>,
+    pub min: Vec3,
+    pub max: Vec3,
+    // multiplies every voxel
+    pub scale: f64,
+    max_voxel: f64,
+}
+
+impl grid_density {
+    pub fn new(
+        nx: usize,
+        ny: usize,
+        nz: usize,
+        data: Vec<f32>,
+        min: Vec3,
+        max: Vec3,
+    ) -> Result<Self, String> {
+        if nx == 0 || ny == 0 || nz == 0 {
+            return Err(format!("grid_density: empty {}x{}x{} grid", nx, ny, nz));
+        }
+        let count = nx.checked_mul(ny).and_then(|n| n.checked_mul(nz));
+        if count != Some(data.len()) {
+            return Err(format!(
+                "grid_density: {} voxels for a {}x{}x{} grid",
+                data.len(),
+                nx,
+                ny,
+                nz
+            ));
+        }
+        let max_voxel = data.iter().fold(0.0f32, |m, &d| m.max(d)) as f64;
+        Ok(Self {
+            nx,
+            ny,
+            nz,
+            data,
+            min,
+            max,
+            scale: 1.0,
+            max_voxel,
+        })
+    }
+    // Raw voxel file: three little-endian u32 sizes nx, ny, nz, then
+    // nx * ny * nz little-endian f32 densities with x varying fastest.
+    pub fn load_raw(path: &Path, min: Vec3, max: Vec3) -> Result<Self, asset_error> {
+        let bytes = match std::fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Err(asset_error::NotFound(path.to_path_buf()))
+            }
+            Err(e) => return Err(asset_error::Unreadable(path.to_path_buf(), e.to_string())),
+        };
+        let unreadable = |reason: String| asset_error::Unreadable(path.to_path_buf(), reason);
+        let word = |i: usize| {
+            [
+                bytes[4 * i],
+                bytes[4 * i + 1],
+                bytes[4 * i + 2],
+                bytes[4 * i + 3],
+            ]
+        };
+        if bytes.len() < 12 {
+            return Err(unreadable("missing voxel header".to_string()));
+        }
+        let nx = u32::from_le_bytes(word(0)) as usize;
+        let ny = u32::from_le_bytes(word(1)) as usize;
+        let nz = u32::from_le_bytes(word(2)) as usize;
+        let size = nx
+            .checked_mul(ny)
+            .and_then(|n| n.checked_mul(nz))
+            .and_then(|n| n.checked_mul(4))
+            .and_then(|n| n.checked_add(12));
+        if size != Some(bytes.len()) {
+            return Err(unreadable(format!(
+                "{} bytes don't hold a {}x{}x{} grid",
+                bytes.len(),
+                nx,
+                ny,
+                nz
+            )));
+        }
+        let data = (0..nx * ny * nz)
+            .map(|i| f32::from_le_bytes(word(3 + i)))
+            .collect();
+        Self::new(nx, ny, nz, data, min, max).map_err(unreadable)
+    }
+    pub fn save_raw(&self, path: &Path) -> std::io::Result<()> {
+        let mut bytes = Vec::with_capacity(12 + 4 * self.data.len());
+        for n in [self.nx, self.ny, self.nz] {
+            bytes.extend_from_slice(&(n as u32).to_le_bytes());
+        }
+        for d in &self.data {
+            bytes.extend_from_slice(&d.to_le_bytes());
+        }
+        std::fs::write(path, bytes)
+    }
+    pub fn bounding_box(&self) -> aabb {
+        aabb::new_from_points(self.min, self.max)
+    }
+    fn voxel(&self, i: usize, j: usize, k: usize) -> f64 {
+        self.data[(k * self.ny + j) * self.nx + i] as f64
+    }
+}
+
+impl density_field for grid_density {
+    fn density(&self, p: &Vec3) -> f64 {
+        let extent = self.max - self.min;
+        // voxel centers sit at (i + 0.5) / n
+        let gx = (p.x - self.min.x) / extent.x * self.nx as f64 - 0.5;
+        let gy = (p.y - self.min.y) / extent.y * self.ny as f64 - 0.5;
+        let gz = (p.z - self.min.z) / extent.z * self.nz as f64 - 0.5;
+        if gx < -0.5
+            || gy < -0.5
+            || gz < -0.5
+            || gx > self.nx as f64 - 0.5
+            || gy > self.ny as f64 - 0.5
+            || gz > self.nz as f64 - 0.5
+        {
+            return 0.0;
+        }
+        let clamp = |g: f64, n: usize| g.max(0.0).min((n - 1) as f64);
+        let (gx, gy, gz) = (clamp(gx, self.nx), clamp(gy, self.ny), clamp(gz, self.nz));
+        let (i0, j0, k0) = (gx as usize, gy as usize, gz as usize);
+        let (i1, j1, k1) = (
+            (i0 + 1).min(self.nx - 1),
+            (j0 + 1).min(self.ny - 1),
+            (k0 + 1).min(self.nz - 1),
+        );
+        let (fx, fy, fz) = (gx - i0 as f64, gy - j0 as f64, gz - k0 as f64);
+        let lerp = |a: f64, b: f64, t: f64| a + (b - a) * t;
+        let c00 = lerp(self.voxel(i0, j0, k0), self.voxel(i1, j0, k0), fx);
+        let c10 = lerp(self.voxel(i0, j1, k0), self.voxel(i1, j1, k0), fx);
+        let c01 = lerp(self.voxel(i0, j0, k1), self.voxel(i1, j0, k1), fx);
+        let c11 = lerp(self.voxel(i0, j1, k1), self.voxel(i1, j1, k1), fx);
+        self.scale * lerp(lerp(c00, c10, fy), lerp(c01, c11, fy), fz)
+    }
+    fn max_density(&self) -> f64 {
+        self.scale * self.max_voxel
+    }
+}
+
+// density from a closure, the caller promises it stays within [0, max]
+pub struct procedural_density {
+    pub func: Box<dyn Fn(&Vec3) -> f64 + Send + Sync>,
+    pub max: f64,
+}
+
+impl procedural_density {
+    pub fn new(func: Box<dyn Fn(&Vec3) -> f64 + Send + Sync>, max: f64) -> Self {
+        Self { func, max }
+    }
+    // density * turb(p * scale) with some contrast, fading out toward the edge of a sphere
+    // so the boundary doesn't show as a hard shell
+    pub fn new_turbulence(center: Vec3, radius: f64, scale: f64, density: f64, seed: u64) -> Self {
+        let noise = perlin::new_with_seed(seed);
+        let func = move |p: &Vec3| {
+            let r = (*p - center).length() / radius;
+            if r >= 1.0 {
+                return 0.0;
+            }
+            let falloff = 1.0 - r * r;
+            let t = (noise.turb(&(*p * scale), 7) * 1.6 - 0.15)
+                .max(0.0)
+                .min(1.0);
+            density * t * falloff
+        };
+        Self {
+            func: Box::new(func),
+            max: density,
+        }
+    }
+}
+
+impl density_field for procedural_density {
+    fn density(&self, p: &Vec3) -> f64 {
+        (self.func)(p).max(0.0).min(self.max)
+    }
+    fn max_density(&self) -> f64 {
+        self.max
+    }
+}
+
+// emission from a temperature field in kelvin: blackbody color, brighter as T^4
+// relative to 1000K, for fire and explosions
+pub struct blackbody_texture {
+    pub temperature: Arc<dyn density_field + Send + Sync>,
+    pub intensity: f64,
+}
+
+impl blackbody_texture {
+    pub fn new(temperature: Arc<dyn density_field + Send + Sync>, intensity: f64) -> Self {
+        Self {
+            temperature,
+            intensity,
+        }
+    }
+}
+
+impl texture for blackbody_texture {
+    fn value(&self, u: f64, v: f64, p: &Vec3) -> Vec3 {
+        let t = self.temperature.density(p);
+        if t <= 0.0 {
+            return Vec3::zero();
+        }
+        blackbody(t) * (self.intensity * (t / 1000.0).powi(4))
+    }
+}
+
+// Medium with spatially varying density inside a closed boundary. Free flights are
+// sampled by delta tracking against the majorant, so the cost grows with the majorant
+// rather than with how fine the density varies.
+pub struct heterogeneous_medium {
+    pub boundary: Arc<dyn Hittable + Send + Sync>,
+    pub density: Arc<dyn density_field + Send + Sync>,
+    pub majorant: f64,
+    pub phase_function: Arc<dyn Material + Send + Sync>,
+    // fraction of collisions that absorb instead of scatter; absorbed paths pick up
+    // the emitter's emission at that point and end there
+    pub absorption: f64,
+    pub emitter: Option<Arc<dyn Material + Send + Sync>>,
+}
+
+impl heterogeneous_medium {
+    pub fn new(
+        b: Arc<dyn Hittable + Send + Sync>,
+        density: Arc<dyn density_field + Send + Sync>,
+        a: Arc<dyn texture + Send + Sync>,
+    ) -> Self {
+        Self::new_with_phase(b, density, Arc::new(Isotropic::new(a)))
+    }
+    pub fn new_from_color(
+        b: Arc<dyn Hittable + Send + Sync>,
+        density: Arc<dyn density_field + Send + Sync>,
+        c: Vec3,
+    ) -> Self {
+        Self::new_with_phase(b, density, Arc::new(Isotropic::new_from_color(c)))
+    }
+    pub fn new_with_phase(
+        b: Arc<dyn Hittable + Send + Sync>,
+        density: Arc<dyn density_field + Send + Sync>,
+        phase: Arc<dyn Material + Send + Sync>,
+    ) -> Self {
+        let majorant = density.max_density();
+        Self {
+            boundary: b,
+            density,
+            majorant,
+            phase_function: phase,
+            absorption: 0.0,
+            emitter: None,
+        }
+    }
+    // glowing medium, emission e.g. a blackbody_texture or a solid color for glowing gas
+    pub fn new_emissive(
+        b: Arc<dyn Hittable + Send + Sync>,
+        density: Arc<dyn density_field + Send + Sync>,
+        emission: Arc<dyn texture + Send + Sync>,
+        absorption: f64,
+        scatter_color: Vec3,
+    ) -> Self {
+        let mut medium = Self::new_from_color(b, density, scatter_color);
+        medium.absorption = absorption.max(0.0).min(1.0);
+        medium.emitter = Some(Arc::new(diffuse_light::new(emission)));
+        medium
+    }
+    // unbiased transmittance estimate between ray_t.min and ray_t.max by ratio tracking
+    pub fn transmittance(&self, r: &Ray, ray_t: Interval) -> f64 {
+        if self.majorant <= 0.0 {
+            return 1.0;
+        }
+        inside_segments(self.boundary.as_ref(), r, ray_t)
+            .into_iter()
+            .map(|(t0, t1)| self.ratio_track(r, t0, t1))
+            .product()
+    }
+    fn ratio_track(&self, r: &Ray, t0: f64, t1: f64) -> f64 {
+        let ray_length = r.direction().length();
+        let mut tr = 1.0;
+        let mut t = t0;
+        loop {
+            t += -(1.0 - raytracer::random_double()).ln() / (self.majorant * ray_length);
+            if t >= t1 {
+                break;
+            }
+            tr *= 1.0 - self.density.density(&r.at(t)) / self.majorant;
+        }
+        tr
+    }
+    // delta tracking from t0 to t1, filling rec at the first real collision
+    fn track(&self, r: &Ray, t0: f64, t1: f64, rec: &mut hit_record) -> bool {
+        let ray_length = r.direction().length();
+        let mut t = t0;
+        loop {
+            t += -(1.0 - raytracer::random_double()).ln() / (self.majorant * ray_length);
+            if t >= t1 {
+                return false;
+            }
+            let p = r.at(t);
+            // real collision with probability density / majorant, otherwise a null one
+            if raytracer::random_double() * self.majorant < self.density.density(&p) {
+                rec.t = t;
+                rec.p = p;
+                rec.normal = Vec3::new(1.0, 0.0, 0.0); // arbitrary
+                rec.front_face = true; // also arbitrary
+                rec.mat = match &self.emitter {
+                    Some(emitter) if raytracer::random_double() < self.absorption => {
+                        emitter.clone()
+                    }
+                    _ => self.phase_function.clone(),
+                };
+                return true;
+            }
+        }
+    }
+}
+
+impl Hittable for heterogeneous_medium {
+    fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut hit_record) -> bool {
+        if self.majorant <= 0.0 {
+            return false;
+        }
+        inside_segments(self.boundary.as_ref(), r, ray_t)
+            .into_iter()
+            .any(|(t0, t1)| self.track(r, t0, t1, rec))
+    }
+    fn bounding_box(&self) -> aabb {
+        self.boundary.bounding_box()
+    }
+}
+
+// the density field is only followed within the boundary's bounding box, where the
+// medium_volume's surface keeps the path anyway
+impl medium for heterogeneous_medium {
+    fn free_flight(&self, r: &Ray, ray_t: Interval, rec: &mut hit_record) -> bool {
+        if self.majorant <= 0.0 {
+            return false;
+        }
+        match self.boundary.bounding_box().clip(r, ray_t) {
+            Some(t) => self.track(r, t.min, t.max, rec),
+            None => false,
+        }
+    }
+    fn transmittance_between(&self, r: &Ray, ray_t: Interval) -> f64 {
+        if self.majorant <= 0.0 {
+            return 1.0;
+        }
+        match self.boundary.bounding_box().clip(r, ray_t) {
+            Some(t) => self.ratio_track(r, t.min, t.max),
+            None => 1.0,
+        }
+    }
+    fn boundary(&self) -> Arc<dyn Hittable + Send + Sync> {
+        self.boundary.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sphere::Sphere;
+
+    #[test]
+    fn test_raw_grid_round_trip() {
+        let data: Vec<f32> = (0..24).map(|i| i as f32).collect();
+        let grid =
+            grid_density::new(2, 3, 4, data, Vec3::zero(), Vec3::new(2.0, 3.0, 4.0)).unwrap();
+        let path = std::env::temp_dir().join("het_medium_test.raw");
+        grid.save_raw(&path).unwrap();
+        let loaded = grid_density::load_raw(&path, Vec3::zero(), Vec3::new(2.0, 3.0, 4.0)).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.data, grid.data);
+        // center of voxel (1, 2, 3)
+        assert_eq!(loaded.density(&Vec3::new(1.5, 2.5, 3.5)), 23.0);
+        assert_eq!(loaded.max_density(), 23.0);
+    }
+
+    #[test]
+    fn test_bad_grids_are_errors() {
+        assert!(grid_density::new(0, 3, 4, Vec::new(), Vec3::zero(), Vec3::ones()).is_err());
+        assert!(grid_density::new(2, 2, 2, vec![0.0; 7], Vec3::zero(), Vec3::ones()).is_err());
+        let path = std::env::temp_dir().join("het_medium_bad_header.raw");
+        for header in [[0u32, 5, 5], [u32::MAX, u32::MAX, u32::MAX]] {
+            let bytes: Vec<u8> = header.iter().flat_map(|n| n.to_le_bytes()).collect();
+            std::fs::write(&path, bytes).unwrap();
+            match grid_density::load_raw(&path, Vec3::zero(), Vec3::ones()) {
+                Err(asset_error::Unreadable(..)) => {}
+                _ => panic!("expected Unreadable"),
+            }
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_tracking_matches_beer_lambert() {
+        // constant 0.5 under a majorant of 2, so most collisions are null
+        let density = Arc::new(procedural_density::new(Box::new(|_p: &Vec3| 0.5), 2.0));
+        let boundary = Arc::new(Sphere::new(
+            Vec3::zero(),
+            1.0,
+            Arc::new(Isotropic::new_from_color(Vec3::zero())),
+        ));
+        let medium = heterogeneous_medium::new_from_color(boundary, density, Vec3::zero());
+        let r = Ray::new(Vec3::new(0.0, 0.0, -5.0), Vec3::new(0.0, 0.0, 1.0));
+        let expected = (-0.5f64 * 2.0).exp();
+        let n = 20000;
+        let mut escaped = 0;
+        let mut tr = 0.0;
+        for _ in 0..n {
+            let mut rec = hit_record::new();
+            if !medium.hit(&r, Interval::new(0.001, f64::INFINITY), &mut rec) {
+                escaped += 1;
+            }
+            tr += medium.transmittance(&r, Interval::new(0.001, f64::INFINITY));
+        }
+        assert!((escaped as f64 / n as f64 - expected).abs() < 0.02);
+        assert!((tr / n as f64 - expected).abs() < 0.02);
+    }
+}
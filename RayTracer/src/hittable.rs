@@ -3,10 +3,12 @@ use crate::vec3::Vec3;
 use crate::interval::Interval;
 use crate::material::Material;
 use crate::aabb::aabb;
+use crate::light::{light, instanced_light};
 // use std::rc::Rc;
 use std::sync::Arc;
 
 
+#[derive(Clone)]
 pub struct hit_record {
     pub p: Vec3,
     pub normal: Vec3,
@@ -15,17 +17,55 @@ pub struct hit_record {
     pub u: f64,
     pub v: f64,
     pub front_face: bool,
+    // surface derivatives dp/du and dp/dv, zero if the primitive has no uv parameterization
+    pub tangent: Vec3,
+    pub bitangent: Vec3,
+    // world space width of the pixel footprint at p
+    pub footprint: f64,
 }
 impl hit_record {
+    pub fn new() -> Self {
+        Self {
+            p: Vec3::zero(),
+            normal: Vec3::zero(),
+            t: 0.0,
+            front_face: false,
+            mat: Arc::new(crate::material::lambertian::new(Vec3::zero())),
+            u: 0.0,
+            v: 0.0,
+            tangent: Vec3::zero(),
+            bitangent: Vec3::zero(),
+            footprint: 0.0,
+        }
+    }
+    // footprint measured in uv units, used to pick a mip level
+    pub fn uv_footprint(&self) -> f64 {
+        let scale = (self.tangent.length() * self.bitangent.length()).sqrt();
+        if scale <= 0.0 {
+            return 0.0;
+        }
+        self.footprint / scale
+    }
     pub fn set_face_normal(&mut self, r: &Ray, outward_normal: Vec3) {
         self.front_face = r.direction().dot(outward_normal) < 0.0;
         self.normal = if self.front_face {outward_normal} else {outward_normal * -1.0};
     }
 }
 
+// alpha-tested hits below this coverage are ignored and the ray continues
+pub const ALPHA_CUTOFF: f64 = 0.5;
+
 pub trait Hittable {
     fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut hit_record) -> bool;
     fn bounding_box(&self) -> aabb;
+    // lights for the emissive primitives in here, for passes that start at every emitter
+    // whether or not it was registered in cam.lights (photon mapping)
+    fn emitters(&self, lights: &mut Vec<Arc<dyn light + Send + Sync>>) {}
+    // hit() that also adds the bvh nodes whose boxes it tests to nodes, for the
+    // traversal cost view; hit() itself keeps no count
+    fn hit_counted(&self, r: &Ray, ray_t: Interval, rec: &mut hit_record, nodes: &mut usize) -> bool {
+        self.hit(r, ray_t, rec)
+    }
 }
 
 pub struct translate {
@@ -49,6 +89,19 @@ impl Hittable for translate {
         rec.p += self.offset;
         return true;
     }
+    fn hit_counted(&self, r: &Ray, ray_t: Interval, rec: &mut hit_record, nodes: &mut usize) -> bool {
+        let offset_r = Ray::new_with_time(r.origin() - self.offset, r.direction(), r.time());
+        if !self.object.hit_counted(&offset_r, ray_t, rec, nodes) {
+            return false;
+        }
+        rec.p += self.offset;
+        return true;
+    }
+    fn emitters(&self, lights: &mut Vec<Arc<dyn light + Send + Sync>>) {
+        let mut inner = Vec::new();
+        self.object.emitters(&mut inner);
+        lights.extend(inner.into_iter().map(|l| Arc::new(instanced_light::new_translated(l, self.offset)) as Arc<dyn light + Send + Sync>));
+    }
     fn bounding_box(&self) -> aabb {
         self.bbox
     }
@@ -94,18 +147,17 @@ impl rotate_y {
 
 }
 
-impl Hittable for rotate_y {
-    fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut hit_record) -> bool {
+impl rotate_y {
+    fn to_object(&self, r: &Ray) -> Ray {
         let mut origin = r.origin();
         let mut direction = r.direction();
         origin.x = self.cos_theta * r.origin().x - self.sin_theta * r.origin().z;
         origin.z = self.sin_theta * r.origin().x + self.cos_theta * r.origin().z;
         direction.x = self.cos_theta * r.direction().x - self.sin_theta * r.direction().z;
         direction.z = self.sin_theta * r.direction().x + self.cos_theta * r.direction().z;
-        let rotated_r = Ray::new_with_time(origin, direction, r.time());
-        if !self.object.hit(&rotated_r, ray_t, rec) {
-            return false;
-        }
+        Ray::new_with_time(origin, direction, r.time())
+    }
+    fn to_world(&self, rec: &mut hit_record) {
         let mut p = rec.p;
         p.x = self.cos_theta * rec.p.x + self.sin_theta * rec.p.z;
         p.z = -self.sin_theta * rec.p.x + self.cos_theta * rec.p.z;
@@ -113,11 +165,40 @@ impl Hittable for rotate_y {
         let mut normal = rec.normal;
         normal.x = self.cos_theta * rec.normal.x + self.sin_theta * rec.normal.z;
         normal.z = -self.sin_theta * rec.normal.x + self.cos_theta * rec.normal.z;
+        let mut tangent = rec.tangent;
+        tangent.x = self.cos_theta * rec.tangent.x + self.sin_theta * rec.tangent.z;
+        tangent.z = -self.sin_theta * rec.tangent.x + self.cos_theta * rec.tangent.z;
+
+        let mut bitangent = rec.bitangent;
+        bitangent.x = self.cos_theta * rec.bitangent.x + self.sin_theta * rec.bitangent.z;
+        bitangent.z = -self.sin_theta * rec.bitangent.x + self.cos_theta * rec.bitangent.z;
         rec.p = p;
         rec.normal = normal;
+        rec.tangent = tangent;
+        rec.bitangent = bitangent;
+    }
+}
 
+impl Hittable for rotate_y {
+    fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut hit_record) -> bool {
+        if !self.object.hit(&self.to_object(r), ray_t, rec) {
+            return false;
+        }
+        self.to_world(rec);
         return true;
     }
+    fn hit_counted(&self, r: &Ray, ray_t: Interval, rec: &mut hit_record, nodes: &mut usize) -> bool {
+        if !self.object.hit_counted(&self.to_object(r), ray_t, rec, nodes) {
+            return false;
+        }
+        self.to_world(rec);
+        return true;
+    }
+    fn emitters(&self, lights: &mut Vec<Arc<dyn light + Send + Sync>>) {
+        let mut inner = Vec::new();
+        self.object.emitters(&mut inner);
+        lights.extend(inner.into_iter().map(|l| Arc::new(instanced_light::new_rotated(l, self.sin_theta, self.cos_theta)) as Arc<dyn light + Send + Sync>));
+    }
     fn bounding_box(&self) -> aabb {
         self.bbox
     }
@@ -8,6 +8,7 @@ use std::sync::Arc;
 use std::vec::Vec;
 use crate::aabb::*;
 use crate::bvh::*;  
+use crate::light::light;
 
 pub struct HittableList {
     pub objects: Vec<Arc<dyn Hittable + Send + Sync>>,
@@ -30,24 +31,14 @@ impl HittableList {
         self.bbox = aabb::new_from_aabbs(&self.bbox, &object.bounding_box());
         self.objects.push(object);
     }
-}
-
-impl Hittable for HittableList {
-    fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut hit_record) -> bool {
-        let mut temp_rec = hit_record {
-            p: Vec3::zero(),
-            normal: Vec3::zero(),
-            t: 0.0,
-            front_face: false,
-            mat: Arc::new(crate::material::lambertian::new(Vec3::zero())),
-            u: 0.0,
-            v: 0.0,     
-        };
+    // the nearest of the objects' hits, each found by hit_object
+    fn nearest(&self, ray_t: Interval, rec: &mut hit_record, mut hit_object: impl FnMut(&Arc<dyn Hittable + Send + Sync>, Interval, &mut hit_record) -> bool) -> bool {
+        let mut temp_rec = hit_record::new();
         // bool hit_anything = false;
         let mut hit_anything = false;
         let mut closest_so_far = ray_t.max;
         for object in &self.objects {
-            if object.hit(r, Interval::new(ray_t.min, closest_so_far), &mut temp_rec) {
+            if hit_object(object, Interval::new(ray_t.min, closest_so_far), &mut temp_rec) {
                 hit_anything = true;
                 closest_so_far = temp_rec.t;
 
@@ -57,12 +48,28 @@ impl Hittable for HittableList {
                 rec.v = temp_rec.v;
                 rec.normal = temp_rec.normal;
                 rec.front_face = temp_rec.front_face;
+                rec.tangent = temp_rec.tangent;
+                rec.bitangent = temp_rec.bitangent;
                 rec.mat = Arc::clone(&temp_rec.mat);
 
             }
         }
         return hit_anything;
     }
+}
+
+impl Hittable for HittableList {
+    fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut hit_record) -> bool {
+        self.nearest(ray_t, rec, |object, ray_t, rec| object.hit(r, ray_t, rec))
+    }
+    fn hit_counted(&self, r: &Ray, ray_t: Interval, rec: &mut hit_record, nodes: &mut usize) -> bool {
+        self.nearest(ray_t, rec, |object, ray_t, rec| object.hit_counted(r, ray_t, rec, nodes))
+    }
+    fn emitters(&self, lights: &mut Vec<Arc<dyn light + Send + Sync>>) {
+        for object in &self.objects {
+            object.emitters(lights);
+        }
+    }
     fn bounding_box(&self) -> aabb {
         return self.bbox;
     }
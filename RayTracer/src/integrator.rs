@@ -0,0 +1,866 @@
+use crate::bdpt::bdpt;
+use crate::camera::Camera;
+use crate::con_medium::medium_stack;
+use crate::fog::fog_event;
+use crate::hittable::{hit_record, Hittable};
+use crate::interval::Interval;
+use crate::mlt::metropolis;
+use crate::onb::onb;
+use crate::photon_map::{photon_map, photon_mapper};
+use crate::ray::Ray;
+use crate::raytracer::{pi, random_double};
+use crate::vec3::Vec3;
+use std::sync::Arc;
+
+// Light transport, separate from the camera that generates the rays. The camera's
+// scene settings (lights, environment, background, fog, max_depth) are read from cam;
+// which of them an integrator honours is up to it.
+pub trait integrator {
+    // radiance arriving at the camera along r; what the sample adds to other pixels
+    // (light paths reaching the lens) goes to splats
+    fn li(
+        &self,
+        cam: &Camera,
+        r: &Ray,
+        world: &Arc<dyn Hittable + Send + Sync>,
+        splats: &mut Vec<splat>,
+    ) -> Vec3;
+    // the integrator to render with once cam is initialized, for ones that need the
+    // lights or the world before the first li; None if this one is ready as it is
+    fn prepare(
+        &self,
+        cam: &Camera,
+        world: &Arc<dyn Hittable + Send + Sync>,
+    ) -> Option<Arc<dyn integrator + Send + Sync>> {
+        None
+    }
+    // the whole linear image, for integrators that don't sample pixel by pixel; None
+    // has the camera call li for each of its pixel samples
+    fn render(&self, cam: &Camera, world: &Arc<dyn Hittable + Send + Sync>) -> Option<Vec<Vec3>> {
+        None
+    }
+}
+
+// (x, y, radiance) for pixel (x, y) from a sample taken through some other pixel, added
+// to the image without that pixel's sample count
+pub type splat = (usize, usize, Vec3);
+
+// "path", "direct", "whitted", "ao", "bdpt", "photons", "mlt" (over the path tracer),
+// "mlt_bdpt", or one of the debug views "normals", "uv", "depth", "material", "bvh"
+// and "front_face", for picking an integrator at runtime
+pub fn by_name(name: &str) -> Option<Arc<dyn integrator + Send + Sync>> {
+    match name {
+        "path" => Some(Arc::new(path_tracer::new())),
+        "bdpt" => Some(Arc::new(bdpt::new())),
+        "photons" => Some(Arc::new(photon_mapper::new())),
+        "mlt" => Some(Arc::new(metropolis::new(Arc::new(path_tracer::new())))),
+        "mlt_bdpt" => Some(Arc::new(metropolis::new(Arc::new(bdpt::new())))),
+        "direct" => Some(Arc::new(direct_lighting::new())),
+        "whitted" => Some(Arc::new(whitted::new())),
+        "ao" => Some(Arc::new(ambient_occlusion::new())),
+        "normals" => Some(Arc::new(debug::new(debug_view::Normals))),
+        "uv" => Some(Arc::new(debug::new(debug_view::UV))),
+        "depth" => Some(Arc::new(debug::new(debug_view::Depth))),
+        "material" => Some(Arc::new(debug::new(debug_view::MaterialId))),
+        "bvh" => Some(Arc::new(debug::new(debug_view::BvhCost(64)))),
+        "front_face" => Some(Arc::new(debug::new(debug_view::FrontFace))),
+        _ => None,
+    }
+}
+
+pub fn power_heuristic(pdf_a: f64, pdf_b: f64) -> f64 {
+    let a = pdf_a * pdf_a;
+    let b = pdf_b * pdf_b;
+    if a + b > 0.0 {
+        a / (a + b)
+    } else {
+        0.0
+    }
+}
+
+// the nearest hit, skipping emitters hidden from the camera when r is a camera ray
+fn hit(
+    world: &Arc<dyn Hittable + Send + Sync>,
+    r: &Ray,
+    camera_ray: bool,
+    rec: &mut hit_record,
+) -> bool {
+    let mut hit = world.hit(r, Interval::new(0.001, f64::INFINITY), rec);
+    while hit && camera_ray && !rec.mat.visible_to_camera() {
+        let t = rec.t;
+        hit = world.hit(r, Interval::new(t + 0.001, f64::INFINITY), rec);
+    }
+    if hit {
+        rec.footprint = r.footprint(rec.t);
+    }
+    hit
+}
+
+// What a ray that leaves the scene sees: the background, or the lights at infinity.
+// bsdf_pdf is the density the ray was sampled with, 0 if light sampling couldn't have
+// produced it (camera rays, mirrors, glass, ...).
+fn escaped(cam: &Camera, r: &Ray, bsdf_pdf: f64) -> Vec3 {
+    // the environment is one of the lights, handled with the others
+    let mut c = if cam.environment.is_some() {
+        Vec3::zero()
+    } else {
+        cam.background
+    };
+    let lights = cam.light_sampler();
+    for &i in lights.infinite_lights() {
+        let l = lights.light(i);
+        let radiance = l.background(&r.direction());
+        if radiance.near_zero() {
+            continue;
+        }
+        // the light could also have been reached by light sampling
+        let mut weight = 1.0;
+        if bsdf_pdf > 0.0 {
+            let light_pdf = l.pdf(&r.origin(), &r.direction()) * lights.pmf(&r.origin(), i);
+            weight = power_heuristic(bsdf_pdf, light_pdf);
+        }
+        c += radiance * weight;
+    }
+    c
+}
+
+// emission at rec back along r, weighted against light sampling like escaped()
+fn emitted(cam: &Camera, r: &Ray, rec: &hit_record, bsdf_pdf: f64) -> Vec3 {
+    let emission = rec.mat.emitted_at(r, rec);
+    if bsdf_pdf > 0.0 && !emission.near_zero() {
+        let light_pdf = cam
+            .light_sampler()
+            .emitter_pdf(&r.origin(), &r.direction(), &rec.p);
+        return emission * power_heuristic(bsdf_pdf, light_pdf);
+    }
+    emission
+}
+
+// Fraction of the light at distance along shadow that reaches its origin. Surfaces the
+// media let through (see medium_stack::passes) dim it by the media behind them, any
+// other surface blocks it.
+fn transmittance(
+    world: &Arc<dyn Hittable + Send + Sync>,
+    shadow: &Ray,
+    distance: f64,
+    media: &medium_stack,
+) -> f64 {
+    let end = distance * (1.0 - 1e-6);
+    let mut media = media.clone();
+    let mut tr = 1.0;
+    let mut t = 0.001;
+    loop {
+        let mut rec = hit_record::new();
+        let found = world.hit(shadow, Interval::new(t, end), &mut rec);
+        if let Some(active) = media.active() {
+            tr *= active
+                .medium
+                .transmittance_between(shadow, Interval::new(t, if found { rec.t } else { end }));
+        }
+        if !found {
+            return tr;
+        }
+        if tr <= 0.0 || !media.passes(&rec) {
+            return 0.0;
+        }
+        media.cross(&rec);
+        t = rec.t + 0.001;
+    }
+}
+
+// next event estimation: one shadow ray toward a light picked by the camera's light
+// sampler, weighted against the material's own sampling by the power heuristic
+fn sample_light(
+    cam: &Camera,
+    r: &Ray,
+    rec: &hit_record,
+    world: &Arc<dyn Hittable + Send + Sync>,
+    media: &medium_stack,
+) -> Vec3 {
+    let lights = cam.light_sampler();
+    let Some((pick, pmf)) = lights.sample(&rec.p) else {
+        return Vec3::zero();
+    };
+    let Some(s) = lights.light(pick).sample(&rec.p) else {
+        return Vec3::zero();
+    };
+    let shadow = Ray::new_with_time(rec.p, s.direction, r.time());
+    let bsdf_pdf = rec.mat.scattering_pdf(r, rec, &shadow);
+    if bsdf_pdf <= 0.0 {
+        return Vec3::zero();
+    }
+    let tr = transmittance(world, &shadow, s.distance, media);
+    if tr <= 0.0 {
+        return Vec3::zero();
+    }
+    let mut radiance = s.radiance * tr;
+    if let Some(fog) = &cam.fog {
+        radiance = radiance * fog.transmittance(&shadow, s.distance);
+    }
+    let light_pdf = s.pdf * pmf;
+    let weight = if s.delta {
+        1.0
+    } else {
+        power_heuristic(light_pdf, bsdf_pdf)
+    };
+    rec.mat.bsdf_cos(r, rec, &shadow) * radiance * (weight / light_pdf)
+}
+
+// Unidirectional path tracing with next event estimation, fog and media. The media the path is in are kept on a medium_stack, updated as it goes
+// through the surfaces of medium_volumes. Paths that carry little light are ended at random by Russian roulette after rr_depth
+// bounces, and the survivors weighted up to make up for them.
+#[derive(Clone)]
+pub struct path_tracer {
+    pub rr_depth: usize,
+}
+
+impl path_tracer {
+    pub fn new() -> Self {
+        Self { rr_depth: 3 }
+    }
+
+    // with the caustics read from maps instead of found by the path, see photon_mapper
+    pub fn trace(
+        &self,
+        cam: &Camera,
+        r: &Ray,
+        world: &Arc<dyn Hittable + Send + Sync>,
+        maps: &[photon_map],
+    ) -> Vec3 {
+        let mut color = Vec3::zero();
+        // what the rest of the path is multiplied by before reaching the camera
+        let mut beta = Vec3::ones();
+        let mut r = r.clone();
+        // the density r was sampled with, 0 if light sampling couldn't have produced it
+        let mut bsdf_pdf = 0.0;
+        // set on rays that left a diffuse surface and have only met mirrors and glass
+        // since, whose light the photon maps already hold
+        let mut caustic = false;
+        let mut media = cam.camera_media.clone();
+        // where r last scattered; past media boundaries r starts on them instead
+        let mut vertex = r.origin();
+        let mut camera_ray = true;
+        for depth in 0..cam.max_depth {
+            let mut rec = hit_record::new();
+            let mut hit = hit(world, &r, camera_ray, &mut rec);
+            if let Some(active) = media.active() {
+                let mut collision = hit_record::new();
+                if active.medium.free_flight(
+                    &r,
+                    Interval::new(0.0, if hit { rec.t } else { f64::INFINITY }),
+                    &mut collision,
+                ) {
+                    collision.footprint = r.footprint(collision.t);
+                    rec = collision;
+                    hit = true;
+                }
+            }
+            if let Some(fog) = &cam.fog {
+                match fog.sample(&r, if hit { rec.t } else { f64::INFINITY }) {
+                    fog_event::Scattered(scattered, weight) => {
+                        beta = beta * weight;
+                        r = scattered;
+                        vertex = r.origin();
+                        camera_ray = false;
+                        bsdf_pdf = 0.0;
+                        caustic = false;
+                        continue;
+                    }
+                    fog_event::Passed(weight) => beta = beta * weight,
+                }
+            }
+            // r from the vertex, which light sampling would have aimed from
+            let seen = Ray::new_with_time(vertex, r.direction(), r.time());
+            if !hit {
+                color += beta * escaped(cam, &seen, bsdf_pdf);
+                break;
+            }
+            // not a vertex of the path, only a change of medium
+            if media.passes(&rec) {
+                media.cross(&rec);
+                let mut through = Ray::new_with_time(rec.p, r.direction(), r.time());
+                through.inherit_footprint(&r, rec.footprint);
+                r = through;
+                continue;
+            }
+            let mut scattered = Ray::new(Vec3::zero(), Vec3::zero());
+            let mut attenuation = Vec3::zero();
+            // the emitters photon_lights traces photons from, registered or not
+            let photon_source = rec.mat.emitter().is_some()
+                || cam
+                    .light_sampler()
+                    .emitter_pdf(&vertex, &r.direction(), &rec.p)
+                    > 0.0;
+            let in_photon_map = caustic && bsdf_pdf == 0.0 && photon_source;
+            if !in_photon_map {
+                color += beta * emitted(cam, &seen, &rec, bsdf_pdf);
+            }
+            let mut pdf = 0.0;
+            if !rec
+                .mat
+                .scatter_with_pdf(&r, &rec, &mut attenuation, &mut scattered, &mut pdf)
+            {
+                break;
+            }
+            scattered.inherit_footprint(&r, rec.footprint);
+            // light for the smooth lobes, even when scatter took a delta one
+            let smooth = rec.mat.has_smooth_lobe(&r, &rec);
+            if smooth && !cam.light_sampler().is_empty() {
+                color += beta * sample_light(cam, &r, &rec, world, &media);
+            }
+            let diffuse = smooth && !rec.mat.is_volumetric();
+            if diffuse && !maps.is_empty() {
+                // any one pass is an unbiased pick from the average over all of them
+                let map =
+                    &maps[((random_double() * maps.len() as f64) as usize).min(maps.len() - 1)];
+                color += beta * map.estimate(&r, &rec);
+            }
+            // transmitted into or out of the medium behind the surface
+            if scattered.direction().dot(rec.normal) < 0.0 {
+                media.cross(&rec);
+            }
+            if pdf > 0.0 {
+                caustic = diffuse && !maps.is_empty();
+            }
+            beta = beta * attenuation;
+            bsdf_pdf = pdf;
+            r = scattered;
+            vertex = r.origin();
+            camera_ray = false;
+            if depth + 1 >= self.rr_depth {
+                let survive = beta.x.max(beta.y).max(beta.z).min(0.95);
+                if random_double() >= survive {
+                    break;
+                }
+                beta = beta / survive;
+            }
+        }
+        color
+    }
+}
+
+impl integrator for path_tracer {
+    fn li(
+        &self,
+        cam: &Camera,
+        r: &Ray,
+        world: &Arc<dyn Hittable + Send + Sync>,
+        splats: &mut Vec<splat>,
+    ) -> Vec3 {
+        self.trace(cam, r, world, &[])
+    }
+}
+
+// Light that reaches the visible surfaces straight from the emitters, seen directly or
+// in mirrors and glass. No indirect bounces, no fog and no scattering in media.
+pub struct direct_lighting {}
+
+impl direct_lighting {
+    pub fn new() -> Self {
+        Self {}
+    }
+
+    // bsdf_pdf > 0 means r was sampled at a diffuse surface, only to find emitters
+    fn trace(
+        &self,
+        cam: &Camera,
+        r: &Ray,
+        depth: usize,
+        world: &Arc<dyn Hittable + Send + Sync>,
+        bsdf_pdf: f64,
+    ) -> Vec3 {
+        if depth == 0 {
+            return Vec3::zero();
+        }
+        let mut rec = hit_record::new();
+        if !hit(world, r, depth == cam.max_depth, &mut rec) {
+            return escaped(cam, r, bsdf_pdf);
+        }
+        let emission = emitted(cam, r, &rec, bsdf_pdf);
+        if bsdf_pdf > 0.0 {
+            return emission;
+        }
+        let mut scattered = Ray::new(Vec3::zero(), Vec3::zero());
+        let mut attenuation = Vec3::zero();
+        let mut pdf = 0.0;
+        if !rec
+            .mat
+            .scatter_with_pdf(r, &rec, &mut attenuation, &mut scattered, &mut pdf)
+        {
+            return emission;
+        }
+        scattered.inherit_footprint(r, rec.footprint);
+        let mut c = emission + self.trace(cam, &scattered, depth - 1, world, pdf) * attenuation;
+        if rec.mat.has_smooth_lobe(r, &rec) && !cam.light_sampler().is_empty() {
+            c += sample_light(cam, r, &rec, world, &medium_stack::new());
+        }
+        c
+    }
+}
+
+impl integrator for direct_lighting {
+    fn li(
+        &self,
+        cam: &Camera,
+        r: &Ray,
+        world: &Arc<dyn Hittable + Send + Sync>,
+        splats: &mut Vec<splat>,
+    ) -> Vec3 {
+        self.trace(cam, r, cam.max_depth, world, 0.0)
+    }
+}
+
+// Whitted 1980: a shadow ray to every light from each diffuse hit, recursion only
+// through mirrors and glass. Area lights get one sample each, so their shadows are noisy.
+pub struct whitted {}
+
+impl whitted {
+    pub fn new() -> Self {
+        Self {}
+    }
+
+    fn trace(
+        &self,
+        cam: &Camera,
+        r: &Ray,
+        depth: usize,
+        world: &Arc<dyn Hittable + Send + Sync>,
+    ) -> Vec3 {
+        if depth == 0 {
+            return Vec3::zero();
+        }
+        let mut rec = hit_record::new();
+        if !hit(world, r, depth == cam.max_depth, &mut rec) {
+            return escaped(cam, r, 0.0);
+        }
+        let mut c = rec.mat.emitted_at(r, &rec);
+        let mut scattered = Ray::new(Vec3::zero(), Vec3::zero());
+        let mut attenuation = Vec3::zero();
+        let mut pdf = 0.0;
+        if !rec
+            .mat
+            .scatter_with_pdf(r, &rec, &mut attenuation, &mut scattered, &mut pdf)
+        {
+            return c;
+        }
+        scattered.inherit_footprint(r, rec.footprint);
+        if pdf <= 0.0 {
+            c += self.trace(cam, &scattered, depth - 1, world) * attenuation;
+        }
+        if !rec.mat.has_smooth_lobe(r, &rec) {
+            return c;
+        }
+        let lights = cam.light_sampler();
+        for i in 0..lights.len() {
+            let Some(s) = lights.light(i).sample(&rec.p) else {
+                continue;
+            };
+            let shadow = Ray::new_with_time(rec.p, s.direction, r.time());
+            let f = rec.mat.bsdf_cos(r, &rec, &shadow);
+            if f.near_zero() {
+                continue;
+            }
+            c += f
+                * s.radiance
+                * (transmittance(world, &shadow, s.distance, &medium_stack::new()) / s.pdf);
+        }
+        c
+    }
+}
+
+impl integrator for whitted {
+    fn li(
+        &self,
+        cam: &Camera,
+        r: &Ray,
+        world: &Arc<dyn Hittable + Send + Sync>,
+        splats: &mut Vec<splat>,
+    ) -> Vec3 {
+        self.trace(cam, r, cam.max_depth, world)
+    }
+}
+
+// White where the hemisphere above the first hit is open within distance, darker in
+// creases and corners. The background is white.
+pub struct ambient_occlusion {
+    pub samples: usize,
+    pub distance: f64,
+}
+
+impl ambient_occlusion {
+    pub fn new() -> Self {
+        Self {
+            samples: 16,
+            distance: f64::INFINITY,
+        }
+    }
+}
+
+impl integrator for ambient_occlusion {
+    fn li(
+        &self,
+        cam: &Camera,
+        r: &Ray,
+        world: &Arc<dyn Hittable + Send + Sync>,
+        splats: &mut Vec<splat>,
+    ) -> Vec3 {
+        let mut rec = hit_record::new();
+        if !hit(world, r, true, &mut rec) {
+            return Vec3::ones();
+        }
+        let basis = onb::new(rec.normal);
+        let mut open = 0;
+        for _ in 0..self.samples {
+            // cosine weighted, so every open direction counts the same
+            let direction = if rec.mat.is_volumetric() {
+                Vec3::random_unit_vector()
+            } else {
+                let (r1, r2) = (random_double(), random_double());
+                let phi = 2.0 * pi * r1;
+                basis.transform(Vec3::new(
+                    phi.cos() * r2.sqrt(),
+                    phi.sin() * r2.sqrt(),
+                    (1.0 - r2).sqrt(),
+                ))
+            };
+            let mut blocker = hit_record::new();
+            if !world.hit(
+                &Ray::new_with_time(rec.p, direction, r.time()),
+                Interval::new(0.001, self.distance),
+                &mut blocker,
+            ) {
+                open += 1;
+            }
+        }
+        Vec3::ones() * (open as f64 / self.samples.max(1) as f64)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum debug_view {
+    // normal on the side the ray hit, each axis from [-1, 1] to [0, 1]
+    Normals,
+    // texture coordinates as red and green
+    UV,
+    // hit distance, white up close, grey at the distance to lookat, black far away
+    Depth,
+    // a color per material, the same for every object sharing one
+    MaterialId,
+    // bvh nodes a camera ray visits, blue for none through red at the given count;
+    // escaped rays are shown too
+    BvhCost(usize),
+    // green where rays hit the side the normal points out of, red for the back
+    FrontFace,
+}
+
+// Shows a property of the first hit instead of light, black where rays escape.
+pub struct debug {
+    pub view: debug_view,
+}
+
+impl debug {
+    pub fn new(view: debug_view) -> Self {
+        Self { view }
+    }
+}
+
+// blue, green, red for t from 0 to 1
+fn heat(t: f64) -> Vec3 {
+    let t = t.clamp(0.0, 1.0) * 2.0;
+    if t < 1.0 {
+        Vec3::new(0.0, t, 1.0 - t)
+    } else {
+        Vec3::new(t - 1.0, 2.0 - t, 0.0)
+    }
+}
+
+// a bright, evenly spread color for any key (splitmix64's finalizer)
+fn hash_color(key: u64) -> Vec3 {
+    let mut z = key.wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^= z >> 31;
+    let channel = |shift: u64| 0.2 + 0.8 * ((z >> shift) & 0xff) as f64 / 255.0;
+    Vec3::new(channel(0), channel(8), channel(16))
+}
+
+impl integrator for debug {
+    fn li(
+        &self,
+        cam: &Camera,
+        r: &Ray,
+        world: &Arc<dyn Hittable + Send + Sync>,
+        splats: &mut Vec<splat>,
+    ) -> Vec3 {
+        let mut rec = hit_record::new();
+        if let debug_view::BvhCost(max) = self.view {
+            let mut nodes = 0;
+            world.hit_counted(r, Interval::new(0.001, f64::INFINITY), &mut rec, &mut nodes);
+            return heat(nodes as f64 / max.max(1) as f64);
+        }
+        let hit = hit(world, r, true, &mut rec);
+        if !hit {
+            return Vec3::zero();
+        }
+        match self.view {
+            debug_view::Normals => (rec.normal + Vec3::ones()) * 0.5,
+            debug_view::UV => Vec3::new(rec.u, rec.v, 0.0),
+            debug_view::Depth => {
+                let distance = rec.t * r.direction().length();
+                let scale = (cam.lookat - cam.lookfrom).length();
+                Vec3::ones() * (scale / (scale + distance))
+            }
+            // the material's address, which stays put for the whole render
+            debug_view::MaterialId => {
+                hash_color(Arc::as_ptr(&rec.mat) as *const u8 as usize as u64)
+            }
+            debug_view::BvhCost(_) => Vec3::zero(),
+            debug_view::FrontFace => {
+                if rec.front_face {
+                    Vec3::new(0.0, 1.0, 0.0)
+                } else {
+                    Vec3::new(1.0, 0.0, 0.0)
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hittable_list::HittableList;
+    use crate::light::quad_light;
+    use crate::material::{diffuse_light, lambertian};
+    use crate::quad::Quad;
+
+    // a grey box with a panel under its ceiling, from inside
+    fn lit_box() -> (Camera, Arc<dyn Hittable + Send + Sync>) {
+        let mut world = HittableList::new();
+        world.add(Quad::boxx(
+            Vec3::new(-1.0, -1.0, -1.0),
+            Vec3::new(1.0, 1.0, 1.0),
+            Arc::new(lambertian::new(Vec3::new(0.5, 0.5, 0.5))),
+        ));
+        let panel = Arc::new(diffuse_light::new_from_emit_color(Vec3::new(2.0, 2.0, 2.0)));
+        let (lamp, lamp_light) = quad_light::new_with_quad(
+            Vec3::new(-0.5, 0.99, -0.5),
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 1.0),
+            panel,
+        );
+        world.add(lamp);
+        let mut cam = Camera::new();
+        cam.width = 8;
+        cam.height = 8;
+        cam.max_depth = 4;
+        cam.lookfrom = Vec3::new(0.0, 0.0, 0.9);
+        cam.lights.push(Arc::new(lamp_light));
+        cam.initialize();
+        (cam, Arc::new(world))
+    }
+
+    #[test]
+    fn test_shadow_rays_see_cutouts() {
+        use crate::texture::texture;
+        struct left_cut;
+        impl texture for left_cut {
+            fn value(&self, u: f64, v: f64, p: &Vec3) -> Vec3 {
+                Vec3::new(0.5, 0.5, 0.5)
+            }
+            fn alpha(&self, u: f64, v: f64, p: &Vec3) -> f64 {
+                if u < 0.5 {
+                    0.0
+                } else {
+                    1.0
+                }
+            }
+        }
+        // a small lamp over a screen whose half at x < 0 is cut away
+        let mut world = HittableList::new();
+        world.add(Arc::new(Quad::new(
+            Vec3::new(-1.0, 1.0, -1.0),
+            Vec3::new(2.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 2.0),
+            Arc::new(lambertian::new_with_texture(Arc::new(left_cut))),
+        )));
+        let panel = Arc::new(diffuse_light::new_from_emit_color(Vec3::ones()));
+        let (lamp, lamp_light) = quad_light::new_with_quad(
+            Vec3::new(-0.05, 2.0, -0.05),
+            Vec3::new(0.1, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 0.1),
+            panel,
+        );
+        world.add(lamp);
+        let world: Arc<dyn Hittable + Send + Sync> = Arc::new(world);
+        let mut cam = Camera::new();
+        cam.lights.push(Arc::new(lamp_light));
+        cam.initialize();
+        let floor = Arc::new(lambertian::new(Vec3::ones()));
+        let lit = |x: f64| {
+            let p = Vec3::new(x, 0.0, 0.0);
+            let r = Ray::new(p + Vec3::new(0.0, 1.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+            let mut rec = hit_record::new();
+            rec.p = p;
+            rec.set_face_normal(&r, Vec3::new(0.0, 1.0, 0.0));
+            rec.mat = floor.clone();
+            sample_light(&cam, &r, &rec, &world, &medium_stack::new())
+        };
+        // the shadow ray from x = -0.5 crosses the screen near x = -0.25, from 0.5 near 0.25
+        assert!(lit(-0.5).y > 0.0);
+        assert!(lit(0.5).near_zero());
+    }
+
+    #[test]
+    fn test_by_name() {
+        for name in [
+            "path",
+            "direct",
+            "whitted",
+            "ao",
+            "bdpt",
+            "photons",
+            "mlt",
+            "mlt_bdpt",
+            "normals",
+            "uv",
+            "depth",
+            "material",
+            "bvh",
+            "front_face",
+        ] {
+            assert!(by_name(name).is_some(), "{}", name);
+        }
+        assert!(by_name("bidirectional").is_none());
+    }
+
+    #[test]
+    fn test_prepare() {
+        let (cam, world) = lit_box();
+        // ready as they are
+        assert!(path_tracer::new().prepare(&cam, &world).is_none());
+        assert!(by_name("mlt").unwrap().prepare(&cam, &world).is_none());
+        // bdpt needs the camera's lights to start paths from, also inside metropolis
+        let splatted = |bdpt: &Arc<dyn integrator + Send + Sync>| {
+            let mut splats = Vec::new();
+            for k in 0..200 {
+                bdpt.li(&cam, &cam.get_ray(k % 8, k / 8 % 8), &world, &mut splats);
+            }
+            assert!(splats.iter().all(|s| s.0 < cam.width && s.1 < cam.height));
+            splats.len()
+        };
+        assert_eq!(splatted(&by_name("bdpt").unwrap()), 0);
+        assert!(splatted(&by_name("bdpt").unwrap().prepare(&cam, &world).unwrap()) > 0);
+        assert!(splatted(&by_name("mlt_bdpt").unwrap().prepare(&cam, &world).unwrap()) > 0);
+        // only whole-image integrators render themselves
+        assert!(by_name("bdpt").unwrap().render(&cam, &world).is_none());
+    }
+
+    #[test]
+    fn test_unidirectional_integrators_dont_splat() {
+        let (cam, world) = lit_box();
+        for name in ["path", "direct", "whitted", "ao", "normals"] {
+            let mut splats = Vec::new();
+            for k in 0..200 {
+                by_name(name).unwrap().li(
+                    &cam,
+                    &cam.get_ray(k % 8, k / 8 % 8),
+                    &world,
+                    &mut splats,
+                );
+            }
+            assert!(splats.is_empty(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_debug_views() {
+        let (cam, world) = lit_box();
+        let view = |v: debug_view, r: &Ray| debug::new(v).li(&cam, r, &world, &mut Vec::new());
+        let down = Ray::new(Vec3::zero(), Vec3::new(0.0, -1.0, 0.0));
+        let up_from_below = Ray::new(Vec3::new(0.0, -2.0, 0.0), Vec3::new(0.0, 1.0, 0.0));
+        assert!((view(debug_view::Normals, &down) - Vec3::new(0.5, 1.0, 0.5)).near_zero());
+        // the floor seen from either side
+        let (inside, outside) = (
+            view(debug_view::FrontFace, &down),
+            view(debug_view::FrontFace, &up_from_below),
+        );
+        assert!((inside + outside - Vec3::new(1.0, 1.0, 0.0)).near_zero());
+        // the back wall is as far from the camera as lookat
+        let ahead = Ray::new(cam.lookfrom, cam.lookat - cam.lookfrom);
+        assert!((view(debug_view::Depth, &ahead).y - 0.5).abs() < 1e-9);
+        let uv = view(debug_view::UV, &down);
+        assert!((0.0..=1.0).contains(&uv.x) && (0.0..=1.0).contains(&uv.y));
+        // floor and back wall share a material, the panel has its own
+        let panel = Ray::new(Vec3::new(0.1, 0.0, 0.1), Vec3::new(0.0, 1.0, 0.0));
+        assert!(
+            (view(debug_view::MaterialId, &down) - view(debug_view::MaterialId, &ahead))
+                .near_zero()
+        );
+        assert!(
+            !(view(debug_view::MaterialId, &down) - view(debug_view::MaterialId, &panel))
+                .near_zero()
+        );
+        assert!(view(
+            debug_view::Normals,
+            &Ray::new(Vec3::new(0.0, 5.0, 0.0), Vec3::new(0.0, 1.0, 0.0))
+        )
+        .near_zero());
+    }
+
+    #[test]
+    fn test_bvh_cost() {
+        use crate::bvh::bvh_node;
+        use crate::sphere::Sphere;
+        let mut row = HittableList::new();
+        for i in 0..64 {
+            row.add(Arc::new(Sphere::new(
+                Vec3::new(i as f64, 0.0, 0.0),
+                0.4,
+                Arc::new(lambertian::new(Vec3::ones())),
+            )));
+        }
+        let world: Arc<dyn Hittable + Send + Sync> = Arc::new(bvh_node::new(row));
+        let cost = |r: &Ray| {
+            let mut nodes = 0;
+            let mut rec = hit_record::new();
+            let hit =
+                world.hit_counted(r, Interval::new(0.001, f64::INFINITY), &mut rec, &mut nodes);
+            // the same hit as without counting
+            let mut plain = hit_record::new();
+            assert_eq!(
+                hit,
+                world.hit(r, Interval::new(0.001, f64::INFINITY), &mut plain)
+            );
+            assert!(!hit || rec.t == plain.t);
+            nodes
+        };
+        // only the root's box, a path down to one sphere, or all 63 nodes (the lowest
+        // hold two spheres each) for a ray grazing past the spheres inside their boxes
+        assert_eq!(
+            cost(&Ray::new(
+                Vec3::new(0.0, 5.0, 0.0),
+                Vec3::new(0.0, 1.0, 0.0)
+            )),
+            1
+        );
+        let across = cost(&Ray::new(
+            Vec3::new(30.0, 0.0, 5.0),
+            Vec3::new(0.0, 0.0, -1.0),
+        ));
+        let grazing = cost(&Ray::new(
+            Vec3::new(-2.0, 0.3, 0.3),
+            Vec3::new(1.0, 0.0, 0.0),
+        ));
+        assert!(1 < across && across < 20);
+        assert_eq!(grazing, 63);
+        let (cam, _) = lit_box();
+        let heat_of =
+            |r: &Ray| debug::new(debug_view::BvhCost(64)).li(&cam, r, &world, &mut Vec::new());
+        assert!((heat_of(&Ray::new(
+            Vec3::new(0.0, 5.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0)
+        )) - heat(1.0 / 64.0))
+        .near_zero());
+    }
+}
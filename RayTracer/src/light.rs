@@ -0,0 +1,1357 @@
+use crate::aabb::aabb;
+use crate::asset::{asset_error, asset_manager};
+use crate::color::luminance;
+use crate::material::{diffuse_light, emission_side};
+use crate::onb::onb;
+use crate::quad::Quad;
+use crate::raytracer::{pi, random_double};
+use crate::sphere::{sphere_uv, Sphere};
+use crate::texture::{solid_color, texture};
+use crate::vec3::Vec3;
+use std::sync::Arc;
+
+pub struct light_sample {
+    // unit direction from the shaded point toward the light
+    pub direction: Vec3,
+    // along direction, infinity for lights at infinity
+    pub distance: f64,
+    pub radiance: Vec3,
+    // solid angle density of direction, or 1 for delta lights
+    pub pdf: f64,
+    // point and directional lights, which no scattered ray can hit by chance
+    pub delta: bool,
+}
+
+// A point and a direction leaving a light, for tracing paths that start at the light.
+pub struct emission_sample {
+    pub p: Vec3,
+    pub direction: Vec3,
+    // radiance along direction, intensity for point lights
+    pub radiance: Vec3,
+    // normal of the emitting surface at p, zero for point lights
+    pub n: Vec3,
+    // per unit area (1 for point lights) and per solid angle
+    pub pdf_pos: f64,
+    pub pdf_dir: f64,
+    pub delta_position: bool,
+}
+
+// What a many-light sampler needs to know about a light without sampling it: where it
+// is, how much it emits, and the cone of directions it emits into. Emission leaves
+// along directions within theta_o of axis, spread by up to theta_e around them.
+#[derive(Clone, Copy)]
+pub struct light_bounds {
+    pub bounds: aabb,
+    // luminance weighted, only compared between lights
+    pub power: f64,
+    pub axis: Vec3,
+    pub cos_theta_o: f64,
+    pub cos_theta_e: f64,
+}
+
+impl light_bounds {
+    // emits the same in every direction
+    pub fn omni(bounds: aabb, power: f64) -> Self {
+        Self {
+            bounds,
+            power,
+            axis: Vec3::new(0.0, 0.0, 1.0),
+            cos_theta_o: -1.0,
+            cos_theta_e: 0.0,
+        }
+    }
+}
+
+// Something an integrator can sample directly (next event estimation) instead
+// of waiting for a scattered ray to run into it.
+pub trait light {
+    fn sample(&self, p: &Vec3) -> Option<light_sample>;
+    // solid angle density of sample() returning direction from p, 0 for delta lights
+    fn pdf(&self, p: &Vec3, direction: &Vec3) -> f64;
+    // radiance reaching rays that leave the scene along direction, for lights at
+    // infinity such as the sun
+    fn background(&self, direction: &Vec3) -> Vec3 {
+        Vec3::zero()
+    }
+    // None for lights at infinity, which are picked apart from the others
+    fn bounds(&self) -> Option<light_bounds> {
+        None
+    }
+    // None if paths can't start at this light (lights at infinity)
+    fn sample_emission(&self) -> Option<emission_sample> {
+        None
+    }
+    // a copy that emits into a scene within bounds, for lights at infinity that can
+    // aim paths at it once they know where it is
+    fn in_scene(&self, bounds: &aabb) -> Option<Arc<dyn light + Send + Sync>> {
+        None
+    }
+    // (pdf_pos, pdf_dir) of sample_emission() leaving p, a point on the light, along direction
+    fn emission_pdf(&self, p: &Vec3, direction: &Vec3) -> (f64, f64) {
+        (0.0, 0.0)
+    }
+    // surface normal at p, a point on the light, zero for point lights
+    fn normal(&self, p: &Vec3) -> Vec3 {
+        Vec3::zero()
+    }
+}
+
+// cosine weighted direction around n
+fn sample_cosine(n: Vec3) -> Vec3 {
+    let (r1, r2) = (random_double(), random_double());
+    let phi = 2.0 * pi * r1;
+    onb::new(n).transform(Vec3::new(
+        phi.cos() * r2.sqrt(),
+        phi.sin() * r2.sqrt(),
+        (1.0 - r2).sqrt(),
+    ))
+}
+
+// the normal emission leaves along, chosen among the emitting sides, and its probability
+fn pick_side(sides: emission_side, n: Vec3) -> (Vec3, f64) {
+    match sides {
+        emission_side::Front => (n, 1.0),
+        emission_side::Back => (n * -1.0, 1.0),
+        emission_side::Both => (if random_double() < 0.5 { n } else { n * -1.0 }, 0.5),
+    }
+}
+
+// density of sample_cosine() around pick_side(), cosine being against the front normal
+fn side_pdf_dir(sides: emission_side, cosine: f64) -> f64 {
+    match sides {
+        emission_side::Front => cosine.max(0.0) / pi,
+        emission_side::Back => (-cosine).max(0.0) / pi,
+        emission_side::Both => 0.5 * cosine.abs() / pi,
+    }
+}
+
+// whether light leaving a surface with front normal n along -direction comes out of
+// an emitting side
+fn faces(sides: emission_side, n: Vec3, direction: &Vec3) -> bool {
+    match sides {
+        emission_side::Front => n.dot(*direction) < 0.0,
+        emission_side::Back => n.dot(*direction) > 0.0,
+        emission_side::Both => true,
+    }
+}
+
+fn point_emission(
+    p: Vec3,
+    direction: Vec3,
+    radiance: Vec3,
+    pdf_dir: f64,
+) -> Option<emission_sample> {
+    Some(emission_sample {
+        p,
+        direction,
+        radiance,
+        n: Vec3::zero(),
+        pdf_pos: 1.0,
+        pdf_dir,
+        delta_position: true,
+    })
+}
+
+// uniform direction within cos_max of axis
+pub fn sample_cone(axis: Vec3, cos_max: f64) -> Vec3 {
+    let cos_theta = 1.0 - random_double() * (1.0 - cos_max);
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let phi = 2.0 * pi * random_double();
+    onb::new(axis).transform(Vec3::new(
+        phi.cos() * sin_theta,
+        phi.sin() * sin_theta,
+        cos_theta,
+    ))
+}
+
+fn point_bounds(p: Vec3) -> aabb {
+    aabb::new_from_points(p, p)
+}
+
+fn toward(from: &Vec3, to: Vec3) -> (Vec3, f64) {
+    let d = to - *from;
+    let distance = d.length();
+    (d / distance, distance)
+}
+
+// Radiant intensity (power per steradian) from a single point, the same in every direction.
+pub struct point_light {
+    pub position: Vec3,
+    pub intensity: Vec3,
+}
+
+impl point_light {
+    pub fn new(position: Vec3, intensity: Vec3) -> Self {
+        Self {
+            position,
+            intensity,
+        }
+    }
+}
+
+impl light for point_light {
+    fn sample(&self, p: &Vec3) -> Option<light_sample> {
+        let (direction, distance) = toward(p, self.position);
+        if distance <= 0.0 {
+            return None;
+        }
+        Some(light_sample {
+            direction,
+            distance,
+            radiance: self.intensity / (distance * distance),
+            pdf: 1.0,
+            delta: true,
+        })
+    }
+    fn pdf(&self, p: &Vec3, direction: &Vec3) -> f64 {
+        0.0
+    }
+    fn bounds(&self) -> Option<light_bounds> {
+        Some(light_bounds::omni(
+            point_bounds(self.position),
+            4.0 * pi * luminance(self.intensity),
+        ))
+    }
+    fn sample_emission(&self) -> Option<emission_sample> {
+        point_emission(
+            self.position,
+            Vec3::random_unit_vector(),
+            self.intensity,
+            1.0 / (4.0 * pi),
+        )
+    }
+    fn emission_pdf(&self, p: &Vec3, direction: &Vec3) -> (f64, f64) {
+        (1.0, 1.0 / (4.0 * pi))
+    }
+}
+
+// A point light shining along direction, full intensity inside the inner cone and
+// fading smoothly to nothing at the outer cone.
+pub struct spot_light {
+    pub position: Vec3,
+    pub direction: Vec3,
+    pub intensity: Vec3,
+    cos_inner: f64,
+    cos_outer: f64,
+}
+
+impl spot_light {
+    // cone half angles in degrees
+    pub fn new(
+        position: Vec3,
+        direction: Vec3,
+        intensity: Vec3,
+        inner_angle: f64,
+        outer_angle: f64,
+    ) -> Self {
+        Self {
+            position,
+            direction: direction.normalize(),
+            intensity,
+            cos_inner: inner_angle.min(outer_angle).to_radians().cos(),
+            cos_outer: outer_angle.to_radians().cos(),
+        }
+    }
+    fn falloff(&self, cos_theta: f64) -> f64 {
+        if cos_theta >= self.cos_inner {
+            return 1.0;
+        }
+        if cos_theta <= self.cos_outer {
+            return 0.0;
+        }
+        let t = (cos_theta - self.cos_outer) / (self.cos_inner - self.cos_outer);
+        t * t * (3.0 - 2.0 * t)
+    }
+}
+
+impl light for spot_light {
+    fn sample(&self, p: &Vec3) -> Option<light_sample> {
+        let (direction, distance) = toward(p, self.position);
+        let falloff = self.falloff((direction * -1.0).dot(self.direction));
+        if distance <= 0.0 || falloff <= 0.0 {
+            return None;
+        }
+        Some(light_sample {
+            direction,
+            distance,
+            radiance: self.intensity * (falloff / (distance * distance)),
+            pdf: 1.0,
+            delta: true,
+        })
+    }
+    fn pdf(&self, p: &Vec3, direction: &Vec3) -> f64 {
+        0.0
+    }
+    fn bounds(&self) -> Option<light_bounds> {
+        // full intensity out to the inner cone, the falloff spreads it to the outer one
+        let (inner, outer) = (self.cos_inner.acos(), self.cos_outer.acos());
+        let power =
+            2.0 * pi * luminance(self.intensity) * (1.0 - (self.cos_inner + self.cos_outer) / 2.0);
+        Some(light_bounds {
+            bounds: point_bounds(self.position),
+            power,
+            axis: self.direction,
+            cos_theta_o: self.cos_inner,
+            cos_theta_e: (outer - inner).cos(),
+        })
+    }
+    fn sample_emission(&self) -> Option<emission_sample> {
+        let direction = sample_cone(self.direction, self.cos_outer);
+        let falloff = self.falloff(direction.dot(self.direction));
+        point_emission(
+            self.position,
+            direction,
+            self.intensity * falloff,
+            1.0 / (2.0 * pi * (1.0 - self.cos_outer)),
+        )
+    }
+    fn emission_pdf(&self, p: &Vec3, direction: &Vec3) -> (f64, f64) {
+        if direction.normalize().dot(self.direction) < self.cos_outer {
+            return (1.0, 0.0);
+        }
+        (1.0, 1.0 / (2.0 * pi * (1.0 - self.cos_outer)))
+    }
+}
+
+// Parallel light from infinitely far away, given as irradiance on a surface facing it.
+pub struct directional_light {
+    // unit vector toward the light
+    pub direction: Vec3,
+    pub irradiance: Vec3,
+    // center and radius of a sphere around the scene, set by in_scene(). Emission
+    // leaves the disk of that radius facing the scene from the light's side.
+    pub scene: Option<(Vec3, f64)>,
+}
+
+impl directional_light {
+    pub fn new(direction: Vec3, irradiance: Vec3) -> Self {
+        Self {
+            direction: direction.normalize(),
+            irradiance,
+            scene: None,
+        }
+    }
+}
+
+impl light for directional_light {
+    fn sample(&self, p: &Vec3) -> Option<light_sample> {
+        Some(light_sample {
+            direction: self.direction,
+            distance: f64::INFINITY,
+            radiance: self.irradiance,
+            pdf: 1.0,
+            delta: true,
+        })
+    }
+    fn pdf(&self, p: &Vec3, direction: &Vec3) -> f64 {
+        0.0
+    }
+    fn bounds(&self) -> Option<light_bounds> {
+        let (center, radius) = self.scene?;
+        let r = Vec3::new(radius, radius, radius);
+        Some(light_bounds::omni(
+            aabb::new_from_points(center - r, center + r),
+            pi * radius * radius * luminance(self.irradiance),
+        ))
+    }
+    fn sample_emission(&self) -> Option<emission_sample> {
+        let (center, radius) = self.scene?;
+        let d = Vec3::random_in_unit_disk();
+        let frame = onb::new(self.direction);
+        let p = center + (frame.transform(Vec3::new(d.x, d.y, 0.0)) + self.direction) * radius;
+        // the direction is a delta, its density 1 like the pdf of delta light samples
+        Some(emission_sample {
+            p,
+            direction: self.direction * -1.0,
+            radiance: self.irradiance,
+            n: Vec3::zero(),
+            pdf_pos: 1.0 / (pi * radius * radius),
+            pdf_dir: 1.0,
+            delta_position: false,
+        })
+    }
+    fn in_scene(&self, bounds: &aabb) -> Option<Arc<dyn light + Send + Sync>> {
+        let min = Vec3::new(bounds.x.min, bounds.y.min, bounds.z.min);
+        let max = Vec3::new(bounds.x.max, bounds.y.max, bounds.z.max);
+        let scene = Some(((min + max) * 0.5, (max - min).length() * 0.5));
+        Some(Arc::new(Self {
+            scene,
+            ..Self::new(self.direction, self.irradiance)
+        }))
+    }
+}
+
+// Rotationally symmetric candela distribution of a luminaire, by angle from its axis
+// (the 0 degree vertical angle of an IES file, which points down for most fixtures).
+pub struct ies_profile {
+    // degrees, increasing
+    angles: Vec<f64>,
+    // relative to the brightest angle
+    values: Vec<f64>,
+}
+
+impl ies_profile {
+    pub fn new(points: Vec<(f64, f64)>) -> Self {
+        let mut points = points;
+        points.sort_by(|a, b| a.0.total_cmp(&b.0));
+        let max = points.iter().map(|p| p.1).fold(0.0, f64::max);
+        let scale = if max > 0.0 { 1.0 / max } else { 0.0 };
+        Self {
+            angles: points.iter().map(|p| p.0).collect(),
+            values: points.iter().map(|p| p.1 * scale).collect(),
+        }
+    }
+
+    // LM-63 photometric data; horizontal angles are averaged away
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let tilt = text.find("TILT=").ok_or("missing TILT= line")?;
+        let mut lines = text[tilt..].lines();
+        let tilt_line = lines.next().unwrap_or("");
+        if tilt_line.trim() != "TILT=NONE" {
+            return Err(format!("unsupported {}", tilt_line.trim()));
+        }
+        let numbers: Vec<f64> = lines
+            .flat_map(|l| l.split(|c: char| c.is_whitespace() || c == ','))
+            .filter(|s| !s.is_empty())
+            .map(|s| {
+                s.parse::<f64>()
+                    .ok()
+                    .filter(|x| x.is_finite())
+                    .ok_or(format!("bad number {}", s))
+            })
+            .collect::<Result<_, _>>()?;
+        if numbers.len() < 13 {
+            return Err("truncated header".to_string());
+        }
+        let multiplier = numbers[2];
+        let count = |x: f64| {
+            if x >= 1.0 && x.fract() == 0.0 && x <= usize::MAX as f64 {
+                Ok(x as usize)
+            } else {
+                Err(format!("bad angle count {}", x))
+            }
+        };
+        let (n_vertical, n_horizontal) = (count(numbers[3])?, count(numbers[4])?);
+        // the next eight numbers are type, units, dimensions and ballast data
+        let data = &numbers[13..];
+        let needed = n_vertical
+            .checked_mul(n_horizontal)
+            .and_then(|n| n.checked_add(n_vertical + n_horizontal));
+        if needed.is_none_or(|n| data.len() < n) {
+            return Err("truncated candela values".to_string());
+        }
+        let vertical = &data[..n_vertical];
+        let candela = &data[n_vertical + n_horizontal..];
+        let points = (0..n_vertical)
+            .map(|v| {
+                let mean = (0..n_horizontal)
+                    .map(|h| candela[h * n_vertical + v])
+                    .sum::<f64>()
+                    / n_horizontal as f64;
+                (vertical[v], mean * multiplier)
+            })
+            .collect();
+        Ok(Self::new(points))
+    }
+
+    pub fn load(assets: &asset_manager, path: &str) -> Result<Self, asset_error> {
+        let resolved = assets.resolve(path);
+        let text = match std::fs::read_to_string(&resolved) {
+            Ok(t) => t,
+            Err(_) => return Err(asset_error::NotFound(resolved)),
+        };
+        Self::parse(&text).map_err(|e| asset_error::Unreadable(resolved, e))
+    }
+
+    // linear in angle, zero outside the measured range
+    pub fn value(&self, angle: f64) -> f64 {
+        let n = self.angles.len();
+        if n == 0 || angle < self.angles[0] || angle > self.angles[n - 1] {
+            return 0.0;
+        }
+        let i = self
+            .angles
+            .partition_point(|&a| a <= angle)
+            .max(1)
+            .min(n - 1);
+        let (a0, a1) = (self.angles[i - 1], self.angles[i]);
+        if a1 <= a0 {
+            return self.values[i];
+        }
+        let t = (angle - a0) / (a1 - a0);
+        self.values[i - 1] * (1.0 - t) + self.values[i] * t
+    }
+}
+
+// A point light whose intensity follows a measured profile around axis.
+pub struct ies_light {
+    pub position: Vec3,
+    pub axis: Vec3,
+    // intensity at the brightest angle of the profile
+    pub intensity: Vec3,
+    pub profile: ies_profile,
+}
+
+impl ies_light {
+    pub fn new(position: Vec3, axis: Vec3, intensity: Vec3, profile: ies_profile) -> Self {
+        Self {
+            position,
+            axis: axis.normalize(),
+            intensity,
+            profile,
+        }
+    }
+}
+
+impl light for ies_light {
+    fn sample(&self, p: &Vec3) -> Option<light_sample> {
+        let (direction, distance) = toward(p, self.position);
+        let angle = (direction * -1.0)
+            .dot(self.axis)
+            .max(-1.0)
+            .min(1.0)
+            .acos()
+            .to_degrees();
+        let value = self.profile.value(angle);
+        if distance <= 0.0 || value <= 0.0 {
+            return None;
+        }
+        Some(light_sample {
+            direction,
+            distance,
+            radiance: self.intensity * (value / (distance * distance)),
+            pdf: 1.0,
+            delta: true,
+        })
+    }
+    fn pdf(&self, p: &Vec3, direction: &Vec3) -> f64 {
+        0.0
+    }
+    fn bounds(&self) -> Option<light_bounds> {
+        Some(light_bounds::omni(
+            point_bounds(self.position),
+            4.0 * pi * luminance(self.intensity),
+        ))
+    }
+    fn sample_emission(&self) -> Option<emission_sample> {
+        let direction = Vec3::random_unit_vector();
+        let angle = direction
+            .dot(self.axis)
+            .max(-1.0)
+            .min(1.0)
+            .acos()
+            .to_degrees();
+        point_emission(
+            self.position,
+            direction,
+            self.intensity * self.profile.value(angle),
+            1.0 / (4.0 * pi),
+        )
+    }
+    fn emission_pdf(&self, p: &Vec3, direction: &Vec3) -> (f64, f64) {
+        (1.0, 1.0 / (4.0 * pi))
+    }
+}
+
+// Emission of a diffuse_light parallelogram, sampled by area. Register one with the
+// same corner and edges as the emissive Quad; u and v of the texture are the Quad's.
+pub struct quad_light {
+    pub q: Vec3,
+    pub u: Vec3,
+    pub v: Vec3,
+    pub tex: Arc<dyn texture + Send + Sync>,
+    pub strength: f64,
+    pub sides: emission_side,
+    normal: Vec3,
+    area: f64,
+}
+
+impl quad_light {
+    // two sided, like diffuse_light::new_from_emit_color
+    pub fn new(q: Vec3, u: Vec3, v: Vec3, emission: Vec3) -> Self {
+        let n = Vec3::cross(u, v);
+        let area = n.length();
+        Self {
+            q,
+            u,
+            v,
+            tex: Arc::new(solid_color::new(emission)),
+            strength: 1.0,
+            sides: emission_side::Both,
+            normal: n / area,
+            area,
+        }
+    }
+    pub fn new_from_material(q: Vec3, u: Vec3, v: Vec3, mat: &diffuse_light) -> Self {
+        Self {
+            tex: mat.tex.clone(),
+            strength: mat.strength,
+            sides: mat.sides,
+            ..Self::new(q, u, v, Vec3::zero())
+        }
+    }
+    // the emissive Quad and its light from one definition, for world.add and cam.lights
+    pub fn new_with_quad(q: Vec3, u: Vec3, v: Vec3, mat: Arc<diffuse_light>) -> (Arc<Quad>, Self) {
+        let light = Self::new_from_material(q, u, v, &mat);
+        (Arc::new(Quad::new(q, u, v, mat)), light)
+    }
+    // (distance, u, v) along unit direction from p to the quad, if it is hit
+    fn intersect(&self, p: &Vec3, direction: &Vec3) -> Option<(f64, f64, f64)> {
+        let denom = self.normal.dot(*direction);
+        if denom.abs() < 1e-8 {
+            return None;
+        }
+        let t = self.normal.dot(self.q - *p) / denom;
+        if t <= 0.0 {
+            return None;
+        }
+        let planar = *p + *direction * t - self.q;
+        let w = self.normal / self.area;
+        let alpha = w.dot(Vec3::cross(planar, self.v));
+        let beta = w.dot(Vec3::cross(self.u, planar));
+        if !(0.0..=1.0).contains(&alpha) || !(0.0..=1.0).contains(&beta) {
+            return None;
+        }
+        Some((t, alpha, beta))
+    }
+    fn faces(&self, direction: &Vec3) -> bool {
+        faces(self.sides, self.normal, direction)
+    }
+}
+
+impl light for quad_light {
+    fn sample(&self, p: &Vec3) -> Option<light_sample> {
+        let (a, b) = (random_double(), random_double());
+        let x = self.q + self.u * a + self.v * b;
+        let (direction, distance) = toward(p, x);
+        let cosine = self.normal.dot(direction).abs();
+        if distance <= 0.0 || cosine < 1e-8 || !self.faces(&direction) {
+            return None;
+        }
+        let pdf = distance * distance / (cosine * self.area);
+        let radiance = self.tex.value(a, b, &x) * self.strength;
+        Some(light_sample {
+            direction,
+            distance,
+            radiance,
+            pdf,
+            delta: false,
+        })
+    }
+    fn pdf(&self, p: &Vec3, direction: &Vec3) -> f64 {
+        let direction = direction.normalize();
+        if !self.faces(&direction) {
+            return 0.0;
+        }
+        match self.intersect(p, &direction) {
+            Some((t, _, _)) => t * t / (self.normal.dot(direction).abs() * self.area),
+            None => 0.0,
+        }
+    }
+    fn bounds(&self) -> Option<light_bounds> {
+        let bounds = aabb::new_from_aabbs(
+            &aabb::new_from_points(self.q, self.q + self.u + self.v),
+            &aabb::new_from_points(self.q + self.u, self.q + self.v),
+        );
+        // mean luminance of the texture on a coarse grid
+        let n = 8;
+        let mut mean = 0.0;
+        for i in 0..n {
+            for j in 0..n {
+                let (a, b) = ((i as f64 + 0.5) / n as f64, (j as f64 + 0.5) / n as f64);
+                mean += luminance(self.tex.value(a, b, &(self.q + self.u * a + self.v * b)));
+            }
+        }
+        let radiance = mean / (n * n) as f64 * self.strength;
+        let (axis, cos_theta_o, sides) = match self.sides {
+            emission_side::Front => (self.normal, 1.0, 1.0),
+            emission_side::Back => (self.normal * -1.0, 1.0, 1.0),
+            emission_side::Both => (self.normal, -1.0, 2.0),
+        };
+        Some(light_bounds {
+            bounds,
+            power: sides * pi * self.area * radiance,
+            axis,
+            cos_theta_o,
+            cos_theta_e: 0.0,
+        })
+    }
+    fn sample_emission(&self) -> Option<emission_sample> {
+        let (a, b) = (random_double(), random_double());
+        let p = self.q + self.u * a + self.v * b;
+        let (n, side_pdf) = pick_side(self.sides, self.normal);
+        let direction = sample_cosine(n);
+        let cosine = direction.dot(n);
+        if cosine <= 0.0 {
+            return None;
+        }
+        Some(emission_sample {
+            p,
+            direction,
+            radiance: self.tex.value(a, b, &p) * self.strength,
+            n,
+            pdf_pos: 1.0 / self.area,
+            pdf_dir: side_pdf * cosine / pi,
+            delta_position: false,
+        })
+    }
+    fn emission_pdf(&self, p: &Vec3, direction: &Vec3) -> (f64, f64) {
+        (
+            1.0 / self.area,
+            side_pdf_dir(self.sides, self.normal.dot(direction.normalize())),
+        )
+    }
+    fn normal(&self, p: &Vec3) -> Vec3 {
+        self.normal
+    }
+}
+
+// Emission of a diffuse_light Triangle, sampled by area, with the Triangle's corners
+// and vertex uvs.
+pub struct triangle_light {
+    pub q: Vec3,
+    pub u: Vec3,
+    pub v: Vec3,
+    pub uv: [(f64, f64); 3],
+    pub tex: Arc<dyn texture + Send + Sync>,
+    pub strength: f64,
+    pub sides: emission_side,
+    normal: Vec3,
+    area: f64,
+}
+
+impl triangle_light {
+    pub fn new_from_material(
+        a: Vec3,
+        b: Vec3,
+        c: Vec3,
+        uv: [(f64, f64); 3],
+        mat: &diffuse_light,
+    ) -> Self {
+        let (u, v) = (b - a, c - a);
+        let n = Vec3::cross(u, v);
+        let length = n.length();
+        Self {
+            q: a,
+            u,
+            v,
+            uv,
+            tex: mat.tex.clone(),
+            strength: mat.strength,
+            sides: mat.sides,
+            normal: n / length,
+            area: 0.5 * length,
+        }
+    }
+    // the point with barycentric weights alpha on b and beta on c, and its radiance
+    fn at(&self, alpha: f64, beta: f64) -> (Vec3, Vec3) {
+        let gamma = 1.0 - alpha - beta;
+        let tu = gamma * self.uv[0].0 + alpha * self.uv[1].0 + beta * self.uv[2].0;
+        let tv = gamma * self.uv[0].1 + alpha * self.uv[1].1 + beta * self.uv[2].1;
+        let x = self.q + self.u * alpha + self.v * beta;
+        (x, self.tex.value(tu, tv, &x) * self.strength)
+    }
+    // uniform over the triangle for uniform r1, r2
+    fn uniform(r1: f64, r2: f64) -> (f64, f64) {
+        let s = r1.sqrt();
+        (s * (1.0 - r2), s * r2)
+    }
+    // distance along unit direction from p to the triangle, if it is hit
+    fn intersect(&self, p: &Vec3, direction: &Vec3) -> Option<f64> {
+        let denom = self.normal.dot(*direction);
+        if denom.abs() < 1e-8 {
+            return None;
+        }
+        let t = self.normal.dot(self.q - *p) / denom;
+        if t <= 0.0 {
+            return None;
+        }
+        let planar = *p + *direction * t - self.q;
+        let w = self.normal / (2.0 * self.area);
+        let alpha = w.dot(Vec3::cross(planar, self.v));
+        let beta = w.dot(Vec3::cross(self.u, planar));
+        if alpha < 0.0 || beta < 0.0 || alpha + beta > 1.0 {
+            return None;
+        }
+        Some(t)
+    }
+}
+
+impl light for triangle_light {
+    fn sample(&self, p: &Vec3) -> Option<light_sample> {
+        let (alpha, beta) = Self::uniform(random_double(), random_double());
+        let (x, radiance) = self.at(alpha, beta);
+        let (direction, distance) = toward(p, x);
+        let cosine = self.normal.dot(direction).abs();
+        if distance <= 0.0 || cosine < 1e-8 || !faces(self.sides, self.normal, &direction) {
+            return None;
+        }
+        Some(light_sample {
+            direction,
+            distance,
+            radiance,
+            pdf: distance * distance / (cosine * self.area),
+            delta: false,
+        })
+    }
+    fn pdf(&self, p: &Vec3, direction: &Vec3) -> f64 {
+        let direction = direction.normalize();
+        if !faces(self.sides, self.normal, &direction) {
+            return 0.0;
+        }
+        match self.intersect(p, &direction) {
+            Some(t) => t * t / (self.normal.dot(direction).abs() * self.area),
+            None => 0.0,
+        }
+    }
+    fn bounds(&self) -> Option<light_bounds> {
+        let bounds = aabb::new_from_aabbs(
+            &aabb::new_from_points(self.q, self.q + self.u),
+            &aabb::new_from_points(self.q, self.q + self.v),
+        );
+        // mean luminance of the texture on a coarse grid
+        let n = 8;
+        let mut mean = 0.0;
+        for i in 0..n {
+            for j in 0..n {
+                let (alpha, beta) =
+                    Self::uniform((i as f64 + 0.5) / n as f64, (j as f64 + 0.5) / n as f64);
+                mean += luminance(self.at(alpha, beta).1);
+            }
+        }
+        let radiance = mean / (n * n) as f64;
+        let (axis, cos_theta_o, sides) = match self.sides {
+            emission_side::Front => (self.normal, 1.0, 1.0),
+            emission_side::Back => (self.normal * -1.0, 1.0, 1.0),
+            emission_side::Both => (self.normal, -1.0, 2.0),
+        };
+        Some(light_bounds {
+            bounds,
+            power: sides * pi * self.area * radiance,
+            axis,
+            cos_theta_o,
+            cos_theta_e: 0.0,
+        })
+    }
+    fn sample_emission(&self) -> Option<emission_sample> {
+        let (alpha, beta) = Self::uniform(random_double(), random_double());
+        let (p, radiance) = self.at(alpha, beta);
+        let (n, side_pdf) = pick_side(self.sides, self.normal);
+        let direction = sample_cosine(n);
+        let cosine = direction.dot(n);
+        if cosine <= 0.0 {
+            return None;
+        }
+        Some(emission_sample {
+            p,
+            direction,
+            radiance,
+            n,
+            pdf_pos: 1.0 / self.area,
+            pdf_dir: side_pdf * cosine / pi,
+            delta_position: false,
+        })
+    }
+    fn emission_pdf(&self, p: &Vec3, direction: &Vec3) -> (f64, f64) {
+        (
+            1.0 / self.area,
+            side_pdf_dir(self.sides, self.normal.dot(direction.normalize())),
+        )
+    }
+    fn normal(&self, p: &Vec3) -> Vec3 {
+        self.normal
+    }
+}
+
+// A light inside a translate or rotate_y, seen from world space: rotated about y by
+// the angle with sin_theta and cos_theta, then moved by offset. Nested instances
+// nest these.
+pub struct instanced_light {
+    pub light: Arc<dyn light + Send + Sync>,
+    pub offset: Vec3,
+    pub sin_theta: f64,
+    pub cos_theta: f64,
+}
+
+impl instanced_light {
+    pub fn new_translated(light: Arc<dyn light + Send + Sync>, offset: Vec3) -> Self {
+        Self {
+            light,
+            offset,
+            sin_theta: 0.0,
+            cos_theta: 1.0,
+        }
+    }
+    pub fn new_rotated(
+        light: Arc<dyn light + Send + Sync>,
+        sin_theta: f64,
+        cos_theta: f64,
+    ) -> Self {
+        Self {
+            light,
+            offset: Vec3::zero(),
+            sin_theta,
+            cos_theta,
+        }
+    }
+    fn to_local_dir(&self, d: &Vec3) -> Vec3 {
+        Vec3::new(
+            self.cos_theta * d.x - self.sin_theta * d.z,
+            d.y,
+            self.sin_theta * d.x + self.cos_theta * d.z,
+        )
+    }
+    fn to_world_dir(&self, d: &Vec3) -> Vec3 {
+        Vec3::new(
+            self.cos_theta * d.x + self.sin_theta * d.z,
+            d.y,
+            -self.sin_theta * d.x + self.cos_theta * d.z,
+        )
+    }
+    fn to_local(&self, p: &Vec3) -> Vec3 {
+        self.to_local_dir(&(*p - self.offset))
+    }
+    fn to_world(&self, p: &Vec3) -> Vec3 {
+        self.to_world_dir(p) + self.offset
+    }
+}
+
+impl light for instanced_light {
+    fn sample(&self, p: &Vec3) -> Option<light_sample> {
+        let s = self.light.sample(&self.to_local(p))?;
+        Some(light_sample {
+            direction: self.to_world_dir(&s.direction),
+            ..s
+        })
+    }
+    fn pdf(&self, p: &Vec3, direction: &Vec3) -> f64 {
+        self.light
+            .pdf(&self.to_local(p), &self.to_local_dir(direction))
+    }
+    fn background(&self, direction: &Vec3) -> Vec3 {
+        self.light.background(&self.to_local_dir(direction))
+    }
+    fn bounds(&self) -> Option<light_bounds> {
+        let b = self.light.bounds()?;
+        // the box around the moved corners
+        let mut bounds = aabb::empty;
+        for i in 0..8 {
+            let x = if i & 1 == 0 {
+                b.bounds.x.min
+            } else {
+                b.bounds.x.max
+            };
+            let y = if i & 2 == 0 {
+                b.bounds.y.min
+            } else {
+                b.bounds.y.max
+            };
+            let z = if i & 4 == 0 {
+                b.bounds.z.min
+            } else {
+                b.bounds.z.max
+            };
+            let corner = self.to_world(&Vec3::new(x, y, z));
+            bounds = aabb::new_from_aabbs(&bounds, &aabb::new_from_points(corner, corner));
+        }
+        Some(light_bounds {
+            bounds,
+            axis: self.to_world_dir(&b.axis),
+            ..b
+        })
+    }
+    fn sample_emission(&self) -> Option<emission_sample> {
+        let e = self.light.sample_emission()?;
+        Some(emission_sample {
+            p: self.to_world(&e.p),
+            direction: self.to_world_dir(&e.direction),
+            n: self.to_world_dir(&e.n),
+            ..e
+        })
+    }
+    fn emission_pdf(&self, p: &Vec3, direction: &Vec3) -> (f64, f64) {
+        self.light
+            .emission_pdf(&self.to_local(p), &self.to_local_dir(direction))
+    }
+    fn normal(&self, p: &Vec3) -> Vec3 {
+        self.to_world_dir(&self.light.normal(&self.to_local(p)))
+    }
+}
+
+// Emission of a diffuse_light sphere, sampled over the cone it subtends. Register one
+// with the same center and radius as the emissive Sphere; u and v of the texture are
+// the Sphere's.
+pub struct sphere_light {
+    pub center: Vec3,
+    pub radius: f64,
+    pub tex: Arc<dyn texture + Send + Sync>,
+    pub strength: f64,
+    pub sides: emission_side,
+}
+
+impl sphere_light {
+    // two sided, like diffuse_light::new_from_emit_color
+    pub fn new(center: Vec3, radius: f64, emission: Vec3) -> Self {
+        Self {
+            center,
+            radius,
+            tex: Arc::new(solid_color::new(emission)),
+            strength: 1.0,
+            sides: emission_side::Both,
+        }
+    }
+    pub fn new_from_material(center: Vec3, radius: f64, mat: &diffuse_light) -> Self {
+        Self {
+            tex: mat.tex.clone(),
+            strength: mat.strength,
+            sides: mat.sides,
+            ..Self::new(center, radius, Vec3::zero())
+        }
+    }
+    // the glowing Sphere and its light, like quad_light::new_with_quad
+    pub fn new_with_sphere(
+        center: Vec3,
+        radius: f64,
+        mat: Arc<diffuse_light>,
+    ) -> (Arc<Sphere>, Self) {
+        let light = Self::new_from_material(center, radius, &mat);
+        (Arc::new(Sphere::new(center, radius, mat)), light)
+    }
+    // cosine of the cone subtended from p, None inside the sphere
+    fn cos_max(&self, p: &Vec3) -> Option<f64> {
+        let d2 = (self.center - *p).squared_length();
+        let r2 = self.radius * self.radius;
+        if d2 <= r2 {
+            return None;
+        }
+        Some((1.0 - r2 / d2).sqrt())
+    }
+    fn cone_pdf(cos_max: f64) -> f64 {
+        1.0 / (2.0 * pi * (1.0 - cos_max))
+    }
+    // emitted radiance at x on the surface
+    fn radiance(&self, x: &Vec3) -> Vec3 {
+        let (u, v) = sphere_uv((*x - self.center) / self.radius);
+        self.tex.value(u, v, x) * self.strength
+    }
+    // from outside only the outer side shows
+    fn lit_outside(&self) -> bool {
+        self.sides != emission_side::Back
+    }
+}
+
+impl light for sphere_light {
+    fn sample(&self, p: &Vec3) -> Option<light_sample> {
+        let cos_max = self.cos_max(p)?;
+        if !self.lit_outside() {
+            return None;
+        }
+        let oc = self.center - *p;
+        let direction = sample_cone(oc.normalize(), cos_max);
+        // nearest intersection, the shadow ray must stop there
+        let b = direction.dot(oc);
+        let disc = (b * b - oc.squared_length() + self.radius * self.radius).max(0.0);
+        let distance = b - disc.sqrt();
+        let radiance = self.radiance(&(*p + direction * distance));
+        Some(light_sample {
+            direction,
+            distance,
+            radiance,
+            pdf: Self::cone_pdf(cos_max),
+            delta: false,
+        })
+    }
+    fn pdf(&self, p: &Vec3, direction: &Vec3) -> f64 {
+        let Some(cos_max) = self.cos_max(p) else {
+            return 0.0;
+        };
+        let oc = self.center - *p;
+        if !self.lit_outside() || direction.normalize().dot(oc.normalize()) < cos_max {
+            return 0.0;
+        }
+        Self::cone_pdf(cos_max)
+    }
+    fn bounds(&self) -> Option<light_bounds> {
+        let r = Vec3::new(self.radius, self.radius, self.radius);
+        let area = 4.0 * pi * self.radius * self.radius;
+        // mean luminance of the texture on a coarse grid of directions
+        let n = 8;
+        let mut mean = 0.0;
+        for i in 0..n {
+            for j in 0..n {
+                let phi = 2.0 * pi * (i as f64 + 0.5) / n as f64;
+                let y = 2.0 * (j as f64 + 0.5) / n as f64 - 1.0;
+                let s = (1.0 - y * y).sqrt();
+                mean += luminance(self.radiance(
+                    &(self.center + Vec3::new(s * phi.cos(), y, s * phi.sin()) * self.radius),
+                ));
+            }
+        }
+        let sides = if self.sides == emission_side::Both {
+            2.0
+        } else {
+            1.0
+        };
+        let power = sides * pi * area * mean / (n * n) as f64;
+        Some(light_bounds::omni(
+            aabb::new_from_points(self.center - r, self.center + r),
+            power,
+        ))
+    }
+    fn sample_emission(&self) -> Option<emission_sample> {
+        let outward = Vec3::random_unit_vector();
+        let (n, side_pdf) = pick_side(self.sides, outward);
+        let direction = sample_cosine(n);
+        let cosine = direction.dot(n);
+        if cosine <= 0.0 {
+            return None;
+        }
+        let p = self.center + outward * self.radius;
+        Some(emission_sample {
+            p,
+            direction,
+            radiance: self.radiance(&p),
+            n,
+            pdf_pos: 1.0 / (4.0 * pi * self.radius * self.radius),
+            pdf_dir: side_pdf * cosine / pi,
+            delta_position: false,
+        })
+    }
+    fn emission_pdf(&self, p: &Vec3, direction: &Vec3) -> (f64, f64) {
+        let n = (*p - self.center).normalize();
+        (
+            1.0 / (4.0 * pi * self.radius * self.radius),
+            side_pdf_dir(self.sides, n.dot(direction.normalize())),
+        )
+    }
+    fn normal(&self, p: &Vec3) -> Vec3 {
+        (*p - self.center).normalize()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_spot_cone() {
+        let spot = spot_light::new(
+            Vec3::new(0.0, 2.0, 0.0),
+            Vec3::new(0.0, -1.0, 0.0),
+            Vec3::ones(),
+            20.0,
+            30.0,
+        );
+        let below = spot.sample(&Vec3::zero()).unwrap();
+        assert!((below.radiance.x - 0.25).abs() < 1e-12 && below.delta);
+        // 25 degrees off axis is in the falloff, 35 is outside
+        let half = spot
+            .sample(&Vec3::new(2.0 * 25f64.to_radians().tan(), 0.0, 0.0))
+            .unwrap();
+        assert!(half.radiance.x > 0.0 && half.radiance.x < 0.25);
+        assert!(spot
+            .sample(&Vec3::new(2.0 * 35f64.to_radians().tan(), 0.0, 0.0))
+            .is_none());
+    }
+
+    #[test]
+    fn test_parse_ies() {
+        let text = "IESNA:LM-63-2002\n[TEST] downlight\nTILT=NONE\n1 1000 2 3 2 1 2 0 0 0\n1 1 50\n0 45 90\n0 90\n100 50 0\n300 150, 0\n";
+        let profile = ies_profile::parse(text).unwrap();
+        assert!((profile.value(0.0) - 1.0).abs() < 1e-12);
+        assert!((profile.value(22.5) - 0.75).abs() < 1e-12);
+        assert_eq!(profile.value(120.0), 0.0);
+        assert!(ies_profile::parse("TILT=NONE\n1 2 3").is_err());
+    }
+
+    #[test]
+    fn test_parse_ies_bad_counts() {
+        let header = |v: &str, h: &str| {
+            format!(
+                "TILT=NONE\n1 1000 1 {} {} 1 2 0 0 0\n1 1 50\n0 90\n0\n100 0\n",
+                v, h
+            )
+        };
+        assert!(ies_profile::parse(&header("2", "1")).is_ok());
+        // more values claimed than given, counts that would overflow, and non-counts
+        for (v, h) in [
+            ("3", "1"),
+            ("1e19", "1e19"),
+            ("-2", "1"),
+            ("2.5", "1"),
+            ("0", "1"),
+        ] {
+            assert!(ies_profile::parse(&header(v, h)).is_err(), "{} {}", v, h);
+        }
+        assert!(ies_profile::parse(
+            "TILT=NONE\n1 1000 1 2 1 1 2 0 0 0\n1 1 50\nnan 90\n0\n100 0\n"
+        )
+        .is_err());
+    }
+
+    fn ceiling(sides: emission_side) -> quad_light {
+        // u x v points down
+        let mat =
+            diffuse_light::new_with_strength(Arc::new(solid_color::new(Vec3::ones())), 2.0, sides);
+        quad_light::new_from_material(
+            Vec3::new(-1.0, 1.0, -1.0),
+            Vec3::new(2.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 2.0),
+            &mat,
+        )
+    }
+
+    #[test]
+    fn test_quad_faces() {
+        let (up, down) = (Vec3::new(0.0, 1.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+        assert!(
+            ceiling(emission_side::Front).faces(&up) && !ceiling(emission_side::Front).faces(&down)
+        );
+        assert!(
+            !ceiling(emission_side::Back).faces(&up) && ceiling(emission_side::Back).faces(&down)
+        );
+        assert!(
+            ceiling(emission_side::Both).faces(&up) && ceiling(emission_side::Both).faces(&down)
+        );
+    }
+
+    #[test]
+    fn test_one_sided_quad_pdf_and_bounds() {
+        let light = ceiling(emission_side::Front);
+        let (below, above) = (Vec3::zero(), Vec3::new(0.0, 2.0, 0.0));
+        // straight up from one below the center: distance 1, cosine 1, area 4
+        assert!((light.pdf(&below, &Vec3::new(0.0, 1.0, 0.0)) - 0.25).abs() < 1e-12);
+        assert!(light.sample(&below).is_some());
+        assert_eq!(light.pdf(&above, &Vec3::new(0.0, -1.0, 0.0)), 0.0);
+        assert!(light.sample(&above).is_none());
+        let bounds = light.bounds().unwrap();
+        assert!((bounds.axis - Vec3::new(0.0, -1.0, 0.0)).near_zero() && bounds.cos_theta_o == 1.0);
+        assert!((bounds.power - 8.0 * pi).abs() < 1e-9);
+        let both = ceiling(emission_side::Both).bounds().unwrap();
+        assert!(both.cos_theta_o == -1.0 && (both.power - 16.0 * pi).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_sphere_light_matches_material() {
+        use crate::hittable::{hit_record, Hittable};
+        use crate::interval::Interval;
+        use crate::ray::Ray;
+        use crate::texture::uv_checker_texture;
+        let tex = Arc::new(uv_checker_texture::new_from_colors(
+            4.0,
+            2.0,
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 1.0),
+        ));
+        let (globe, light) = sphere_light::new_with_sphere(
+            Vec3::zero(),
+            1.0,
+            Arc::new(diffuse_light::new_with_strength(
+                tex.clone(),
+                3.0,
+                emission_side::Front,
+            )),
+        );
+        let p = Vec3::new(0.5, 0.3, 4.0);
+        for _ in 0..50 {
+            // light sampling sees what a ray hitting the sphere would
+            let s = light.sample(&p).unwrap();
+            let r = Ray::new(p, s.direction);
+            let mut rec = hit_record::new();
+            assert!(globe.hit(&r, Interval::new(0.001, f64::INFINITY), &mut rec));
+            assert!((rec.t * s.direction.length() - s.distance).abs() < 1e-6);
+            assert!((rec.mat.emitted_at(&r, &rec) - s.radiance).near_zero());
+        }
+        // glowing only inside, it lights nothing outside
+        let inward = sphere_light::new_from_material(
+            Vec3::zero(),
+            1.0,
+            &diffuse_light::new_with_strength(tex, 3.0, emission_side::Back),
+        );
+        assert!(inward.sample(&p).is_none() && inward.pdf(&p, &(p * -1.0)) == 0.0);
+    }
+
+    #[test]
+    fn test_triangle_light_matches_material() {
+        use crate::hittable::{hit_record, Hittable};
+        use crate::interval::Interval;
+        use crate::ray::Ray;
+        use crate::texture::uv_checker_texture;
+        use crate::triangle::Triangle;
+        let tex = Arc::new(uv_checker_texture::new_from_colors(
+            4.0,
+            4.0,
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 1.0),
+        ));
+        let mat = Arc::new(diffuse_light::new_with_strength(
+            tex,
+            3.0,
+            emission_side::Both,
+        ));
+        let tri = Triangle::new_with_uv(
+            Vec3::new(-1.0, 1.0, 0.0),
+            Vec3::new(1.0, 1.0, 0.0),
+            Vec3::new(0.0, 1.0, 1.0),
+            [(0.0, 0.0), (1.0, 0.0), (0.5, 1.0)],
+            mat,
+        );
+        let mut lights = Vec::new();
+        tri.emitters(&mut lights);
+        let light = &lights[0];
+        let p = Vec3::new(0.2, -0.5, 0.3);
+        for _ in 0..50 {
+            let s = light.sample(&p).unwrap();
+            let r = Ray::new(p, s.direction);
+            let mut rec = hit_record::new();
+            assert!(tri.hit(&r, Interval::new(0.001, f64::INFINITY), &mut rec));
+            assert!((rec.t * s.direction.length() - s.distance).abs() < 1e-6);
+            assert!((rec.mat.emitted_at(&r, &rec) - s.radiance).near_zero());
+            assert!((light.pdf(&p, &s.direction) - s.pdf).abs() < 1e-9 * s.pdf);
+        }
+        // the solid angle density integrates to one
+        let n = 200000;
+        let sum: f64 = (0..n)
+            .map(|_| light.pdf(&p, &Vec3::random_unit_vector()))
+            .sum();
+        assert!((sum / n as f64 * 4.0 * pi - 1.0).abs() < 0.05);
+    }
+
+    #[test]
+    fn test_instanced_light_matches_moved_quad() {
+        use crate::hittable::{rotate_y, translate, Hittable};
+        let mat = Arc::new(diffuse_light::new_with_strength(
+            Arc::new(solid_color::new(Vec3::ones())),
+            2.0,
+            emission_side::Front,
+        ));
+        let (q, u, v) = (
+            Vec3::new(0.0, 1.0, 0.0),
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 1.0),
+        );
+        let (quad, _) = quad_light::new_with_quad(q, u, v, mat.clone());
+        let offset = Vec3::new(3.0, 0.5, -2.0);
+        let moved = translate::new(Arc::new(rotate_y::new(quad, 30.0)), offset);
+        let mut lights = Vec::new();
+        moved.emitters(&mut lights);
+        assert_eq!(lights.len(), 1);
+        // the same quad built where the instance puts it
+        let (sin, cos) = (30f64.to_radians().sin(), 30f64.to_radians().cos());
+        let turn = |d: Vec3| Vec3::new(cos * d.x + sin * d.z, d.y, -sin * d.x + cos * d.z);
+        let direct = quad_light::new_from_material(turn(q) + offset, turn(u), turn(v), &mat);
+        let p = Vec3::new(3.2, -1.0, -1.6);
+        for _ in 0..50 {
+            let s = lights[0].sample(&p).unwrap();
+            assert!((direct.pdf(&p, &s.direction) - s.pdf).abs() < 1e-9 * s.pdf);
+            let e = lights[0].sample_emission().unwrap();
+            assert!((direct.emission_pdf(&e.p, &e.direction).1 - e.pdf_dir).abs() < 1e-9);
+            assert!((e.n - direct.normal(&e.p)).near_zero());
+        }
+        let (a, b) = (lights[0].bounds().unwrap(), direct.bounds().unwrap());
+        assert!((a.axis - b.axis).near_zero() && (a.power - b.power).abs() < 1e-9);
+        // the box of a rotated box may be looser, but never tighter
+        assert!(a.bounds.x.min <= b.bounds.x.min + 1e-9 && a.bounds.x.max >= b.bounds.x.max - 1e-9);
+        assert!(a.bounds.z.min <= b.bounds.z.min + 1e-9 && a.bounds.z.max >= b.bounds.z.max - 1e-9);
+    }
+}
@@ -0,0 +1,474 @@
+use crate::aabb::aabb;
+use crate::light::{light, light_bounds};
+use crate::raytracer::{pi, random_double};
+use crate::vec3::Vec3;
+use std::sync::Arc;
+
+// How Camera picks the one light a shading point sends its shadow ray to.
+#[derive(Clone, Copy, PartialEq)]
+pub enum light_sampling {
+    Uniform,
+    // in proportion to emitted power, the same everywhere
+    Power,
+    // down a light BVH, by the importance of each subtree seen from the shading point
+    Tree,
+}
+
+// Walker's alias method: O(1) draws from a fixed discrete distribution.
+pub struct alias_table {
+    prob: Vec<f64>,
+    alias: Vec<usize>,
+    pmf: Vec<f64>,
+}
+
+impl alias_table {
+    pub fn new(weights: &[f64]) -> Self {
+        let n = weights.len();
+        let total: f64 = weights.iter().sum();
+        let pmf: Vec<f64> = if total > 0.0 {
+            weights.iter().map(|w| w / total).collect()
+        } else {
+            vec![1.0 / n as f64; n]
+        };
+        let mut prob = vec![1.0; n];
+        let mut alias: Vec<usize> = (0..n).collect();
+        let mut scaled: Vec<f64> = pmf.iter().map(|p| p * n as f64).collect();
+        let mut small: Vec<usize> = (0..n).filter(|&i| scaled[i] < 1.0).collect();
+        let mut large: Vec<usize> = (0..n).filter(|&i| scaled[i] >= 1.0).collect();
+        while let (Some(&s), Some(&l)) = (small.last(), large.last()) {
+            small.pop();
+            prob[s] = scaled[s];
+            alias[s] = l;
+            scaled[l] -= 1.0 - scaled[s];
+            if scaled[l] < 1.0 {
+                large.pop();
+                small.push(l);
+            }
+        }
+        // whatever is left is 1 up to rounding
+        Self { prob, alias, pmf }
+    }
+    pub fn len(&self) -> usize {
+        self.pmf.len()
+    }
+    pub fn sample(&self) -> usize {
+        let n = self.len();
+        let x = random_double() * n as f64;
+        let i = (x as usize).min(n - 1);
+        if x - (i as f64) < self.prob[i] {
+            i
+        } else {
+            self.alias[i]
+        }
+    }
+    pub fn pmf(&self, i: usize) -> f64 {
+        self.pmf[i]
+    }
+}
+
+fn union_bounds(a: &light_bounds, b: &light_bounds) -> light_bounds {
+    if a.power <= 0.0 {
+        return *b;
+    }
+    if b.power <= 0.0 {
+        return *a;
+    }
+    let (axis, cos_theta_o) = union_cone(a.axis, a.cos_theta_o, b.axis, b.cos_theta_o);
+    light_bounds {
+        bounds: aabb::new_from_aabbs(&a.bounds, &b.bounds),
+        power: a.power + b.power,
+        axis,
+        cos_theta_o,
+        cos_theta_e: a.cos_theta_e.min(b.cos_theta_e),
+    }
+}
+
+// smallest cone (axis, cosine of half angle) holding both cones
+fn union_cone(a: Vec3, cos_a: f64, b: Vec3, cos_b: f64) -> (Vec3, f64) {
+    let theta_a = cos_a.max(-1.0).min(1.0).acos();
+    let theta_b = cos_b.max(-1.0).min(1.0).acos();
+    let theta_d = a.dot(b).max(-1.0).min(1.0).acos();
+    if (theta_d + theta_b).min(pi) <= theta_a {
+        return (a, cos_a);
+    }
+    if (theta_d + theta_a).min(pi) <= theta_b {
+        return (b, cos_b);
+    }
+    let theta_o = (theta_a + theta_d + theta_b) / 2.0;
+    let k = Vec3::cross(a, b);
+    if theta_o >= pi || k.squared_length() == 0.0 {
+        return (a, -1.0);
+    }
+    // rotate a toward b by theta_o - theta_a (rodrigues, k is perpendicular to a)
+    let k = k.normalize();
+    let theta_r = theta_o - theta_a;
+    let axis = a * theta_r.cos() + Vec3::cross(k, a) * theta_r.sin();
+    (axis.normalize(), theta_o.cos())
+}
+
+fn centroid(b: &aabb) -> Vec3 {
+    Vec3::new(
+        (b.x.min + b.x.max) / 2.0,
+        (b.y.min + b.y.max) / 2.0,
+        (b.z.min + b.z.max) / 2.0,
+    )
+}
+
+pub fn contains(b: &aabb, p: &Vec3) -> bool {
+    let eps = 1e-4;
+    b.x.expand(eps).contains(p.x) && b.y.expand(eps).contains(p.y) && b.z.expand(eps).contains(p.z)
+}
+
+// cos(max(0, theta_a - theta_b)) and its sine from both angles' sines and cosines
+fn cos_sub_clamped(sin_a: f64, cos_a: f64, sin_b: f64, cos_b: f64) -> f64 {
+    if cos_a > cos_b {
+        1.0
+    } else {
+        cos_a * cos_b + sin_a * sin_b
+    }
+}
+fn sin_sub_clamped(sin_a: f64, cos_a: f64, sin_b: f64, cos_b: f64) -> f64 {
+    if cos_a > cos_b {
+        0.0
+    } else {
+        sin_a * cos_b - cos_a * sin_b
+    }
+}
+
+// Conservative estimate of how much a group of lights can contribute at p: power over
+// squared distance, times the best emission cosine any of them could have toward p.
+fn importance(b: &light_bounds, p: &Vec3) -> f64 {
+    if b.power <= 0.0 {
+        return 0.0;
+    }
+    let pc = centroid(&b.bounds);
+    let diagonal = Vec3::new(b.bounds.x.size(), b.bounds.y.size(), b.bounds.z.size());
+    let radius2 = diagonal.squared_length() / 4.0;
+    let offset = *p - pc;
+    let d2 = offset.squared_length().max(radius2);
+    if offset.squared_length() <= radius2 {
+        // p is in or next to the bounds, any direction is possible
+        return b.power / d2;
+    }
+    let wi = offset.normalize();
+    let cos_w = b.axis.dot(wi);
+    let sin_w = (1.0 - cos_w * cos_w).max(0.0).sqrt();
+    let sin_o = (1.0 - b.cos_theta_o * b.cos_theta_o).max(0.0).sqrt();
+    // angle subtended by the bounding sphere
+    let sin_b2 = radius2 / offset.squared_length();
+    let (sin_b, cos_b) = (sin_b2.sqrt(), (1.0 - sin_b2).max(0.0).sqrt());
+    let cos_x = cos_sub_clamped(sin_w, cos_w, sin_o, b.cos_theta_o);
+    let sin_x = sin_sub_clamped(sin_w, cos_w, sin_o, b.cos_theta_o);
+    let cos_p = cos_sub_clamped(sin_x, cos_x, sin_b, cos_b);
+    if cos_p <= b.cos_theta_e {
+        return 0.0;
+    }
+    b.power * cos_p / d2
+}
+
+struct light_node {
+    bounds: light_bounds,
+    // children, or the light of a leaf
+    left: usize,
+    right: usize,
+    light: usize,
+    leaf: bool,
+}
+
+// Chooses a light for next event estimation and reports the probability of any
+// choice, which multiple importance sampling needs for lights hit by scattered rays.
+// Lights at infinity get a fixed share, the rest go through an alias table or a BVH
+// over their bounds depending on the mode.
+pub struct light_sampler {
+    lights: Vec<Arc<dyn light + Send + Sync>>,
+    mode: light_sampling,
+    infinite: Vec<usize>,
+    bounded: Vec<usize>,
+    // chance of picking from the infinite lights
+    infinite_share: f64,
+    // over bounded, by power
+    alias: Option<alias_table>,
+    nodes: Vec<light_node>,
+    parent: Vec<usize>,
+    // leaf node of each light, usize::MAX for infinite ones
+    leaf_of: Vec<usize>,
+    // position of each light in bounded
+    bounded_index: Vec<usize>,
+}
+
+impl light_sampler {
+    pub fn new(lights: Vec<Arc<dyn light + Send + Sync>>, mode: light_sampling) -> Self {
+        let mut infinite = Vec::new();
+        let mut bounded = Vec::new();
+        let mut all_bounds = Vec::new();
+        let mut bounded_index = vec![usize::MAX; lights.len()];
+        for (i, l) in lights.iter().enumerate() {
+            match l.bounds() {
+                Some(b) => {
+                    bounded_index[i] = bounded.len();
+                    bounded.push(i);
+                    all_bounds.push(b);
+                }
+                None => infinite.push(i),
+            }
+        }
+        let infinite_share = if bounded.is_empty() {
+            1.0
+        } else if mode == light_sampling::Uniform {
+            infinite.len() as f64 / lights.len() as f64
+        } else {
+            // one share per light at infinity, one for everything else
+            infinite.len() as f64 / (infinite.len() + 1) as f64
+        };
+        let alias = if mode == light_sampling::Power && !bounded.is_empty() {
+            Some(alias_table::new(
+                &all_bounds.iter().map(|b| b.power).collect::<Vec<_>>(),
+            ))
+        } else {
+            None
+        };
+        let mut sampler = Self {
+            lights,
+            mode,
+            infinite,
+            bounded: bounded.clone(),
+            infinite_share,
+            alias,
+            nodes: Vec::new(),
+            parent: Vec::new(),
+            leaf_of: Vec::new(),
+            bounded_index,
+        };
+        sampler.leaf_of = vec![usize::MAX; sampler.lights.len()];
+        if !bounded.is_empty() {
+            let mut items: Vec<(usize, light_bounds)> =
+                bounded.into_iter().zip(all_bounds).collect();
+            sampler.build(&mut items, usize::MAX);
+        }
+        sampler
+    }
+
+    // splits at the median centroid along the longest axis, like bvh_node
+    fn build(&mut self, items: &mut [(usize, light_bounds)], parent: usize) -> usize {
+        let index = self.nodes.len();
+        self.parent.push(parent);
+        if items.len() == 1 {
+            let (light, bounds) = items[0];
+            self.nodes.push(light_node {
+                bounds,
+                left: 0,
+                right: 0,
+                light,
+                leaf: true,
+            });
+            self.leaf_of[light] = index;
+            return index;
+        }
+        let mut centroids = aabb::empty;
+        for (_, b) in items.iter() {
+            let c = centroid(&b.bounds);
+            centroids = aabb::new_from_aabbs(&centroids, &aabb::new_from_points(c, c));
+        }
+        let axis = centroids.longest_axis();
+        items.sort_by(|a, b| centroid(&a.1.bounds)[axis].total_cmp(&centroid(&b.1.bounds)[axis]));
+        let bounds = items
+            .iter()
+            .skip(1)
+            .fold(items[0].1, |acc, (_, b)| union_bounds(&acc, b));
+        self.nodes.push(light_node {
+            bounds,
+            left: 0,
+            right: 0,
+            light: 0,
+            leaf: false,
+        });
+        let mid = items.len() / 2;
+        let (left_items, right_items) = items.split_at_mut(mid);
+        let left = self.build(left_items, index);
+        let right = self.build(right_items, index);
+        self.nodes[index].left = left;
+        self.nodes[index].right = right;
+        index
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lights.is_empty()
+    }
+    pub fn len(&self) -> usize {
+        self.lights.len()
+    }
+    pub fn light(&self, i: usize) -> &dyn light {
+        self.lights[i].as_ref()
+    }
+    pub fn lights(&self) -> &[Arc<dyn light + Send + Sync>] {
+        &self.lights
+    }
+    pub fn infinite_lights(&self) -> &[usize] {
+        &self.infinite
+    }
+
+    // a light index and the probability it was chosen, None if no light can reach p
+    pub fn sample(&self, p: &Vec3) -> Option<(usize, f64)> {
+        if self.lights.is_empty() {
+            return None;
+        }
+        if random_double() < self.infinite_share {
+            let n = self.infinite.len();
+            let i = ((random_double() * n as f64) as usize).min(n - 1);
+            return Some((self.infinite[i], self.infinite_share / n as f64));
+        }
+        let share = 1.0 - self.infinite_share;
+        match self.mode {
+            light_sampling::Uniform => {
+                let n = self.bounded.len();
+                let i = ((random_double() * n as f64) as usize).min(n - 1);
+                Some((self.bounded[i], share / n as f64))
+            }
+            light_sampling::Power => {
+                let alias = self.alias.as_ref().unwrap();
+                let i = alias.sample();
+                Some((self.bounded[i], share * alias.pmf(i)))
+            }
+            light_sampling::Tree => {
+                let mut node = 0;
+                let mut prob = share;
+                while !self.nodes[node].leaf {
+                    let n = &self.nodes[node];
+                    let left = importance(&self.nodes[n.left].bounds, p);
+                    let right = importance(&self.nodes[n.right].bounds, p);
+                    if left + right <= 0.0 {
+                        return None;
+                    }
+                    let p_left = left / (left + right);
+                    if random_double() < p_left {
+                        prob *= p_left;
+                        node = n.left;
+                    } else {
+                        prob *= 1.0 - p_left;
+                        node = n.right;
+                    }
+                }
+                Some((self.nodes[node].light, prob))
+            }
+        }
+    }
+
+    // probability that sample(p) returns light i
+    pub fn pmf(&self, p: &Vec3, i: usize) -> f64 {
+        if self.leaf_of[i] == usize::MAX {
+            return self.infinite_share / self.infinite.len() as f64;
+        }
+        let share = 1.0 - self.infinite_share;
+        match self.mode {
+            light_sampling::Uniform => share / self.bounded.len() as f64,
+            light_sampling::Power => {
+                share * self.alias.as_ref().unwrap().pmf(self.bounded_index[i])
+            }
+            light_sampling::Tree => {
+                let mut prob = share;
+                let mut node = self.leaf_of[i];
+                while self.parent[node] != usize::MAX {
+                    let n = &self.nodes[self.parent[node]];
+                    let left = importance(&self.nodes[n.left].bounds, p);
+                    let right = importance(&self.nodes[n.right].bounds, p);
+                    if left + right <= 0.0 {
+                        return 0.0;
+                    }
+                    prob *= if n.left == node { left } else { right } / (left + right);
+                    node = self.parent[node];
+                }
+                prob
+            }
+        }
+    }
+
+    // Density with which next event estimation from p would have produced direction
+    // toward an emitter at hit_point, found by walking the BVH down to the lights whose
+    // bounds contain it. 0 if that emitter isn't a registered light.
+    pub fn emitter_pdf(&self, p: &Vec3, direction: &Vec3, hit_point: &Vec3) -> f64 {
+        if self.nodes.is_empty() {
+            return 0.0;
+        }
+        let mut pdf = 0.0;
+        let mut stack = vec![0];
+        while let Some(node) = stack.pop() {
+            let n = &self.nodes[node];
+            if !contains(&n.bounds.bounds, hit_point) {
+                continue;
+            }
+            if n.leaf {
+                let light_pdf = self.lights[n.light].pdf(p, direction);
+                if light_pdf > 0.0 {
+                    pdf += self.pmf(p, n.light) * light_pdf;
+                }
+            } else {
+                stack.push(n.left);
+                stack.push(n.right);
+            }
+        }
+        pdf
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::light::{directional_light, point_light, quad_light};
+
+    #[test]
+    fn test_alias_table_frequencies() {
+        let table = alias_table::new(&[1.0, 0.0, 3.0, 6.0]);
+        let n = 100000;
+        let mut counts = [0usize; 4];
+        for _ in 0..n {
+            counts[table.sample()] += 1;
+        }
+        for (i, expected) in [0.1, 0.0, 0.3, 0.6].iter().enumerate() {
+            assert!((counts[i] as f64 / n as f64 - expected).abs() < 0.01);
+            assert!((table.pmf(i) - expected).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn test_pmf_matches_sampling() {
+        let mut lights: Vec<Arc<dyn light + Send + Sync>> = Vec::new();
+        for i in 0..12 {
+            let x = i as f64 * 2.0;
+            lights.push(Arc::new(point_light::new(
+                Vec3::new(x, 3.0, (i % 3) as f64),
+                Vec3::new(1.0 + i as f64, 1.0, 1.0),
+            )));
+        }
+        // an area light and one at infinity
+        lights.push(Arc::new(quad_light::new(
+            Vec3::new(0.0, 5.0, 0.0),
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 1.0),
+            Vec3::ones(),
+        )));
+        lights.push(Arc::new(directional_light::new(
+            Vec3::new(0.0, 1.0, 0.0),
+            Vec3::ones(),
+        )));
+        let p = Vec3::new(3.0, 0.0, 0.0);
+        for mode in [
+            light_sampling::Uniform,
+            light_sampling::Power,
+            light_sampling::Tree,
+        ] {
+            let sampler = light_sampler::new(lights.clone(), mode);
+            let total: f64 = (0..lights.len()).map(|i| sampler.pmf(&p, i)).sum();
+            assert!((total - 1.0).abs() < 1e-9);
+            let n = 200000;
+            let mut counts = vec![0usize; lights.len()];
+            for _ in 0..n {
+                let (i, prob) = sampler.sample(&p).unwrap();
+                assert!((prob - sampler.pmf(&p, i)).abs() < 1e-12);
+                counts[i] += 1;
+            }
+            for i in 0..lights.len() {
+                assert!((counts[i] as f64 / n as f64 - sampler.pmf(&p, i)).abs() < 0.01);
+            }
+        }
+    }
+}
@@ -13,8 +13,25 @@ mod aabb;
 mod bvh;
 mod texture;
 mod perlin;
+mod noise;
 mod quad;
 mod con_medium;
+mod het_medium;
+mod onb;
+mod phase;
+mod fog;
+mod sss;
+mod light;
+mod light_sampler;
+mod bdpt;
+mod photon_map;
+mod mlt;
+mod integrator;
+mod environment;
+mod sky;
+mod triangle;
+mod asset;
+mod texture_graph;
 
 use std::rc::Rc;
 use std::sync::Arc;
@@ -33,9 +50,24 @@ use texture::*;
 // use crate::texture::*;
 use material::*;
 use perlin::*;
+use noise::*;
 use quad::Quad;
+use triangle::Triangle;
+use asset::*;
+use texture_graph::*;
 // use constant_medium::*;
 use con_medium::*;
+use het_medium::*;
+use phase::*;
+use fog::*;
+use sss::*;
+use light::*;
+use light_sampler::*;
+use environment::*;
+use sky::*;
+use integrator::path_tracer;
+use photon_map::photon_mapper;
+use mlt::metropolis;
 // use quad::quad;
 
 use image::{ImageBuffer, RgbImage}; //接收render传回来的图片，在main中文件输出
@@ -83,6 +115,10 @@ fn bouncing_spheres() {
     world.add(Arc::new(Sphere::new(Vec3::new(-4.0, 1.0, 0.0), 1.0, material2)));
     let material3 = Arc::new(crate::material::metal::new(Vec3::new(0.7, 0.6, 0.5), 0.0));
     world.add(Arc::new(Sphere::new(Vec3::new(4.0, 1.0, 0.0), 1.0, material3)));
+    // a small lamp above the scene, just out of view, for the glass balls to focus
+    let lamp_color = Vec3::new(150.0, 140.0, 125.0);
+    let (lamp, lamp_light) = sphere_light::new_with_sphere(Vec3::new(-3.0, 6.5, -5.0), 0.5, Arc::new(diffuse_light::new_from_emit_color(lamp_color)));
+    world.add(lamp);
 
     // world = Arc::new(bvh_node::new(world));
     // world = HittableList::new_from_bvh(world);
@@ -101,7 +137,12 @@ fn bouncing_spheres() {
     cam.samples_per_pixel = 30;
     cam.aspect_ratio = cam.width as f64 / cam.height as f64;
     cam.max_depth = 50;
-    cam.background = Vec3::new(0.7, 0.8, 1.0);
+    cam.background = Vec3::new(0.25, 0.3, 0.4);
+    cam.lights.push(Arc::new(lamp_light));
+    // sharp caustics under the glass balls
+    let mut photons = photon_mapper::new();
+    photons.photon_radius = 0.04;
+    cam.integrator = Arc::new(photons);
     cam.vfov = 20.0;
     cam.lookfrom = Vec3::new(13.0, 2.0, 3.0);
     cam.lookat = Vec3::new(0.0, 0.0, 0.0);
@@ -136,7 +177,7 @@ fn checkered_spheres() {
     cam.render(Arc::new(world));
 }
 fn earth() {
-    let earth_texture = Arc::new(image_texture::new("earthmap.jpg"));
+    let earth_texture = asset_manager::new().texture("earthmap.jpg").unwrap_or_else(|e| panic!("{}", e));
     let earth_surface = Arc::new(lambertian::new_with_texture(earth_texture));
     let globe = Arc::new(Sphere::new(Vec3::zero(), 2.0, earth_surface));
     let mut cam = Camera::new();
@@ -146,6 +187,11 @@ fn earth() {
     cam.aspect_ratio = cam.width as f64 / cam.height as f64;
     cam.max_depth = 50;
     cam.background = Vec3::new(0.7, 0.8, 1.0);
+    // lat-long sky lighting the globe when available
+    match environment_map::load(&asset_manager::new(), "sky.hdr") {
+        Ok(sky) => cam.environment = Some(Arc::new(sky)),
+        Err(e) => eprintln!("{}, using the constant background", e),
+    }
     cam.vfov = 20.0;
     cam.lookfrom = Vec3::new(0.0, 0.0, 12.0);
     cam.lookat = Vec3::new(0.0, 0.0, 0.0);
@@ -212,8 +258,10 @@ fn simple_light() {
     world.add(Arc::new(Sphere::new(Vec3::new(0.0, -1000.0, 0.0), 1000.0, Arc::new(lambertian::new_with_texture(pertext.clone())))));
     world.add(Arc::new(Sphere::new(Vec3::new(0.0, 2.0, 0.0), 2.0, Arc::new(lambertian::new_with_texture(pertext.clone()))))); 
     let difflight = Arc::new(diffuse_light::new_from_emit_color(Vec3::new(4.0, 4.0, 4.0)));
-    world.add(Arc::new(Sphere::new(Vec3::new(0.0, 7.0, 0.0), 2.0, difflight.clone())));  
-    world.add(Arc::new(Quad::new(Vec3::new(3.0,1.0,-2.0), Vec3::new(2.0, 0.0, 0.0), Vec3::new(0.0, 2.0, 0.0), difflight.clone())));
+    let (globe, globe_light) = sphere_light::new_with_sphere(Vec3::new(0.0, 7.0, 0.0), 2.0, difflight.clone());
+    world.add(globe);
+    let (panel, panel_light) = quad_light::new_with_quad(Vec3::new(3.0,1.0,-2.0), Vec3::new(2.0, 0.0, 0.0), Vec3::new(0.0, 2.0, 0.0), difflight);
+    world.add(panel);
     let mut cam = Camera::new();
     cam.width = 800;
     cam.height = 800;
@@ -226,6 +274,8 @@ fn simple_light() {
     cam.lookat = Vec3::new(0.0, 2.0, 0.0);
     cam.vup = Vec3::new(0.0, 1.0, 0.0);
     cam.defocus_angle = 0.0;
+    cam.lights.push(Arc::new(globe_light));
+    cam.lights.push(Arc::new(panel_light));
     cam.render(Arc::new(world));
 }
 fn cornell_box() {
@@ -233,11 +283,13 @@ fn cornell_box() {
     let red = Arc::new(lambertian::new(Vec3::new(0.65, 0.05, 0.05)));
     let white = Arc::new(lambertian::new(Vec3::new(0.73, 0.73, 0.73)));
     let green = Arc::new(lambertian::new(Vec3::new(0.12, 0.45, 0.15)));
-    let light = Arc::new(diffuse_light::new_from_emit_color(Vec3::new(15.0, 15.0, 15.0)));
+    // the ceiling light's normal points down, so only its underside glows
+    let light = Arc::new(diffuse_light::new_with_strength(Arc::new(solid_color::new(Vec3::ones())), 15.0, emission_side::Front));
 
     world.add(Arc::new(Quad::new(Vec3::new(555.0, 0.0, 0.0), Vec3::new(0.0, 555.0, 0.0), Vec3::new(0.0, 0.0, 555.0), green.clone())));
     world.add(Arc::new(Quad::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 555.0, 0.0), Vec3::new(0.0,0.0, 555.0), red.clone())));    
-    world.add(Arc::new(Quad::new(Vec3::new(343.0,554.0,332.0), Vec3::new(-130.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -105.0), light.clone())));
+    let (ceiling, ceiling_light) = quad_light::new_with_quad(Vec3::new(343.0,554.0,332.0), Vec3::new(-130.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -105.0), light);
+    world.add(ceiling);
     world.add(Arc::new(Quad::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(555.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 555.0), white.clone())));
     world.add(Arc::new(Quad::new(Vec3::new(555.0,555.0,555.0),Vec3::new(-555.0,0.0,0.0),Vec3::new(0.0,0.0,-555.0), white.clone())));
     world.add(Arc::new(Quad::new(Vec3::new(0.0,0.0,555.0),Vec3::new(555.0,0.0,0.0),Vec3::new(0.0,555.0,0.0), white.clone())));
@@ -272,6 +324,7 @@ fn cornell_box() {
     cam.lookat = Vec3::new(278.0, 278.0, 0.0);
     cam.vup = Vec3::new(0.0, 1.0, 0.0);
     cam.defocus_angle = 0.0;
+    cam.lights.push(Arc::new(ceiling_light));
     cam.render(Arc::new(world));
 
 }
@@ -283,20 +336,44 @@ fn cornell_smoke() {
     let light = Arc::new(diffuse_light::new_from_emit_color(Vec3::new(7.0, 7.0, 7.0)));
     world.add(Arc::new(Quad::new(Vec3::new(555.0, 0.0, 0.0), Vec3::new(0.0, 555.0, 0.0), Vec3::new(0.0, 0.0, 555.0), green.clone())));
     world.add(Arc::new(Quad::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 555.0, 0.0), Vec3::new(0.0,0.0, 555.0), red.clone())));    
-    world.add(Arc::new(Quad::new(Vec3::new(113.0,554.0,127.0), Vec3::new(330.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 305.0), light.clone())));
+    let (ceiling, ceiling_light) = quad_light::new_with_quad(Vec3::new(113.0,554.0,127.0), Vec3::new(330.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 305.0), light);
+    world.add(ceiling);
     world.add(Arc::new(Quad::new(Vec3::new(0.0, 555.0, 0.0), Vec3::new(555.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 555.0), white.clone())));
     world.add(Arc::new(Quad::new(Vec3::new(0.0,0.0,0.0),Vec3::new(555.0,0.0,0.0),Vec3::new(0.0,0.0,555.0), white.clone())));
     world.add(Arc::new(Quad::new(Vec3::new(0.0,0.0,555.0),Vec3::new(555.0,0.0,0.0),Vec3::new(0.0,555.0,0.0), white.clone())));
     
-    let box1 = Quad::boxx(Vec3::new(0.0,0.0,0.0),Vec3::new(165.0,330.0,165.0),white.clone());
-    let box1 = Arc::new(rotate_y::new(box1, 15.0));
-    let box1 = Arc::new(translate::new(box1, Vec3::new(265.0,0.0,295.0)));
-    // world.add(box1);
-    world.add(Arc::new(constant_medium::new_from_color(box1, 0.01, Vec3::new(0.0, 0.0, 0.0))));
-    let box2 = Quad::boxx(Vec3::new(0.0,0.0,0.0),Vec3::new(165.0,165.0,165.0),white.clone());
-    let box2 = Arc::new(rotate_y::new(box2, -18.0));
-    let box2 = Arc::new(translate::new(box2, Vec3::new(130.0,0.0,65.0)));
-    world.add(Arc::new(constant_medium::new_from_color(box2, 0.01, Vec3::new(1.0, 1.0, 1.0))));
+    // dark smoke plume rising from the floor, baked into a voxel grid
+    let (min, max) = (Vec3::new(250.0, 0.0, 280.0), Vec3::new(450.0, 450.0, 480.0));
+    let (nx, ny, nz) = (40, 90, 40);
+    let turb = perlin::perlin::new_with_seed(3);
+    let mut voxels = Vec::with_capacity(nx * ny * nz);
+    for k in 0..nz {
+        for j in 0..ny {
+            for i in 0..nx {
+                let p = Vec3::new(
+                    min.x + (i as f64 + 0.5) / nx as f64 * (max.x - min.x),
+                    min.y + (j as f64 + 0.5) / ny as f64 * (max.y - min.y),
+                    min.z + (k as f64 + 0.5) / nz as f64 * (max.z - min.z),
+                );
+                let h = p.y / max.y;
+                // the column widens and thins out as it rises
+                let radius = 25.0 + 70.0 * h;
+                let axis = Vec3::new(350.0 + 20.0 * (h * 6.0).sin(), p.y, 380.0);
+                let r = (p - axis).length() / radius;
+                let shape = (1.0 - r * r).max(0.0) * (1.0 - h);
+                let wisps = turb.turb(&(p * 0.03), 6);
+                voxels.push((0.08 * shape * (0.3 + wisps)) as f32);
+            }
+        }
+    }
+    let plume = Arc::new(grid_density::new(nx, ny, nz, voxels, min, max).unwrap_or_else(|e| panic!("{}", e)));
+    let box1 = Quad::boxx(min, max, white.clone());
+    world.add(Arc::new(heterogeneous_medium::new_from_color(box1, plume, Vec3::new(0.05, 0.05, 0.05))));
+
+    // fluffy white cloud
+    let cloud = Arc::new(procedural_density::new_turbulence(Vec3::new(190.0, 130.0, 150.0), 120.0, 0.02, 0.12, 7));
+    let box2 = Arc::new(Sphere::new(Vec3::new(190.0, 130.0, 150.0), 120.0, white.clone()));
+    world.add(Arc::new(heterogeneous_medium::new_from_color(box2, cloud, Vec3::new(1.0, 1.0, 1.0))));
 
 
 
@@ -312,6 +389,7 @@ fn cornell_smoke() {
     cam.lookat = Vec3::new(278.0, 278.0, 0.0);
     cam.vup = Vec3::new(0.0, 1.0, 0.0);
     cam.defocus_angle = 0.0;
+    cam.lights.push(Arc::new(ceiling_light));
     cam.render(Arc::new(world));
 }
 fn final_scene() {
@@ -335,7 +413,8 @@ fn final_scene() {
     world.add(Arc::new(bvh_node::new(boxes1)));
 
     let light = Arc::new(diffuse_light::new_from_emit_color(Vec3::new(7.0, 7.0, 7.0)));
-    world.add(Arc::new(Quad::new(Vec3::new(123.0, 554.0, 147.0), Vec3::new(300.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 265.0), light.clone())));
+    let (ceiling, ceiling_light) = quad_light::new_with_quad(Vec3::new(123.0, 554.0, 147.0), Vec3::new(300.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 265.0), light);
+    world.add(ceiling);
 
     let center1 = Vec3::new(400.0, 400.0, 200.0);
     let center2 = center1 + Vec3::new(30.0, 0.0, 0.0);
@@ -349,9 +428,16 @@ fn final_scene() {
     world.add(boundary.clone());
     world.add(Arc::new(constant_medium::new_from_color(boundary.clone(), 0.2, Vec3::new(0.2, 0.4, 0.9))));
     let boundary = Arc::new(Sphere::new(Vec3::zero(), 5000.0, Arc::new(dielectric::new(1.5))));
-    world.add(Arc::new(constant_medium::new_from_color(boundary.clone(), 0.0001, Vec3::new(1.0, 1.0, 1.0))));
+    // patchy haze instead of a uniform one
+    let haze_noise = perlin::perlin::new_with_seed(11);
+    let haze = Arc::new(procedural_density::new(Box::new(move |p: &Vec3| 0.0002 * haze_noise.turb(&(*p * 0.004), 5)), 0.0004));
+    world.add(Arc::new(heterogeneous_medium::new_from_color(boundary.clone(), haze, Vec3::new(1.0, 1.0, 1.0))));
+    let cloud = Arc::new(procedural_density::new_turbulence(Vec3::new(80.0, 420.0, 300.0), 90.0, 0.025, 0.08, 5));
+    let cloud_boundary = Arc::new(Sphere::new(Vec3::new(80.0, 420.0, 300.0), 90.0, Arc::new(dielectric::new(1.5))));
+    world.add(Arc::new(heterogeneous_medium::new_from_color(cloud_boundary, cloud, Vec3::new(1.0, 1.0, 1.0))));
 
-    let emat = Arc::new(lambertian::new_with_texture(Arc::new(image_texture::new("earthmap.jpg"))));
+    let earth_texture = asset_manager::new().texture("earthmap.jpg").unwrap_or_else(|e| panic!("{}", e));
+    let emat = Arc::new(lambertian::new_with_texture(earth_texture));
     world.add(Arc::new(Sphere::new(Vec3::new(400.0,200.0,400.0),100.0,emat)));
     let pertext = Arc::new(noise_texture::new_with_scale(0.2));
     world.add(Arc::new(Sphere::new(Vec3::new(220.0,280.0,300.0),80.0,Arc::new(lambertian::new_with_texture(pertext)))));
@@ -378,21 +464,28 @@ fn final_scene() {
     cam.lookat = Vec3::new(278.0, 278.0, 0.0);
     cam.vup = Vec3::new(0.0, 1.0, 0.0);
     cam.defocus_angle = 0.0;
+    cam.lights.push(Arc::new(ceiling_light));
     cam.render(Arc::new(world));
 }
 fn mc() {
     let mut world = HittableList::new();
+    let assets = asset_manager::new();
+    let load = |name: &str| assets.texture(name).unwrap_or_else(|e| panic!("{}", e));
+    // the skin textures double as height maps for some surface detail
+    let bumped = |tex: Arc<image_texture>, strength: f64| -> Arc<dyn Material + Send + Sync> {
+        Arc::new(normal_mapped::new_bump_map(Arc::new(lambertian::new_with_texture(tex.clone())), tex, strength))
+    };
     //head
     // let head_up_mat = Arc::new(lambertian::new(Vec3::new(0.8, 0.8, 0.8)));
     // let head_up_mat = Arc::new(diffuse_light::new_from_emit_color(Vec3::new(4.0, 4.0, 4.0)));
-    let head_up_tex = Arc::new(image_texture::new("head_up.png"));
+    let head_up_tex = load("head_up.png");
     let head_up_mat = Arc::new(lambertian::new_with_texture(head_up_tex));
     let point1 = Vec3::new(-96.4222,-60.6220,701.8958);
     let point2 = Vec3::new(-177.1899,88.9660,701.8958);
     let point3 = Vec3::new(53.1658,20.1457,701.8958);
     world.add(Arc::new(Quad::new(point1, point2-point1, point3-point1, head_up_mat.clone())));
     // let head_front_mat = Arc::new(lambertian::new(Vec3::new(0.8, 0.8, 0.8)));
-    let head_front_tex = Arc::new(image_texture::new("head_front.png"));
+    let head_front_tex = load("head_front.png");
     let head_front_mat = Arc::new(lambertian::new_with_texture(head_front_tex));
     // let head_front_mat = Arc::new(diffuse_light::new_from_emit_color(Vec3::new(4.0, 4.0, 4.0)));
     let point1 = Vec3::new(-130.8492,-19.4752,524.2944);
@@ -400,7 +493,7 @@ fn mc() {
     let point3 = Vec3::new(-96.4222,-60.6220,701.8958);
     world.add(Arc::new(Quad::new(point1, point2-point1, point3-point1, head_front_mat.clone())));
     // let head_right_mat = Arc::new(lambertian::new(Vec3::new(0.8, 0.8, 0.8)));
-    let head_right_tex = Arc::new(image_texture::new("head_right.png"));
+    let head_right_tex = load("head_right.png");
     let head_right_mat = Arc::new(lambertian::new_with_texture(head_right_tex));
     // let head_right_mat = Arc::new(diffuse_light::new_from_emit_color(Vec3::new(4.0, 4.0, 4.0)));
     let point1 = Vec3::new(53.1658,20.1457,531.8958);
@@ -411,7 +504,7 @@ fn mc() {
     //body
     // let body_up_mat = Arc::new(lambertian::new(Vec3::new(0.8, 0.8, 0.8)));
     // let body_up_mat = Arc::new(diffuse_light::new_from_emit_color(Vec3::new(4.0, 4.0, 4.0)));
-    let body_up_tex = Arc::new(image_texture::new("body_up.png"));
+    let body_up_tex = load("body_up.png");
     let body_up_mat = Arc::new(lambertian::new_with_texture(body_up_tex));
     let point1 = Vec3::new(-130.8492,-19.4752,524.2944);
     let point2 = Vec3::new(38.3475,-2.9682,524.2944);
@@ -419,7 +512,7 @@ fn mc() {
     world.add(Arc::new(Quad::new(point1, point2-point1, point3-point1, body_up_mat.clone())));
     // let body_front_mat = Arc::new(lambertian::new(Vec3::new(0.8, 0.8, 0.8)));
     // let body_front_mat = Arc::new(diffuse_light::new_from_emit_color(Vec3::new(4.0, 4.0, 4.0)));
-    let body_front_tex = Arc::new(image_texture::new("body_front.png"));
+    let body_front_tex = load("body_front.png");
     let body_front_mat = Arc::new(lambertian::new_with_texture(body_front_tex));
     let point1 = Vec3::new(-130.8492,-19.4752,269.2944);
     let point2 = Vec3::new(38.3475,-2.9682,269.2944);
@@ -427,7 +520,7 @@ fn mc() {
     world.add(Arc::new(Quad::new(point1, point2-point1, point3-point1, body_front_mat.clone())));
     // let body_right_mat = Arc::new(lambertian::new(Vec3::new(0.8, 0.8, 0.8)));
     // let body_right_mat = Arc::new(diffuse_light::new_from_emit_color(Vec3::new(4.0, 4.0, 4.0)));
-    let body_right_tex = Arc::new(image_texture::new("body_right.png"));
+    let body_right_tex = load("body_right.png");
     let body_right_mat = Arc::new(lambertian::new_with_texture(body_right_tex));
     let point1 = Vec3::new(38.3475,-2.9682,269.2944);
     let point2 = Vec3::new(30.0940,81.6301,269.2944);
@@ -436,17 +529,17 @@ fn mc() {
 
     //left arm
     // let left_arm_up_mat = Arc::new(lambertian::new(Vec3::new(0.8, 0.8, 0.8)));
-    let left_arm_up_tex = Arc::new(image_texture::new("arm_up.png"));
-    let left_arm_up_mat = Arc::new(lambertian::new_with_texture(left_arm_up_tex));
+    let left_arm_up_tex = load("arm_up.png");
+    let left_arm_up_mat = bumped(left_arm_up_tex, 0.3);
     // let left_arm_up_mat = Arc::new(diffuse_light::new_from_emit_color(Vec3::new(4.0, 4.0, 4.0)));
     let point1 = Vec3::new(44.3618,-9.3981,467.6647);
     let point2 = Vec3::new(129.3618,-9.3981,467.6647);
     let point3 = Vec3::new(44.3618,27.1401,544.4107);
     world.add(Arc::new(Quad::new(point1, point2-point1, point3-point1, left_arm_up_mat.clone())));
     // let left_arm_right_mat = Arc::new(lambertian::new(Vec3::new(0.8, 0.8, 0.8)));
-    let left_arm_right_tex = Arc::new(image_texture::new("arm_side.png"));
+    let left_arm_right_tex = load("arm_side.png");
     // let left_arm_right_mat = Arc::new(diffuse_light::new_from_emit_color(Vec3::new(4.0, 4.0, 4.0)));
-    let left_arm_right_mat = Arc::new(lambertian::new_with_texture(left_arm_right_tex));
+    let left_arm_right_mat = bumped(left_arm_right_tex, 0.3);
     let point3 = Vec3::new(129.3618,-9.3981,467.6647);
     let point1 = Vec3::new(129.3618,220.8402,358.0501);
     // let point2 = Vec3::new(129.3618,27.1401,544.4107);
@@ -456,8 +549,8 @@ fn mc() {
     //right_arm
     // let right_arm_up_mat = Arc::new(lambertian::new(Vec3::new(0.8, 0.8, 0.8)));
     // let right_arm_up_mat = Arc::new(diffuse_light::new_from_emit_color(Vec3::new(4.0, 4.0, 4.0)));
-    let right_arm_up_tex = Arc::new(image_texture::new("arm_front.png"));
-    let right_arm_up_mat = Arc::new(lambertian::new_with_texture(right_arm_up_tex));
+    let right_arm_up_tex = load("arm_front.png");
+    let right_arm_up_mat = bumped(right_arm_up_tex, 0.3);
     let point1 = Vec3::new(-149.2936,-191.9560,417.1133);
     let point3 = Vec3::new(-149.2936,25.4590,550.3639);
     let point2 = Vec3::new(-232.6707, -183.3180,403.0194);
@@ -465,16 +558,16 @@ fn mc() {
 
     // let right_arm_front_mat = Arc::new(lambertian::new(Vec3::new(0.8, 0.8, 0.8)));
     // let right_arm_front_mat = Arc::new(diffuse_light::new_from_emit_color(Vec3::new(4.0, 4.0, 4.0)));
-    let right_arm_front_tex = Arc::new(image_texture::new("arm_bottom.png"));
-    let right_arm_front_mat = Arc::new(lambertian::new_with_texture(right_arm_front_tex));
+    let right_arm_front_tex = load("arm_bottom.png");
+    let right_arm_front_mat = bumped(right_arm_front_tex, 0.3);
     let point1 = Vec3::new(-216.1404,-139.7492,331.9314);
     let point3 = Vec3::new(-132.7632,-148.3872,346.0253);
     let point2 = Vec3::new(-232.6707,-183.3180,403.0194);
     world.add(Arc::new(Quad::new(point1, point2-point1, point3-point1, right_arm_front_mat.clone())));
     // let right_arm_right_mat = Arc::new(lambertian::new(Vec3::new(0.8, 0.8, 0.8)));
     // let right_arm_right_mat = Arc::new(diffuse_light::new_from_emit_color(Vec3::new(4.0, 4.0, 4.0)));
-    let right_arm_right_tex = Arc::new(image_texture::new("arm_side.png"));
-    let right_arm_right_mat = Arc::new(lambertian::new_with_texture(right_arm_right_tex));
+    let right_arm_right_tex = load("arm_side.png");
+    let right_arm_right_mat = bumped(right_arm_right_tex, 0.3);
     let point1 = Vec3::new(-132.7632,-148.3872,346.0253);
     let point3 = Vec3::new(-132.7632,69.0278,479.2759);
     let point2 = Vec3::new(-149.2936,-191.9560,417.1133);
@@ -483,8 +576,8 @@ fn mc() {
     //left leg
     // let left_leg_up_mat = Arc::new(lambertian::new(Vec3::new(0.8, 0.8, 0.8)));
     // let left_leg_up_mat = Arc::new(diffuse_light::new_from_emit_color(Vec3::new(4.0, 4.0, 4.0)));
-    let left_leg_up_tex = Arc::new(image_texture::new("leg_up.png"));
-    let left_leg_up_mat = Arc::new(lambertian::new_with_texture(left_leg_up_tex));
+    let left_leg_up_tex = load("leg_up.png");
+    let left_leg_up_mat = bumped(left_leg_up_tex, 0.3);
 
     let point1 = Vec3::new(33.1658,-11.1290,282.0460);
     let point2 = Vec3::new(33.1658,66.2087,246.7774);
@@ -493,8 +586,8 @@ fn mc() {
 
     // let left_leg_right_mat = Arc::new(lambertian::new(Vec3::new(0.8, 0.8, 0.8)));
     // let left_leg_right_mat = Arc::new(diffuse_light::new_from_emit_color(Vec3::new(4.0, 4.0, 4.0)));
-    let left_leg_right_tex = Arc::new(image_texture::new("leg_side.png"));
-    let left_leg_right_mat = Arc::new(lambertian::new_with_texture(left_leg_right_tex));
+    let left_leg_right_tex = load("leg_side.png");
+    let left_leg_right_mat = bumped(left_leg_right_tex, 0.3);
     let point1 = Vec3::new(33.1658,-116.9349,50.0329);
     let point2 = Vec3::new(33.1658,-39.5972,14.7642);
     let point3 = Vec3::new(33.1658,-11.1290,282.0460);
@@ -502,8 +595,8 @@ fn mc() {
 
     // let left_leg_front_mat = Arc::new(lambertian::new(Vec3::new(0.8, 0.8, 0.8)));
     // let left_leg_front_mat = Arc::new(diffuse_light::new_from_emit_color(Vec3::new(4.0, 4.0, 4.0)));
-    let left_leg_front_tex = Arc::new(image_texture::new("leg_front.png"));
-    let left_leg_front_mat = Arc::new(lambertian::new_with_texture(left_leg_front_tex));
+    let left_leg_front_tex = load("leg_front.png");
+    let left_leg_front_mat = bumped(left_leg_front_tex, 0.3);
     let point1 = Vec3::new(-51.8342,-116.9349,50.0329);
     let point2 = Vec3::new(33.1658,-116.9349,50.0329);
     let point3 = Vec3::new(-51.8342,-11.1290,282.0460);
@@ -511,8 +604,8 @@ fn mc() {
     //right leg
     // let right_leg_up_mat = Arc::new(lambertian::new(Vec3::new(0.8, 0.8, 0.8)));
     // let right_leg_up_mat = Arc::new(diffuse_light::new_from_emit_color(Vec3::new(4.0, 4.0, 4.0)));
-    let right_leg_up_tex = Arc::new(image_texture::new("leg_up.png"));
-    let right_leg_up_mat = Arc::new(lambertian::new_with_texture(right_leg_up_tex));
+    let right_leg_up_tex = load("leg_up.png");
+    let right_leg_up_mat = bumped(right_leg_up_tex, 0.3);
     let point1 = Vec3::new(-135.3507,-23.3993,254.0267);
     let point2 = Vec3::new(-50.3606,-22.8742,252.8401);
     let point3 = Vec3::new(-135.2492,51.4010,294.3987);
@@ -520,44 +613,45 @@ fn mc() {
 
     // let right_leg_right_mat = Arc::new(lambertian::new(Vec3::new(0.8, 0.8, 0.8)));
     // let right_leg_right_mat = Arc::new(diffuse_light::new_from_emit_color(Vec3::new(4.0, 4.0, 4.0)));
-    let right_leg_right_tex = Arc::new(image_texture::new("leg_side.png"));
-    let right_leg_right_mat = Arc::new(lambertian::new_with_texture(right_leg_right_tex));
+    let right_leg_right_tex = load("leg_side.png");
+    let right_leg_right_mat = bumped(right_leg_right_tex, 0.3);
     let point1 = Vec3::new(-54.2413,98.2321,28.4672);
     let point2 = Vec3::new(-54.1398,173.0324,68.8392);
     let point3 = Vec3::new(-50.3606,-22.8742,252.8401);
     world.add(Arc::new(Quad::new(point1, point2-point1, point3-point1, right_leg_right_mat.clone())));
     // let right_leg_front_mat = Arc::new(lambertian::new(Vec3::new(0.8, 0.8, 0.8)));
     // let right_leg_front_mat = Arc::new(diffuse_light::new_from_emit_color(Vec3::new(4.0, 4.0, 4.0)));
-    let right_leg_front_tex = Arc::new(image_texture::new("leg_front.png"));
-    let right_leg_front_mat = Arc::new(lambertian::new_with_texture(right_leg_front_tex));
+    let right_leg_front_tex = load("leg_front.png");
+    let right_leg_front_mat = bumped(right_leg_front_tex, 0.3);
     let point1 = Vec3::new(-139.2314,97.7070,29.6537);
     let point2 = Vec3::new(-54.2413,98.2321,28.4672);
     let point3 = Vec3::new(-135.3507,-23.3993,254.0267);
     world.add(Arc::new(Quad::new(point1, point2-point1, point3-point1, right_leg_front_mat.clone())));
     
     //light
-    let light = Arc::new(diffuse_light::new_from_emit_color(Vec3::new(10.0, 10.0, 10.0)));
-    world.add(Arc::new(Sphere::new(Vec3::new(-265.2733,-905.0369,620.6736), 250.0, light.clone())));
+    let (lamp1, lamp1_light) = sphere_light::new_with_sphere(Vec3::new(-265.2733,-905.0369,620.6736), 250.0, Arc::new(diffuse_light::new_from_emit_color(Vec3::new(10.0, 10.0, 10.0))));
+    world.add(lamp1);
     // world.add(Arc::new(Quad::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1000.0), Vec3::new(1000.0, 0.0, 0.0), light.clone())));
-    let light2 = Arc::new(diffuse_light::new_from_emit_color(Vec3::new(10.0, 10.0, 10.0)));
-    world.add(Arc::new(Sphere::new(Vec3::new(1057.1919,-207.2104,224.1840), 220.0, light2.clone())));
-    let light3 = Arc::new(diffuse_light::new_from_emit_color(Vec3::new(10.0, 10.0, 10.0)));
-    world.add(Arc::new(Sphere::new(Vec3::new(955.4125,-972.9311,1373.4002), 320.0, light3.clone())));
+    let (lamp2, lamp2_light) = sphere_light::new_with_sphere(Vec3::new(1057.1919,-207.2104,224.1840), 220.0, Arc::new(diffuse_light::new_from_emit_color(Vec3::new(10.0, 10.0, 10.0))));
+    world.add(lamp2);
+    let (lamp3, lamp3_light) = sphere_light::new_with_sphere(Vec3::new(955.4125,-972.9311,1373.4002), 320.0, Arc::new(diffuse_light::new_from_emit_color(Vec3::new(10.0, 10.0, 10.0))));
+    world.add(lamp3);
 
 
     //grass
-    let grass_tex = Arc::new(image_texture::new("grass.png"));
-    let grass_mat = Arc::new(lambertian::new_with_texture(grass_tex));
+    // one 90 x 40 tile quad with repeat wrapping instead of 3600 separate quads
+    let mut grass_tex = image_texture::new_from_data(assets.load_image("grass.png", true).unwrap_or_else(|e| panic!("{}", e)));
+    grass_tex.wrap = wrap_mode::Repeat;
+    grass_tex.filter = texture_filter::Trilinear;
+    grass_tex.uv_scale = (90.0, 40.0);
+    // height differences one texel apart, in the quad's uv
+    let texel = 1.0 / (grass_tex.width().max(grass_tex.height()) as f64 * 90.0f64.max(40.0));
+    let grass_tex = Arc::new(grass_tex);
+    let mut grass_mat = normal_mapped::new_bump_map(Arc::new(lambertian::new_with_texture(grass_tex.clone())), grass_tex, 0.5);
+    grass_mat.delta = texel;
+    let grass_mat = Arc::new(grass_mat);
     // let grass_mat = Arc::new(lambertian::new(Vec3::new(0.055,0.765,0.22)));   
-    // world.add(Arc::new(Quad::new(Vec3::new(-5500.0, -1500.0, -116.93), Vec3::new(0.0, 8000.0, 0.0), Vec3::new(18000.0, 0.0, 0.0), grass_mat.clone())));
-    for i in 0..40 {
-        for j in 0..90 {
-            let x0 = -5500.0 + i as f64 * 200.0;
-            let z0 = -116.93;
-            let y0 = -1500.0 + j as f64 * 200.0;
-            world.add(Arc::new(Quad::new(Vec3::new(x0 as f64, y0 as f64, z0 as f64), Vec3::new(0.0, 200.0, 0.0), Vec3::new(200.0, 0.0, 0.0), grass_mat.clone())));
-        }
-    }
+    world.add(Arc::new(Quad::new(Vec3::new(-5500.0, -1500.0, -116.93), Vec3::new(0.0, 18000.0, 0.0), Vec3::new(8000.0, 0.0, 0.0), grass_mat.clone())));
 
 
 
@@ -567,16 +661,394 @@ fn mc() {
     cam.samples_per_pixel = 200;
     cam.aspect_ratio = cam.width as f64 / cam.height as f64;
     cam.max_depth = 40;
-    // cam.background = Vec3::new(0.078, 0.76, 1.0);
     cam.background = Vec3::new(0.0, 0.0, 0.0);
+    // afternoon daylight; 7.0 or 17.8 give dawn and dusk
+    let mut sky = preetham_sky::new(Vec3::new(0.0, 0.0, 1.0));
+    sky.turbidity = 3.0;
+    sky.ground_albedo = Vec3::new(0.15, 0.25, 0.1);
+    sky.set_time_of_day(15.0);
+    cam.environment = Some(Arc::new(sky.environment(512, 256)));
+    cam.lights.push(Arc::new(sky.sun()));
     cam.vfov = 20.0;
     cam.lookfrom = Vec3::new(1533.9,-1547.1,1068.3);
     cam.lookat = Vec3::new(152.9,-151.5,403.4);
     cam.vup = Vec3::new(0.0, 0.0, 1.0);
     cam.defocus_angle = 0.0;
+    // aerial perspective: thin, slightly blue haze hugging the ground, forward
+    // scattering so the lights get a halo
+    let mut haze = fog::fog::new_height(Vec3::new(0.00002, 0.00002, 0.00002), Vec3::new(0.00004, 0.00005, 0.00007), -116.93, 1.0 / 600.0, Vec3::new(0.0, 0.0, 1.0));
+    haze.phase = Arc::new(henyey_greenstein::new(0.8));
+    cam.fog = Some(Arc::new(haze));
+    cam.lights.push(Arc::new(lamp1_light));
+    cam.lights.push(Arc::new(lamp2_light));
+    cam.lights.push(Arc::new(lamp3_light));
+    cam.render(Arc::new(world));
+}
+fn layered_materials() {
+    let mut world = HittableList::new();
+    let checker = Arc::new(checker_texture::new_from_colors(0.32,Vec3::new(0.2, 0.3, 0.1), Vec3::new(0.9, 0.9, 0.9)));
+    world.add(Arc::new(Sphere::new(Vec3::new(0.0, -1000.0, 0.0), 1000.0, Arc::new(lambertian::new_with_texture(checker)))));
+
+    // rust over metal, masked by noise
+    let steel = Arc::new(metal::new(Vec3::new(0.8, 0.8, 0.85), 0.05));
+    let rust = Arc::new(lambertian::new(Vec3::new(0.45, 0.2, 0.07)));
+    let rust_mask = Arc::new(noise_texture::new_with_scale(6.0));
+    let rusty = Arc::new(mix_material::new_with_texture(steel, rust, rust_mask));
+    world.add(Arc::new(Sphere::new(Vec3::new(-2.2, 1.0, 0.0), 1.0, rusty)));
+
+    // varnished paint
+    let paint = Arc::new(lambertian::new(Vec3::new(0.7, 0.1, 0.1)));
+    let varnished = Arc::new(coated::new(paint, 1.5));
+    world.add(Arc::new(Sphere::new(Vec3::new(0.0, 1.0, 0.0), 1.0, varnished)));
+
+    // half matte half mirror
+    let half = Arc::new(mix_material::new(Arc::new(lambertian::new(Vec3::new(0.1, 0.3, 0.7))), Arc::new(metal::new(Vec3::new(0.9, 0.9, 0.9), 0.0)), 0.5));
+    world.add(Arc::new(Sphere::new(Vec3::new(2.2, 1.0, 0.0), 1.0, half)));
+
+    let mut cam = Camera::new();
+    cam.width = 800;
+    cam.height = 800;
+    cam.samples_per_pixel = 30;
+    cam.aspect_ratio = cam.width as f64 / cam.height as f64;
+    cam.max_depth = 50;
+    cam.background = Vec3::new(0.7, 0.8, 1.0);
+    cam.vfov = 30.0;
+    cam.lookfrom = Vec3::new(0.0, 3.0, 10.0);
+    cam.lookat = Vec3::new(0.0, 1.0, 0.0);
+    cam.vup = Vec3::new(0.0, 1.0, 0.0);
+    cam.defocus_angle = 0.0;
+    cam.render(Arc::new(world));
+}
+fn noise_textures() {
+    let mut world = HittableList::new();
+    let ground = Arc::new(granite_texture::new(3));
+    world.add(Arc::new(Sphere::new(Vec3::new(0.0, -1000.0, 0.0), 1000.0, Arc::new(lambertian::new_with_texture(ground)))));
+
+    let mut marble = marble_texture::new(1);
+    marble.vein = Vec3::new(0.2, 0.25, 0.3);
+    world.add(Arc::new(Sphere::new(Vec3::new(-3.3, 1.0, 0.0), 1.0, Arc::new(lambertian::new_with_texture(Arc::new(marble))))));
+    world.add(Arc::new(Sphere::new(Vec3::new(-1.1, 1.0, 0.0), 1.0, Arc::new(lambertian::new_with_texture(Arc::new(wood_texture::new(2)))))));
+    world.add(Arc::new(Sphere::new(Vec3::new(1.1, 1.0, 0.0), 1.0, Arc::new(lambertian::new_with_texture(Arc::new(granite_texture::new(4)))))));
+    let mut clouds = clouds_texture::new(5);
+    clouds.scale = 1.5;
+    world.add(Arc::new(Sphere::new(Vec3::new(3.3, 1.0, 0.0), 1.0, Arc::new(lambertian::new_with_texture(Arc::new(clouds))))));
+
+    let mut cam = Camera::new();
+    cam.width = 800;
+    cam.height = 800;
+    cam.samples_per_pixel = 30;
+    cam.aspect_ratio = cam.width as f64 / cam.height as f64;
+    cam.max_depth = 50;
+    cam.background = Vec3::new(0.7, 0.8, 1.0);
+    cam.vfov = 35.0;
+    cam.lookfrom = Vec3::new(0.0, 3.0, 12.0);
+    cam.lookat = Vec3::new(0.0, 1.0, 0.0);
+    cam.vup = Vec3::new(0.0, 1.0, 0.0);
+    cam.defocus_angle = 0.0;
     cam.render(Arc::new(world));
 }
 
+// every texture here comes from scenes/graph_textures.graph
+fn graph_textures() {
+    let path = "scenes/graph_textures.graph";
+    let assets = asset_manager::for_scene_file(path);
+    let mut graph = texture_graph::texture_graph::new(&assets);
+    graph.parse_file(path).unwrap_or_else(|e| panic!("{}", e));
+    let mat = |name: &str| Arc::new(lambertian::new_with_texture(graph.get(name).unwrap_or_else(|| panic!("{} defines no texture '{}'", path, name))));
+
+    let mut world = HittableList::new();
+    world.add(Arc::new(Quad::new(Vec3::new(-20.0, 0.0, 20.0), Vec3::new(40.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -40.0), mat("ground"))));
+    world.add(Arc::new(Sphere::new(Vec3::new(-2.2, 1.0, 0.0), 1.0, mat("stone"))));
+    world.add(Arc::new(Sphere::new(Vec3::new(0.0, 1.0, 0.0), 1.0, mat("meadow"))));
+    world.add(Arc::new(Sphere::new(Vec3::new(2.2, 1.0, 0.0), 1.0, mat("timber"))));
+
+    let mut cam = Camera::new();
+    cam.width = 800;
+    cam.height = 800;
+    cam.samples_per_pixel = 30;
+    cam.aspect_ratio = cam.width as f64 / cam.height as f64;
+    cam.max_depth = 50;
+    cam.background = Vec3::new(0.7, 0.8, 1.0);
+    cam.vfov = 35.0;
+    cam.lookfrom = Vec3::new(0.0, 3.0, 10.0);
+    cam.lookat = Vec3::new(0.0, 1.0, 0.0);
+    cam.vup = Vec3::new(0.0, 1.0, 0.0);
+    cam.defocus_angle = 0.0;
+    cam.render(Arc::new(world));
+}
+
+fn uv_debug() {
+    let mut world = HittableList::new();
+    let ground = Arc::new(uv_checker_texture::new_from_colors(1.0, 1.0, Vec3::new(0.2, 0.2, 0.2), Vec3::new(0.8, 0.8, 0.8)));
+    world.add(Arc::new(Sphere::new(Vec3::new(0.0, -1000.0, 0.0), 1000.0, Arc::new(lambertian::new_with_texture(ground)))));
+
+    let grid = Arc::new(lambertian::new_with_texture(Arc::new(uv_debug_texture::new(8.0))));
+    world.add(Arc::new(Quad::new(Vec3::new(-3.5, 0.0, 0.0), Vec3::new(2.0, 0.0, 0.0), Vec3::new(0.0, 2.0, 0.0), grid.clone())));
+    world.add(Arc::new(Sphere::new(Vec3::new(0.0, 1.0, 0.0), 1.0, grid)));
+    let checker = Arc::new(uv_checker_texture::new_from_colors(8.0, 8.0, Vec3::new(0.9, 0.2, 0.1), Vec3::new(0.9, 0.9, 0.9)));
+    world.add(Arc::new(Triangle::new(Vec3::new(1.5, 0.0, 0.0), Vec3::new(3.5, 0.0, 0.0), Vec3::new(1.5, 2.0, 0.0), Arc::new(lambertian::new_with_texture(checker)))));
+    world.add(Arc::new(Sphere::new(Vec3::new(0.0, 0.5, 2.0), 0.5, Arc::new(lambertian::new_with_texture(Arc::new(normal_texture::new()))))));
+
+    let mut cam = Camera::new();
+    cam.width = 800;
+    cam.height = 800;
+    cam.samples_per_pixel = 30;
+    cam.aspect_ratio = cam.width as f64 / cam.height as f64;
+    cam.max_depth = 50;
+    cam.background = Vec3::new(0.7, 0.8, 1.0);
+    cam.vfov = 35.0;
+    cam.lookfrom = Vec3::new(0.0, 3.0, 12.0);
+    cam.lookat = Vec3::new(0.0, 1.0, 0.0);
+    cam.vup = Vec3::new(0.0, 1.0, 0.0);
+    cam.defocus_angle = 0.0;
+    cam.render(Arc::new(world));
+}
+
+fn fire() {
+    let mut world = HittableList::new();
+    let ground = Arc::new(lambertian::new(Vec3::new(0.5, 0.5, 0.5)));
+    world.add(Arc::new(Sphere::new(Vec3::new(0.0, -1000.0, 0.0), 1000.0, ground)));
+    world.add(Arc::new(Sphere::new(Vec3::new(-2.6, 0.8, 0.5), 0.8, Arc::new(lambertian::new(Vec3::new(0.7, 0.7, 0.7))))));
+    world.add(Arc::new(Sphere::new(Vec3::new(2.6, 0.8, 0.5), 0.8, Arc::new(metal::new(Vec3::new(0.8, 0.8, 0.8), 0.1)))));
+
+    // fireball: hot turbulent core cooling toward the edge, with soot around it
+    let center = Vec3::new(0.0, 1.4, 0.0);
+    let radius = 1.4;
+    let flames = perlin::perlin::new_with_seed(9);
+    let temperature = Arc::new(procedural_density::new(Box::new(move |p: &Vec3| {
+        let r = (*p - center).length() / radius;
+        if r >= 1.0 {
+            return 0.0;
+        }
+        let t = flames.turb(&(*p * 2.5), 6);
+        (2600.0 * (1.0 - r * r) * (0.5 + t)).min(3000.0)
+    }), 3000.0));
+    let density = Arc::new(procedural_density::new_turbulence(center, radius, 2.0, 6.0, 4));
+    let boundary = Arc::new(Sphere::new(center, radius, Arc::new(lambertian::new(Vec3::zero()))));
+    let emission = Arc::new(blackbody_texture::new(temperature, 2.0));
+    world.add(Arc::new(heterogeneous_medium::new_emissive(boundary, density, emission, 0.7, Vec3::new(0.2, 0.2, 0.2))));
+
+    let mut cam = Camera::new();
+    cam.width = 800;
+    cam.height = 800;
+    cam.samples_per_pixel = 100;
+    cam.aspect_ratio = cam.width as f64 / cam.height as f64;
+    cam.max_depth = 50;
+    cam.background = Vec3::new(0.01, 0.01, 0.02);
+    cam.vfov = 35.0;
+    cam.lookfrom = Vec3::new(0.0, 3.0, 12.0);
+    cam.lookat = Vec3::new(0.0, 1.2, 0.0);
+    cam.vup = Vec3::new(0.0, 1.0, 0.0);
+    cam.defocus_angle = 0.0;
+    cam.render(Arc::new(world));
+}
+
+fn subsurface_spheres() {
+    let mut world = HittableList::new();
+    let ground = Arc::new(lambertian::new(Vec3::new(0.4, 0.4, 0.45)));
+    world.add(Arc::new(Sphere::new(Vec3::new(0.0, -1000.0, 0.0), 1000.0, ground)));
+
+    // only the shape of the boundaries matters, subsurface_object replaces their material
+    let shell = Arc::new(lambertian::new(Vec3::zero()));
+    // marble: white, light travels far in every channel
+    let marble = subsurface::new_from_mfp(Vec3::new(0.85, 0.85, 0.83), Vec3::new(0.6, 0.55, 0.5), 1.5);
+    world.add(Arc::new(subsurface_object::new(Arc::new(Sphere::new(Vec3::new(-2.2, 1.0, 0.0), 1.0, shell.clone())), marble)));
+    // skin: red scatters much deeper than green and blue
+    let skin = subsurface::new_from_mfp(Vec3::new(0.8, 0.5, 0.4), Vec3::new(0.37, 0.14, 0.08), 1.4);
+    world.add(Arc::new(subsurface_object::new(Arc::new(Sphere::new(Vec3::new(0.0, 1.0, 0.0), 1.0, shell.clone())), skin)));
+    // wax on a rotated box, a non-spherical boundary
+    let mut wax = subsurface::new_from_mfp(Vec3::new(0.9, 0.75, 0.4), Vec3::new(0.5, 0.4, 0.25), 1.45);
+    wax.phase = henyey_greenstein::new(0.3);
+    let candle = Quad::boxx(Vec3::new(-0.6, 0.0, -0.6), Vec3::new(0.6, 1.8, 0.6), shell.clone());
+    let candle = Arc::new(translate::new(Arc::new(rotate_y::new(candle, 30.0)), Vec3::new(2.2, 0.0, 0.0)));
+    world.add(Arc::new(subsurface_object::new(candle, wax)));
+
+    // back light, so the thin edges glow
+    let light = Arc::new(diffuse_light::new_from_emit_color(Vec3::new(8.0, 8.0, 8.0)));
+    let (panel, panel_light) = quad_light::new_with_quad(Vec3::new(-3.0, 4.0, -4.0), Vec3::new(6.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 2.0), light);
+    world.add(panel);
+
+    let mut cam = Camera::new();
+    cam.width = 800;
+    cam.height = 800;
+    cam.samples_per_pixel = 100;
+    cam.aspect_ratio = cam.width as f64 / cam.height as f64;
+    cam.max_depth = 50;
+    cam.background = Vec3::new(0.05, 0.05, 0.08);
+    cam.vfov = 30.0;
+    cam.lookfrom = Vec3::new(0.0, 3.0, 10.0);
+    cam.lookat = Vec3::new(0.0, 1.0, 0.0);
+    cam.vup = Vec3::new(0.0, 1.0, 0.0);
+    cam.defocus_angle = 0.0;
+    cam.lights.push(Arc::new(panel_light));
+    cam.render(Arc::new(world));
+}
+
+fn punctual_lights() {
+    let mut world = HittableList::new();
+    let ground = Arc::new(lambertian::new(Vec3::new(0.6, 0.6, 0.6)));
+    world.add(Arc::new(Sphere::new(Vec3::new(0.0, -1000.0, 0.0), 1000.0, ground)));
+    let red = Arc::new(lambertian::new(Vec3::new(0.7, 0.15, 0.1)));
+    let blue = Arc::new(lambertian::new(Vec3::new(0.1, 0.2, 0.7)));
+    world.add(Arc::new(Sphere::new(Vec3::new(-2.0, 1.0, 0.0), 1.0, red)));
+    world.add(Arc::new(Sphere::new(Vec3::new(2.0, 1.0, 0.0), 1.0, blue)));
+    world.add(Quad::boxx(Vec3::new(-0.5, 0.0, -0.5), Vec3::new(0.5, 1.5, 0.5), Arc::new(lambertian::new(Vec3::new(0.8, 0.8, 0.8)))));
+
+    let mut cam = Camera::new();
+    cam.width = 800;
+    cam.height = 800;
+    cam.samples_per_pixel = 50;
+    cam.aspect_ratio = cam.width as f64 / cam.height as f64;
+    cam.max_depth = 20;
+    cam.background = Vec3::zero();
+    // none of these are geometry: the shadows are sharp and only the shadow ray is traced
+    cam.lights.push(Arc::new(directional_light::new(Vec3::new(-1.0, 2.0, 1.0), Vec3::new(0.3, 0.35, 0.45))));
+    cam.lights.push(Arc::new(point_light::new(Vec3::new(0.0, 3.0, 3.0), Vec3::new(6.0, 5.0, 4.0))));
+    cam.lights.push(Arc::new(spot_light::new(Vec3::new(-2.0, 5.0, 0.0), Vec3::new(0.0, -1.0, 0.0), Vec3::new(40.0, 35.0, 20.0), 15.0, 25.0)));
+    // a downlight with a bright ring at 30 degrees
+    let profile = ies_profile::new(vec![(0.0, 0.4), (20.0, 0.6), (30.0, 1.0), (40.0, 0.3), (60.0, 0.0)]);
+    cam.lights.push(Arc::new(ies_light::new(Vec3::new(2.0, 4.0, 0.0), Vec3::new(0.0, -1.0, 0.0), Vec3::new(30.0, 30.0, 40.0), profile)));
+    cam.vfov = 35.0;
+    cam.lookfrom = Vec3::new(0.0, 4.0, 10.0);
+    cam.lookat = Vec3::new(0.0, 1.0, 0.0);
+    cam.vup = Vec3::new(0.0, 1.0, 0.0);
+    cam.defocus_angle = 0.0;
+    cam.render(Arc::new(world));
+}
+
+fn city_lights() {
+    let mut world = HittableList::new();
+    let mut lights: Vec<Arc<dyn light::light + Send + Sync>> = Vec::new();
+    let ground = Arc::new(lambertian::new(Vec3::new(0.3, 0.3, 0.3)));
+    world.add(Arc::new(Quad::new(Vec3::new(-40.0, 0.0, -40.0), Vec3::new(80.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 80.0), ground)));
+    let concrete = Arc::new(lambertian::new(Vec3::new(0.5, 0.5, 0.55)));
+
+    // a grid of blocks with lit windows on the faces toward the camera, a few hundred emitters
+    let mut blocks = HittableList::new();
+    for i in -5..5 {
+        for k in -5..5 {
+            let (x, z) = (i as f64 * 2.5, k as f64 * 2.5);
+            let height = 1.0 + 4.0 * random_double();
+            blocks.add(Quad::boxx(Vec3::new(x, 0.0, z), Vec3::new(x + 1.5, height, z + 1.5), concrete.clone()));
+            let floors = (height / 0.6) as usize;
+            for f in 0..floors {
+                for w in 0..2 {
+                    if random_double() < 0.4 {
+                        continue;
+                    }
+                    // four panes with darker frames, lit toward the street only
+                    let panes = Arc::new(uv_checker_texture::new_from_colors(2.0, 2.0, Vec3::new(1.0, 0.75, 0.4), Vec3::new(0.6, 0.45, 0.25)));
+                    let window = diffuse_light::new_with_strength(panes, 4.0 * (0.5 + random_double()), emission_side::Front);
+                    let q = Vec3::new(x + 0.25 + 0.65 * w as f64, 0.25 + 0.6 * f as f64, z + 1.51);
+                    let (u, v) = (Vec3::new(0.35, 0.0, 0.0), Vec3::new(0.0, 0.3, 0.0));
+                    let (pane, pane_light) = quad_light::new_with_quad(q, u, v, Arc::new(window));
+                    lights.push(Arc::new(pane_light));
+                    blocks.add(pane);
+                }
+            }
+        }
+    }
+    world.add(Arc::new(bvh_node::new(blocks)));
+    // faint moonlight from a panel the camera looks straight through
+    let mut moon = diffuse_light::new_with_strength(Arc::new(solid_color::new(Vec3::new(0.6, 0.7, 1.0))), 0.05, emission_side::Front);
+    moon.visible_to_camera = false;
+    let (sky, moonlight) = quad_light::new_with_quad(Vec3::new(-30.0, 20.0, -30.0), Vec3::new(60.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 60.0), Arc::new(moon));
+    lights.push(Arc::new(moonlight));
+    world.add(sky);
+
+    let mut cam = Camera::new();
+    cam.width = 800;
+    cam.height = 450;
+    cam.samples_per_pixel = 32;
+    cam.aspect_ratio = cam.width as f64 / cam.height as f64;
+    cam.max_depth = 10;
+    cam.background = Vec3::new(0.005, 0.007, 0.02);
+    cam.lights = lights;
+    // try Uniform or Power to compare the noise
+    cam.light_sampling = light_sampling::Tree;
+    cam.vfov = 40.0;
+    cam.lookfrom = Vec3::new(4.0, 9.0, 22.0);
+    cam.lookat = Vec3::new(0.0, 1.0, 0.0);
+    cam.vup = Vec3::new(0.0, 1.0, 0.0);
+    cam.defocus_angle = 0.0;
+    cam.render(Arc::new(world));
+}
+
+
+fn caustics() {
+    let mut world = HittableList::new();
+    let white = Arc::new(lambertian::new(Vec3::new(0.73, 0.73, 0.73)));
+    world.add(Arc::new(Quad::new(Vec3::new(-5.0, 0.0, -5.0), Vec3::new(10.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 10.0), white.clone())));
+    world.add(Arc::new(Quad::new(Vec3::new(-5.0, 0.0, -3.0), Vec3::new(10.0, 0.0, 0.0), Vec3::new(0.0, 6.0, 0.0), white.clone())));
+    world.add(Arc::new(Sphere::new(Vec3::new(0.0, 1.0, 0.0), 1.0, Arc::new(dielectric::new(1.5)))));
+    world.add(Arc::new(Sphere::new(Vec3::new(2.2, 0.6, 0.8), 0.6, Arc::new(metal::new(Vec3::new(0.9, 0.8, 0.5), 0.0)))));
+    // a small light off to the side, so the focused spot lands beside the sphere
+    let light = Arc::new(diffuse_light::new_with_strength(Arc::new(solid_color::new(Vec3::ones())), 60.0, emission_side::Front));
+    let (lamp, lamp_light) = quad_light::new_with_quad(Vec3::new(-2.25, 4.0, 0.25), Vec3::new(0.5, 0.0, 0.0), Vec3::new(0.0, 0.0, 0.5), light);
+    world.add(lamp);
+
+    let mut cam = Camera::new();
+    cam.width = 800;
+    cam.height = 600;
+    cam.samples_per_pixel = 32;
+    cam.aspect_ratio = cam.width as f64 / cam.height as f64;
+    cam.max_depth = 8;
+    cam.background = Vec3::zero();
+    cam.lights.push(Arc::new(lamp_light));
+    // the caustics under the glass come from light paths, a path tracer barely finds them
+    cam.integrator = Arc::new(bdpt::bdpt::new());
+    cam.vfov = 40.0;
+    cam.lookfrom = Vec3::new(0.0, 3.0, 8.0);
+    cam.lookat = Vec3::new(0.0, 0.8, 0.0);
+    cam.vup = Vec3::new(0.0, 1.0, 0.0);
+    cam.defocus_angle = 0.0;
+    cam.render(Arc::new(world));
+}
+
+fn shaded_lamp() {
+    let mut world = HittableList::new();
+    let red = Arc::new(lambertian::new(Vec3::new(0.65, 0.05, 0.05)));
+    let white = Arc::new(lambertian::new(Vec3::new(0.73, 0.73, 0.73)));
+    let green = Arc::new(lambertian::new(Vec3::new(0.12, 0.45, 0.15)));
+    let light = Arc::new(diffuse_light::new_with_strength(Arc::new(solid_color::new(Vec3::ones())), 40.0, emission_side::Front));
+
+    world.add(Arc::new(Quad::new(Vec3::new(555.0, 0.0, 0.0), Vec3::new(0.0, 555.0, 0.0), Vec3::new(0.0, 0.0, 555.0), green.clone())));
+    world.add(Arc::new(Quad::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 555.0, 0.0), Vec3::new(0.0, 0.0, 555.0), red.clone())));
+    let (ceiling, ceiling_light) = quad_light::new_with_quad(Vec3::new(343.0, 554.0, 332.0), Vec3::new(-130.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -105.0), light);
+    world.add(ceiling);
+    world.add(Arc::new(Quad::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(555.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 555.0), white.clone())));
+    world.add(Arc::new(Quad::new(Vec3::new(555.0, 555.0, 555.0), Vec3::new(-555.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -555.0), white.clone())));
+    world.add(Arc::new(Quad::new(Vec3::new(0.0, 0.0, 555.0), Vec3::new(555.0, 0.0, 0.0), Vec3::new(0.0, 555.0, 0.0), white.clone())));
+    // a shade around the ceiling light, open only toward the back wall: the room is lit
+    // by what leaks out of the slot, and no shadow ray from the room reaches the light
+    world.add(Arc::new(Quad::new(Vec3::new(193.0, 500.0, 207.0), Vec3::new(170.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 145.0), white.clone())));
+    world.add(Arc::new(Quad::new(Vec3::new(193.0, 500.0, 207.0), Vec3::new(0.0, 55.0, 0.0), Vec3::new(0.0, 0.0, 145.0), white.clone())));
+    world.add(Arc::new(Quad::new(Vec3::new(363.0, 500.0, 207.0), Vec3::new(0.0, 55.0, 0.0), Vec3::new(0.0, 0.0, 145.0), white.clone())));
+    world.add(Arc::new(Quad::new(Vec3::new(193.0, 500.0, 207.0), Vec3::new(170.0, 0.0, 0.0), Vec3::new(0.0, 55.0, 0.0), white.clone())));
+    let box1 = Arc::new(translate::new(Arc::new(rotate_y::new(Quad::boxx(Vec3::zero(), Vec3::new(165.0, 330.0, 165.0), white.clone()), 15.0)), Vec3::new(265.0, 0.0, 295.0)));
+    world.add(box1);
+    let box2 = Arc::new(translate::new(Arc::new(rotate_y::new(Quad::boxx(Vec3::zero(), Vec3::new(165.0, 165.0, 165.0), white.clone()), -18.0)), Vec3::new(130.0, 0.0, 65.0)));
+    world.add(box2);
+
+    let mut cam = Camera::new();
+    cam.width = 600;
+    cam.height = 600;
+    cam.samples_per_pixel = 30;
+    cam.aspect_ratio = cam.width as f64 / cam.height as f64;
+    cam.max_depth = 12;
+    cam.background = Vec3::zero();
+    cam.lights.push(Arc::new(ceiling_light));
+    // once a chain finds the slot it keeps sampling paths through it
+    let mut mlt = metropolis::new(Arc::new(path_tracer::new()));
+    mlt.mutations_per_pixel = 64;
+    cam.integrator = Arc::new(mlt);
+    cam.vfov = 40.0;
+    cam.lookfrom = Vec3::new(278.0, 278.0, -800.0);
+    cam.lookat = Vec3::new(278.0, 278.0, 0.0);
+    cam.vup = Vec3::new(0.0, 1.0, 0.0);
+    cam.defocus_angle = 0.0;
+    cam.render(Arc::new(world));
+}
 
 fn main() {
     match 10 {
@@ -590,6 +1062,16 @@ fn main() {
         8 => cornell_smoke(),
         9 => final_scene(),
         10 => mc(),
+        11 => layered_materials(),
+        12 => noise_textures(),
+        13 => uv_debug(),
+        14 => fire(),
+        15 => subsurface_spheres(),
+        16 => punctual_lights(),
+        17 => city_lights(),
+        18 => caustics(),
+        19 => shaded_lamp(),
+        20 => graph_textures(),
         _ => {}
     }
     
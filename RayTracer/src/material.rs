@@ -2,7 +2,9 @@ use crate::vec3::Vec3;
 use crate::ray::Ray;
 use crate::hittable::{hit_record, Hittable};
 use crate::texture::*;
-use crate::raytracer::random_double;
+use crate::raytracer::{pi, random_double};
+use crate::phase::{phase, isotropic_phase};
+use crate::con_medium::medium_entry;
 use std::rc::Rc;
 use std::sync::Arc;
 
@@ -14,6 +16,59 @@ pub trait Material {
     fn emitted(&self, u: f64, v: f64, p: &Vec3) -> Vec3 {
         Vec3::zero()
     }
+    // emission back along r_in; unlike emitted() this knows which side was hit
+    fn emitted_at(&self, r_in: &Ray, rec: &hit_record) -> Vec3 {
+        self.emitted(rec.u, rec.v, &rec.p)
+    }
+    // false lets camera rays pass through, while the surface still lights the scene
+    fn visible_to_camera(&self) -> bool {
+        true
+    }
+    // density of scatter() producing `scattered`, for weighting directions that
+    // were not sampled by scatter itself (e.g. toward a light). 0 for delta lobes.
+    fn scattering_pdf(&self, r_in: &Ray, rec: &hit_record, scattered: &Ray) -> f64 {
+        0.0
+    }
+    // f * cos toward scattered, for directions scatter() didn't pick (shadow rays,
+    // photon lookups). Materials with a scattering_pdf implement it alongside; the
+    // rest, delta lobes and emitters, have none.
+    fn bsdf_cos(&self, r_in: &Ray, rec: &hit_record, scattered: &Ray) -> Vec3 {
+        Vec3::zero()
+    }
+    // scatter(), also giving the density scattered was drawn with, 0 when it came from a
+    // delta lobe. Only materials with both kinds of lobe know more than scattering_pdf.
+    fn scatter_with_pdf(&self, r_in: &Ray, rec: &hit_record, attenuation: &mut Vec3, scattered: &mut Ray, pdf: &mut f64) -> bool {
+        if !self.scatter(r_in, rec, attenuation, scattered) {
+            return false;
+        }
+        *pdf = self.scattering_pdf(r_in, rec, scattered);
+        true
+    }
+    // whether shadow rays, photon lookups and bdpt joins find anything here, whichever
+    // lobe scatter() took. Every smooth lobe sends some light back along the normal.
+    fn has_smooth_lobe(&self, r_in: &Ray, rec: &hit_record) -> bool {
+        self.scattering_pdf(r_in, rec, &Ray::new_with_time(rec.p, rec.normal, r_in.time())) > 0.0
+    }
+    // scatters inside a medium, where there is no surface normal to take cosines with
+    fn is_volumetric(&self) -> bool {
+        false
+    }
+    // hits where this drops below ALPHA_CUTOFF are skipped by the primitives
+    fn alpha(&self, u: f64, v: f64, p: &Vec3) -> f64 {
+        1.0
+    }
+    // the medium on the back side of surfaces made of this, see medium_volume
+    fn interior(&self) -> Option<&medium_entry> {
+        None
+    }
+    // only marks where a medium starts; rays pass it unchanged
+    fn is_interface(&self) -> bool {
+        false
+    }
+    // Some for plain emitters, which Hittable::emitters turns into lights
+    fn emitter(&self) -> Option<&diffuse_light> {
+        None
+    }
 }
 
 pub struct lambertian {
@@ -40,18 +95,30 @@ impl Material for lambertian {
         // *scattered = Ray::new(rec.p, scatter_direction);
         // *attenuation = self.albedo;
         // println!("rec.u: {}, rec.v: {}", rec.u, rec.v);
-        *attenuation = self.tex.value(rec.u, rec.v, &rec.p);
+        *attenuation = self.tex.value_at(rec);
         return true;
     }
+    fn scattering_pdf(&self, r_in: &Ray, rec: &hit_record, scattered: &Ray) -> f64 {
+        let cos_theta = rec.normal.dot(scattered.direction().normalize());
+        if cos_theta < 0.0 { 0.0 } else { cos_theta / pi }
+    }
+    fn bsdf_cos(&self, r_in: &Ray, rec: &hit_record, scattered: &Ray) -> Vec3 {
+        self.tex.value_at(rec) * self.scattering_pdf(r_in, rec, scattered)
+    }
+    fn alpha(&self, u: f64, v: f64, p: &Vec3) -> f64 {
+        self.tex.alpha(u, v, p)
+    }
 }
 
 pub struct metal {
     pub albedo: Vec3,
     pub fuzz: f64,
+    // cut-out coverage, opaque when None
+    pub alpha_mask: Option<Arc<dyn texture + Send + Sync>>,
 }
 impl metal {
     pub fn new(a: Vec3, f: f64) -> Self {
-        Self { albedo: a, fuzz: f.min(1.0) }    
+        Self { albedo: a, fuzz: f.min(1.0), alpha_mask: None }    
     }
 }
 impl Material for metal {
@@ -66,14 +133,19 @@ impl Material for metal {
         *attenuation = self.albedo;
         return scattered.direction().dot(rec.normal) > 0.0;
     }
+    fn alpha(&self, u: f64, v: f64, p: &Vec3) -> f64 {
+        self.alpha_mask.as_ref().map_or(1.0, |mask| mask.alpha(u, v, p))
+    }
 }
 
 pub struct dielectric {
     pub refraction_index: f64,
+    // cut-out coverage, opaque when None
+    pub alpha_mask: Option<Arc<dyn texture + Send + Sync>>,
 }
 impl dielectric {
     pub fn new(ri: f64) -> Self {
-        Self { refraction_index: ri }
+        Self { refraction_index: ri, alpha_mask: None }
     }
     pub fn reflectance(cosine: f64, ref_idx: f64) -> f64 {
         let r0 = (1.0-ref_idx) / (1.0+ref_idx);
@@ -105,18 +177,43 @@ impl Material for dielectric {
 
         return true;
     }
+    fn alpha(&self, u: f64, v: f64, p: &Vec3) -> f64 {
+        self.alpha_mask.as_ref().map_or(1.0, |mask| mask.alpha(u, v, p))
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum emission_side {
+    // along the outward normal, as in book 3
+    Front,
+    Back,
+    Both,
 }
 
 pub struct diffuse_light {
     pub tex: Arc<dyn texture + Send + Sync>,
+    // scales tex, so the color can stay in [0, 1]
+    pub strength: f64,
+    pub sides: emission_side,
+    pub visible_to_camera: bool,
 }
 
 impl diffuse_light {
     pub fn new(t: Arc<dyn texture + Send + Sync>) -> Self {
-        Self { tex: t }
+        Self { tex: t, strength: 1.0, sides: emission_side::Both, visible_to_camera: true }
     }
     pub fn new_from_emit_color(c: Vec3) -> Self {
-        Self { tex: Arc::new(solid_color::new(c)) }
+        Self::new(Arc::new(solid_color::new(c)))
+    }
+    pub fn new_with_strength(t: Arc<dyn texture + Send + Sync>, strength: f64, sides: emission_side) -> Self {
+        Self { strength, sides, ..Self::new(t) }
+    }
+    pub fn emits_toward(&self, front_face: bool) -> bool {
+        match self.sides {
+            emission_side::Front => front_face,
+            emission_side::Back => !front_face,
+            emission_side::Both => true,
+        }
     }
 }
 
@@ -125,7 +222,22 @@ impl Material for diffuse_light {
         false
     }
     fn emitted(&self, u: f64, v: f64, p: &Vec3) -> Vec3 {
-        self.tex.value(u, v, p)
+        self.tex.value(u, v, p) * self.strength
+    }
+    fn emitted_at(&self, r_in: &Ray, rec: &hit_record) -> Vec3 {
+        if !self.emits_toward(rec.front_face) {
+            return Vec3::zero();
+        }
+        self.tex.value_at(rec) * self.strength
+    }
+    fn visible_to_camera(&self) -> bool {
+        self.visible_to_camera
+    }
+    fn alpha(&self, u: f64, v: f64, p: &Vec3) -> f64 {
+        self.tex.alpha(u, v, p)
+    }
+    fn emitter(&self) -> Option<&diffuse_light> {
+        Some(self)
     }
 }
 
@@ -143,7 +255,437 @@ impl Isotropic {
 impl Material for Isotropic {
     fn scatter(&self, r_in: &Ray, rec: &hit_record, attenuation: &mut Vec3, scattered: &mut Ray) -> bool {
         *scattered = Ray::new_with_time(rec.p, Vec3::random_unit_vector(), r_in.time());
-        *attenuation = self.tex.value(rec.u, rec.v, &rec.p);
+        *attenuation = self.tex.value_at(rec);
+        return true;
+    }
+    fn scattering_pdf(&self, r_in: &Ray, rec: &hit_record, scattered: &Ray) -> f64 {
+        1.0 / (4.0 * pi)
+    }
+    fn bsdf_cos(&self, r_in: &Ray, rec: &hit_record, scattered: &Ray) -> Vec3 {
+        self.tex.value_at(rec) / (4.0 * pi)
+    }
+    fn is_volumetric(&self) -> bool {
+        true
+    }
+    fn alpha(&self, u: f64, v: f64, p: &Vec3) -> f64 {
+        self.tex.alpha(u, v, p)
+    }
+}
+// medium scattering by an arbitrary phase function, e.g. henyey_greenstein
+pub struct Anisotropic {
+    pub tex: Arc<dyn texture + Send + Sync>,
+    pub phase: Arc<dyn phase + Send + Sync>,
+}
+impl Anisotropic {
+    pub fn new(t: Arc<dyn texture + Send + Sync>, phase: Arc<dyn phase + Send + Sync>) -> Self {
+        Self { tex: t, phase }
+    }
+    pub fn new_from_color(c: Vec3, phase: Arc<dyn phase + Send + Sync>) -> Self {
+        Self { tex: Arc::new(solid_color::new(c)), phase }
+    }
+}
+impl Material for Anisotropic {
+    fn scatter(&self, r_in: &Ray, rec: &hit_record, attenuation: &mut Vec3, scattered: &mut Ray) -> bool {
+        *scattered = Ray::new_with_time(rec.p, self.phase.sample(r_in.direction()), r_in.time());
+        *attenuation = self.tex.value_at(rec);
         return true;
     }
-}
\ No newline at end of file
+    fn scattering_pdf(&self, r_in: &Ray, rec: &hit_record, scattered: &Ray) -> f64 {
+        self.phase.evaluate(r_in.direction(), scattered.direction())
+    }
+    fn bsdf_cos(&self, r_in: &Ray, rec: &hit_record, scattered: &Ray) -> Vec3 {
+        self.tex.value_at(rec) * self.scattering_pdf(r_in, rec, scattered)
+    }
+    fn is_volumetric(&self) -> bool {
+        true
+    }
+    fn alpha(&self, u: f64, v: f64, p: &Vec3) -> f64 {
+        self.tex.alpha(u, v, p)
+    }
+}
+pub struct mix_material {
+    pub a: Arc<dyn Material + Send + Sync>,
+    pub b: Arc<dyn Material + Send + Sync>,
+    pub mask: Arc<dyn texture + Send + Sync>,
+}
+impl mix_material {
+    // ratio = 0 gives pure a, ratio = 1 gives pure b
+    pub fn new(a: Arc<dyn Material + Send + Sync>, b: Arc<dyn Material + Send + Sync>, ratio: f64) -> Self {
+        Self { a, b, mask: Arc::new(solid_color::new(Vec3::new(ratio, ratio, ratio))) }
+    }
+    pub fn new_with_texture(a: Arc<dyn Material + Send + Sync>, b: Arc<dyn Material + Send + Sync>, mask: Arc<dyn texture + Send + Sync>) -> Self {
+        Self { a, b, mask }
+    }
+    pub fn weight(&self, u: f64, v: f64, p: &Vec3) -> f64 {
+        let m = self.mask.value(u, v, p);
+        ((m.x + m.y + m.z) / 3.0).max(0.0).min(1.0)
+    }
+    fn pick(&self, rec: &hit_record) -> &Arc<dyn Material + Send + Sync> {
+        if random_double() < self.weight(rec.u, rec.v, &rec.p) {
+            &self.b
+        } else {
+            &self.a
+        }
+    }
+}
+impl Material for mix_material {
+    // scatters like one of the two materials, picked at random by the mask, while
+    // scattering_pdf and bsdf_cos are the mask's blend of both
+    fn scatter(&self, r_in: &Ray, rec: &hit_record, attenuation: &mut Vec3, scattered: &mut Ray) -> bool {
+        let mut pdf = 0.0;
+        self.scatter_with_pdf(r_in, rec, attenuation, scattered, &mut pdf)
+    }
+    fn scatter_with_pdf(&self, r_in: &Ray, rec: &hit_record, attenuation: &mut Vec3, scattered: &mut Ray, pdf: &mut f64) -> bool {
+        if !self.pick(rec).scatter_with_pdf(r_in, rec, attenuation, scattered, pdf) {
+            return false;
+        }
+        // a smooth direction could have come from either material
+        if *pdf > 0.0 {
+            *pdf = self.scattering_pdf(r_in, rec, scattered);
+        }
+        true
+    }
+    fn scattering_pdf(&self, r_in: &Ray, rec: &hit_record, scattered: &Ray) -> f64 {
+        let w = self.weight(rec.u, rec.v, &rec.p);
+        self.a.scattering_pdf(r_in, rec, scattered) * (1.0 - w) + self.b.scattering_pdf(r_in, rec, scattered) * w
+    }
+    fn bsdf_cos(&self, r_in: &Ray, rec: &hit_record, scattered: &Ray) -> Vec3 {
+        let w = self.weight(rec.u, rec.v, &rec.p);
+        self.a.bsdf_cos(r_in, rec, scattered) * (1.0 - w) + self.b.bsdf_cos(r_in, rec, scattered) * w
+    }
+    fn emitted(&self, u: f64, v: f64, p: &Vec3) -> Vec3 {
+        let w = self.weight(u, v, p);
+        self.a.emitted(u, v, p) * (1.0 - w) + self.b.emitted(u, v, p) * w
+    }
+    fn emitted_at(&self, r_in: &Ray, rec: &hit_record) -> Vec3 {
+        let w = self.weight(rec.u, rec.v, &rec.p);
+        self.a.emitted_at(r_in, rec) * (1.0 - w) + self.b.emitted_at(r_in, rec) * w
+    }
+    fn visible_to_camera(&self) -> bool {
+        self.a.visible_to_camera() || self.b.visible_to_camera()
+    }
+    fn alpha(&self, u: f64, v: f64, p: &Vec3) -> f64 {
+        let w = self.weight(u, v, p);
+        self.a.alpha(u, v, p) * (1.0 - w) + self.b.alpha(u, v, p) * w
+    }
+}
+
+pub struct coated {
+    pub base: Arc<dyn Material + Send + Sync>,
+    pub refraction_index: f64,
+}
+impl coated {
+    pub fn new(base: Arc<dyn Material + Send + Sync>, ri: f64) -> Self {
+        Self { base, refraction_index: ri }
+    }
+    // the share of light the clear layer reflects; it only has an outside
+    fn reflectance(&self, r_in: &Ray, rec: &hit_record) -> f64 {
+        if !rec.front_face {
+            return 0.0;
+        }
+        let cos_theta = (r_in.direction().normalize() * -1.0).dot(rec.normal).min(1.0);
+        dielectric::reflectance(cos_theta, 1.0 / self.refraction_index)
+    }
+}
+impl Material for coated {
+    // either a mirror, with probability given by fresnel, or the base beneath. The mirror
+    // is a delta lobe, so scattering_pdf and bsdf_cos are the base's share alone.
+    fn scatter(&self, r_in: &Ray, rec: &hit_record, attenuation: &mut Vec3, scattered: &mut Ray) -> bool {
+        let mut pdf = 0.0;
+        self.scatter_with_pdf(r_in, rec, attenuation, scattered, &mut pdf)
+    }
+    fn scatter_with_pdf(&self, r_in: &Ray, rec: &hit_record, attenuation: &mut Vec3, scattered: &mut Ray, pdf: &mut f64) -> bool {
+        if random_double() >= self.reflectance(r_in, rec) {
+            if !self.base.scatter_with_pdf(r_in, rec, attenuation, scattered, pdf) {
+                return false;
+            }
+            *pdf = *pdf * (1.0 - self.reflectance(r_in, rec));
+            return true;
+        }
+        let unit_direction = r_in.direction().normalize();
+        *scattered = Ray::new_with_time(rec.p, unit_direction.reflect(rec.normal), r_in.time());
+        *attenuation = Vec3::new(1.0, 1.0, 1.0);
+        *pdf = 0.0;
+        true
+    }
+    fn scattering_pdf(&self, r_in: &Ray, rec: &hit_record, scattered: &Ray) -> f64 {
+        self.base.scattering_pdf(r_in, rec, scattered) * (1.0 - self.reflectance(r_in, rec))
+    }
+    fn bsdf_cos(&self, r_in: &Ray, rec: &hit_record, scattered: &Ray) -> Vec3 {
+        self.base.bsdf_cos(r_in, rec, scattered) * (1.0 - self.reflectance(r_in, rec))
+    }
+    fn emitted(&self, u: f64, v: f64, p: &Vec3) -> Vec3 {
+        self.base.emitted(u, v, p)
+    }
+    fn emitted_at(&self, r_in: &Ray, rec: &hit_record) -> Vec3 {
+        self.base.emitted_at(r_in, rec)
+    }
+    fn visible_to_camera(&self) -> bool {
+        self.base.visible_to_camera()
+    }
+    fn alpha(&self, u: f64, v: f64, p: &Vec3) -> f64 {
+        self.base.alpha(u, v, p)
+    }
+}
+
+pub struct normal_mapped {
+    pub base: Arc<dyn Material + Send + Sync>,
+    pub map: Arc<dyn texture + Send + Sync>,
+    pub is_height_map: bool,
+    pub strength: f64,
+    // uv step for the height map finite differences
+    pub delta: f64,
+}
+impl normal_mapped {
+    // tangent space normal map, rgb in [0,1] encodes xyz in [-1,1]
+    pub fn new_normal_map(base: Arc<dyn Material + Send + Sync>, map: Arc<dyn texture + Send + Sync>) -> Self {
+        Self { base, map, is_height_map: false, strength: 1.0, delta: 1.0 / 128.0 }
+    }
+    // height map, like bump_fragment_shader in Games101
+    pub fn new_bump_map(base: Arc<dyn Material + Send + Sync>, height: Arc<dyn texture + Send + Sync>, strength: f64) -> Self {
+        Self { base, map: height, is_height_map: true, strength, delta: 1.0 / 128.0 }
+    }
+    fn height(&self, u: f64, v: f64, p: &Vec3) -> f64 {
+        let c = self.map.value(u, v, p);
+        (c.x + c.y + c.z) / 3.0
+    }
+    pub fn shading_normal(&self, rec: &hit_record) -> Vec3 {
+        let n = rec.normal;
+        if rec.tangent.near_zero() {
+            return n;
+        }
+        let t = (rec.tangent - n * n.dot(rec.tangent)).normalize();
+        let mut b = Vec3::cross(n, t);
+        if b.dot(rec.bitangent) < 0.0 {
+            b = b * -1.0;
+        }
+        let ln = if self.is_height_map {
+            let h = self.height(rec.u, rec.v, &rec.p);
+            let du = self.strength * (self.height(rec.u + self.delta, rec.v, &rec.p) - h);
+            let dv = self.strength * (self.height(rec.u, rec.v + self.delta, &rec.p) - h);
+            Vec3::new(-du, -dv, 1.0)
+        } else {
+            let c = self.map.value(rec.u, rec.v, &rec.p) * 2.0 + (-1.0);
+            Vec3::new(c.x * self.strength, c.y * self.strength, c.z)
+        };
+        let ns = (t * ln.x + b * ln.y + n * ln.z).normalize();
+        if ns.x.is_nan() {
+            return n;
+        }
+        ns
+    }
+}
+impl Material for normal_mapped {
+    fn scatter(&self, r_in: &Ray, rec: &hit_record, attenuation: &mut Vec3, scattered: &mut Ray) -> bool {
+        let mut pdf = 0.0;
+        self.scatter_with_pdf(r_in, rec, attenuation, scattered, &mut pdf)
+    }
+    fn scatter_with_pdf(&self, r_in: &Ray, rec: &hit_record, attenuation: &mut Vec3, scattered: &mut Ray, pdf: &mut f64) -> bool {
+        let ns = self.shading_normal(rec);
+        // rays arriving from behind the shading normal fall back to the geometric normal
+        if r_in.direction().dot(ns) >= 0.0 {
+            return self.base.scatter_with_pdf(r_in, rec, attenuation, scattered, pdf);
+        }
+        let mut shading_rec = rec.clone();
+        shading_rec.normal = ns;
+        if !self.base.scatter_with_pdf(r_in, &shading_rec, attenuation, scattered, pdf) {
+            return false;
+        }
+        // directions on different sides of the shading and geometric surfaces would leak light
+        let d = scattered.direction();
+        (d.dot(ns) > 0.0) == (d.dot(rec.normal) > 0.0)
+    }
+    fn scattering_pdf(&self, r_in: &Ray, rec: &hit_record, scattered: &Ray) -> f64 {
+        let ns = self.shading_normal(rec);
+        if r_in.direction().dot(ns) >= 0.0 {
+            return self.base.scattering_pdf(r_in, rec, scattered);
+        }
+        let d = scattered.direction();
+        if (d.dot(ns) > 0.0) != (d.dot(rec.normal) > 0.0) {
+            return 0.0;
+        }
+        let mut shading_rec = rec.clone();
+        shading_rec.normal = ns;
+        self.base.scattering_pdf(r_in, &shading_rec, scattered)
+    }
+    fn bsdf_cos(&self, r_in: &Ray, rec: &hit_record, scattered: &Ray) -> Vec3 {
+        let ns = self.shading_normal(rec);
+        if r_in.direction().dot(ns) >= 0.0 {
+            return self.base.bsdf_cos(r_in, rec, scattered);
+        }
+        let d = scattered.direction();
+        if (d.dot(ns) > 0.0) != (d.dot(rec.normal) > 0.0) {
+            return Vec3::zero();
+        }
+        let mut shading_rec = rec.clone();
+        shading_rec.normal = ns;
+        self.base.bsdf_cos(r_in, &shading_rec, scattered)
+    }
+    fn emitted(&self, u: f64, v: f64, p: &Vec3) -> Vec3 {
+        self.base.emitted(u, v, p)
+    }
+    fn emitted_at(&self, r_in: &Ray, rec: &hit_record) -> Vec3 {
+        self.base.emitted_at(r_in, rec)
+    }
+    fn visible_to_camera(&self) -> bool {
+        self.base.visible_to_camera()
+    }
+    fn alpha(&self, u: f64, v: f64, p: &Vec3) -> f64 {
+        self.base.alpha(u, v, p)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn floor_hit(mat: Arc<dyn Material + Send + Sync>, p: Vec3) -> (Ray, hit_record) {
+        let r = Ray::new(p + Vec3::new(0.3, 1.0, 0.2), Vec3::new(-0.3, -1.0, -0.2));
+        let mut rec = hit_record::new();
+        rec.p = p;
+        rec.set_face_normal(&r, Vec3::new(0.0, 1.0, 0.0));
+        rec.mat = mat;
+        (r, rec)
+    }
+
+    #[test]
+    fn test_mix_pdf_is_the_blend() {
+        let diffuse: Arc<dyn Material + Send + Sync> = Arc::new(lambertian::new(Vec3::new(0.5, 0.5, 0.5)));
+        let mirror: Arc<dyn Material + Send + Sync> = Arc::new(metal::new(Vec3::ones(), 0.0));
+        let mix: Arc<dyn Material + Send + Sync> = Arc::new(mix_material::new(diffuse, mirror, 0.25));
+        let (r, rec) = floor_hit(mix.clone(), Vec3::zero());
+        let (mut delta, mut smooth) = (0, 0);
+        for _ in 0..400 {
+            let mut attenuation = Vec3::zero();
+            let mut scattered = Ray::new(Vec3::zero(), Vec3::zero());
+            let mut pdf = -1.0;
+            assert!(mix.scatter_with_pdf(&r, &rec, &mut attenuation, &mut scattered, &mut pdf));
+            let cosine = scattered.direction().normalize().y;
+            if pdf > 0.0 {
+                smooth += 1;
+                assert!((pdf - 0.75 * cosine / pi).abs() < 1e-9);
+                assert!((mix.bsdf_cos(&r, &rec, &scattered) - Vec3::ones() * (0.75 * 0.5 * cosine / pi)).near_zero());
+            } else {
+                delta += 1;
+                assert!((scattered.direction().normalize() - r.direction().normalize().reflect(rec.normal)).near_zero());
+            }
+            // the same answer for a ray rebuilt from the vertices, as bdpt does
+            let rebuilt = Ray::new(r.origin() * 2.0 - rec.p, r.direction() * 3.0);
+            assert!((mix.scattering_pdf(&rebuilt, &rec, &scattered) - mix.scattering_pdf(&r, &rec, &scattered)).abs() < 1e-12);
+        }
+        assert!(delta > 60 && smooth > 250);
+        assert!(mix.has_smooth_lobe(&r, &rec));
+    }
+
+    #[test]
+    fn test_coated_base_share() {
+        let base: Arc<dyn Material + Send + Sync> = Arc::new(lambertian::new(Vec3::new(0.8, 0.1, 0.1)));
+        let coat: Arc<dyn Material + Send + Sync> = Arc::new(coated::new(base.clone(), 1.5));
+        let (r, rec) = floor_hit(coat.clone(), Vec3::zero());
+        let toward_light = Ray::new(Vec3::zero(), Vec3::new(0.0, 1.0, 0.0));
+        let cos_theta = r.direction().normalize().y * -1.0;
+        let transmitted = 1.0 - dielectric::reflectance(cos_theta, 1.0 / 1.5);
+        assert!((coat.scattering_pdf(&r, &rec, &toward_light) - transmitted / pi).abs() < 1e-12);
+        assert!((coat.bsdf_cos(&r, &rec, &toward_light) - base.bsdf_cos(&r, &rec, &toward_light) * transmitted).near_zero());
+        // the mirror is picked about as often as fresnel says
+        let n = 20000;
+        let mut mirrored = 0;
+        for _ in 0..n {
+            let mut attenuation = Vec3::zero();
+            let mut scattered = Ray::new(Vec3::zero(), Vec3::zero());
+            let mut pdf = 0.0;
+            assert!(coat.scatter_with_pdf(&r, &rec, &mut attenuation, &mut scattered, &mut pdf));
+            if pdf == 0.0 {
+                mirrored += 1;
+            } else {
+                assert!((pdf - coat.scattering_pdf(&r, &rec, &scattered)).abs() < 1e-12);
+            }
+        }
+        assert!((mirrored as f64 / n as f64 - (1.0 - transmitted)).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_has_smooth_lobe() {
+        let (r, rec) = floor_hit(Arc::new(dielectric::new(1.5)), Vec3::zero());
+        assert!(!rec.mat.has_smooth_lobe(&r, &rec));
+        let (r, rec) = floor_hit(Arc::new(metal::new(Vec3::ones(), 0.3)), Vec3::zero());
+        assert!(!rec.mat.has_smooth_lobe(&r, &rec));
+        let (r, rec) = floor_hit(Arc::new(coated::new(Arc::new(lambertian::new(Vec3::ones())), 1.5)), Vec3::zero());
+        assert!(rec.mat.has_smooth_lobe(&r, &rec));
+    }
+
+    #[test]
+    fn test_alpha_forwarded() {
+        use crate::con_medium::{constant_medium, medium_volume};
+        use crate::phase::henyey_greenstein;
+        use crate::sphere::Sphere;
+        struct left_cut;
+        impl texture for left_cut {
+            fn value(&self, u: f64, v: f64, p: &Vec3) -> Vec3 {
+                Vec3::new(0.5, 0.5, 0.5)
+            }
+            fn alpha(&self, u: f64, v: f64, p: &Vec3) -> f64 {
+                if u < 0.5 { 0.0 } else { 1.0 }
+            }
+        }
+        let mask: Arc<dyn texture + Send + Sync> = Arc::new(left_cut);
+        let mut mirror = metal::new(Vec3::ones(), 0.0);
+        mirror.alpha_mask = Some(mask.clone());
+        let mut glass = dielectric::new(1.5);
+        glass.alpha_mask = Some(mask.clone());
+        let ball = Arc::new(Sphere::new(Vec3::zero(), 1.0, Arc::new(lambertian::new(Vec3::ones()))));
+        let water = medium_volume::new_with_surface(Arc::new(constant_medium::new(ball, 1.0, mask.clone())), 0, Arc::new(glass));
+        let materials: Vec<Arc<dyn Material + Send + Sync>> = vec![
+            Arc::new(mirror),
+            water.interface.clone(),
+            Arc::new(Isotropic::new(mask.clone())),
+            Arc::new(Anisotropic::new(mask.clone(), Arc::new(henyey_greenstein::new(0.5)))),
+        ];
+        for mat in materials {
+            assert_eq!(mat.alpha(0.25, 0.5, &Vec3::zero()), 0.0);
+            assert_eq!(mat.alpha(0.75, 0.5, &Vec3::zero()), 1.0);
+        }
+    }
+
+    #[test]
+    fn test_emits_toward() {
+        let white = || Arc::new(solid_color::new(Vec3::ones()));
+        let front = diffuse_light::new_with_strength(white(), 1.0, emission_side::Front);
+        let back = diffuse_light::new_with_strength(white(), 1.0, emission_side::Back);
+        let both = diffuse_light::new(white());
+        assert!(front.emits_toward(true) && !front.emits_toward(false));
+        assert!(!back.emits_toward(true) && back.emits_toward(false));
+        assert!(both.emits_toward(true) && both.emits_toward(false));
+        // the floor is hit from above, on its front
+        let (r, rec) = floor_hit(Arc::new(back), Vec3::zero());
+        assert!(rec.mat.emitted_at(&r, &rec).near_zero());
+        let (r, rec) = floor_hit(Arc::new(front), Vec3::zero());
+        assert!((rec.mat.emitted_at(&r, &rec) - Vec3::ones()).near_zero());
+    }
+
+    #[test]
+    fn test_normal_mapped_bsdf_cos() {
+        let base: Arc<dyn Material + Send + Sync> = Arc::new(lambertian::new(Vec3::new(0.5, 0.5, 0.5)));
+        // tilted normals, so some directions lie between the shading and geometric surfaces
+        let bumped = Arc::new(normal_mapped::new_normal_map(base, Arc::new(solid_color::new(Vec3::new(0.9, 0.5, 0.7)))));
+        let (r, mut rec) = floor_hit(bumped.clone(), Vec3::zero());
+        rec.tangent = Vec3::new(1.0, 0.0, 0.0);
+        rec.bitangent = Vec3::new(0.0, 0.0, 1.0);
+        let ns = bumped.shading_normal(&rec);
+        let mut leaks = 0;
+        for _ in 0..200 {
+            let d = Vec3::random_unit_vector();
+            let shadow = Ray::new(rec.p, d);
+            let f = bumped.bsdf_cos(&r, &rec, &shadow);
+            if (d.dot(ns) > 0.0) != (d.dot(rec.normal) > 0.0) {
+                leaks += 1;
+                assert!(f.near_zero());
+                continue;
+            }
+            // the lambertian's f * cos about the shading normal, the same every time
+            assert!((f.y - 0.5 * d.dot(ns).max(0.0) / pi).abs() < 1e-12);
+            assert!((bumped.bsdf_cos(&r, &rec, &shadow) - f).near_zero());
+        }
+        assert!(leaks > 0);
+    }
+}
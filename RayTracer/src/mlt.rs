@@ -0,0 +1,305 @@
+use crate::vec3::Vec3;
+use crate::camera::Camera;
+use crate::hittable::Hittable;
+use crate::color::luminance;
+use crate::light_sampler::alias_table;
+use crate::integrator::{integrator, splat};
+use crate::ray::Ray;
+use crate::raytracer::{pi, random_double, sampler, with_sampler};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::sync::{Arc, Mutex};
+use crossbeam::thread;
+
+// Primary sample space Metropolis light transport (Kelemen et al. 2002, as in pbrt).
+// The path tracer is a function of the random numbers it draws; a Markov chain
+// wanders over those numbers, mostly by small perturbations, so once it finds a path
+// that carries light (through a crack, around a corner) it keeps exploring its
+// neighbourhood instead of starting over. Every random_double() on the thread comes
+// from the chain while a path is evaluated (with_sampler), so nothing else needs to
+// know about it.
+//
+// Rejection loops (Vec3::random_in_unit_sphere, random_in_unit_disk, delta tracking
+// in the media) draw a varying count of numbers. A small step that changes how many
+// tries one takes shifts every number after it, so the rest of the path behaves like
+// a large step. The path is still a function of the numbers and the chain stays
+// correct; it just explores less locally through those loops.
+//
+// Paths are ranked by the luminance of everything they add to the image, their own
+// pixel and any splats (bdpt's light paths); the overall brightness comes from a
+// bootstrap of independent paths.
+
+// independent paths for the normalization and the chains' starting points
+const BOOTSTRAP_SAMPLES: usize = 100000;
+const CHAINS: usize = 1024;
+const THREADS: usize = 16;
+
+#[derive(Clone, Copy, Default)]
+struct primary_sample {
+    value: f64,
+    // iteration of the last change, for catching up on skipped small steps
+    last_modified: u64,
+    value_backup: f64,
+    modify_backup: u64,
+}
+
+// The vector of random numbers behind one path, mutated one iteration at a time.
+pub struct mlt_sampler {
+    rng: StdRng,
+    x: Vec<primary_sample>,
+    // width of a small step, in [0, 1)
+    sigma: f64,
+    large_step_probability: f64,
+    iteration: u64,
+    large_step: bool,
+    last_large_step: u64,
+    index: usize,
+}
+
+impl mlt_sampler {
+    pub fn new(seed: u64, sigma: f64, large_step_probability: f64) -> Self {
+        Self {
+            rng: StdRng::seed_from_u64(seed),
+            x: Vec::new(),
+            sigma,
+            large_step_probability,
+            iteration: 0,
+            large_step: true,
+            last_large_step: 0,
+            index: 0,
+        }
+    }
+
+    pub fn reseed(&mut self, seed: u64) {
+        self.rng = StdRng::seed_from_u64(seed);
+    }
+
+    pub fn start_iteration(&mut self) {
+        self.iteration += 1;
+        self.large_step = self.rng.gen::<f64>() < self.large_step_probability;
+        self.index = 0;
+    }
+
+    pub fn accept(&mut self) {
+        if self.large_step {
+            self.last_large_step = self.iteration;
+        }
+    }
+
+    pub fn reject(&mut self) {
+        for x in self.x.iter_mut() {
+            if x.last_modified == self.iteration {
+                x.value = x.value_backup;
+                x.last_modified = x.modify_backup;
+            }
+        }
+        self.iteration -= 1;
+    }
+}
+
+impl sampler for mlt_sampler {
+    fn next(&mut self) -> f64 {
+        let i = self.index;
+        self.index += 1;
+        let (iteration, last_large_step, large_step, sigma) = (self.iteration, self.last_large_step, self.large_step, self.sigma);
+        let rng = &mut self.rng;
+        // a number the path never asked for before is a fresh one
+        while self.x.len() <= i {
+            self.x.push(primary_sample { value: rng.gen(), last_modified: iteration, ..primary_sample::default() });
+        }
+        let x = &mut self.x[i];
+        // untouched since the last accepted large step, which would have redrawn it
+        if x.last_modified < last_large_step {
+            x.value = rng.gen();
+            x.last_modified = last_large_step;
+        }
+        x.value_backup = x.value;
+        x.modify_backup = x.last_modified;
+        if large_step {
+            x.value = rng.gen();
+        } else {
+            // all the small steps it missed at once
+            let n = (iteration - x.last_modified) as f64;
+            let (u1, u2): (f64, f64) = (1.0 - rng.gen::<f64>(), rng.gen());
+            let normal = (-2.0 * u1.ln()).sqrt() * (2.0 * pi * u2).cos();
+            x.value += normal * sigma * n.sqrt();
+            x.value -= x.value.floor();
+        }
+        x.last_modified = iteration;
+        x.value
+    }
+}
+
+// Metropolis light transport over inner, the path tracer or bdpt: mutations_per_pixel
+// steps per pixel in all, mutation_size the width of a small step in primary sample
+// space.
+pub struct metropolis {
+    pub inner: Arc<dyn integrator + Send + Sync>,
+    pub mutations_per_pixel: usize,
+    pub mutation_size: f64,
+    pub large_step_probability: f64,
+}
+
+impl metropolis {
+    pub fn new(inner: Arc<dyn integrator + Send + Sync>) -> Self {
+        Self { inner, mutations_per_pixel: 100, mutation_size: 0.01, large_step_probability: 0.3 }
+    }
+}
+
+impl integrator for metropolis {
+    fn prepare(&self, cam: &Camera, world: &Arc<dyn Hittable + Send + Sync>) -> Option<Arc<dyn integrator + Send + Sync>> {
+        let inner = self.inner.prepare(cam, world)?;
+        Some(Arc::new(Self { inner, ..*self }))
+    }
+    fn li(&self, cam: &Camera, r: &Ray, world: &Arc<dyn Hittable + Send + Sync>, splats: &mut Vec<splat>) -> Vec3 {
+        self.inner.li(cam, r, world, splats)
+    }
+    fn render(&self, cam: &Camera, world: &Arc<dyn Hittable + Send + Sync>) -> Option<Vec<Vec3>> {
+        Some(render(cam, world, self))
+    }
+}
+
+// one path through the camera, driven by sampler: (pixel, radiance) for the pixel it
+// was traced through and every pixel it splatted onto
+fn evaluate(cam: &Camera, world: &Arc<dyn Hittable + Send + Sync>, inner: &Arc<dyn integrator + Send + Sync>, sampler: mlt_sampler) -> (mlt_sampler, Vec<(usize, Vec3)>) {
+    let (sampler, (pixel, l, splats)) = with_sampler(sampler, || {
+        let x = ((random_double() * cam.width as f64) as usize).min(cam.width - 1);
+        let y = ((random_double() * cam.height as f64) as usize).min(cam.height - 1);
+        let r = cam.get_ray(x, y);
+        let mut splats = Vec::new();
+        let l = inner.li(cam, &r, world, &mut splats);
+        (y * cam.width + x, l, splats)
+    });
+    let mut path = vec![(pixel, l)];
+    path.extend(splats.into_iter().map(|(x, y, c)| (y * cam.width + x, c)));
+    path.retain(|(_, c)| c.x.is_finite() && c.y.is_finite() && c.z.is_finite());
+    (sampler, path)
+}
+
+fn importance(path: &[(usize, Vec3)]) -> f64 {
+    path.iter().map(|(_, c)| luminance(*c)).sum::<f64>().max(0.0)
+}
+
+// The image, linear, from mutations_per_pixel steps per pixel spread over the chains.
+// cam must be initialized.
+pub fn render(cam: &Camera, world: &Arc<dyn Hittable + Send + Sync>, m: &metropolis) -> Vec<Vec3> {
+    let (sigma, large, inner) = (m.mutation_size, m.large_step_probability, &m.inner);
+    let pixels = cam.width * cam.height;
+
+    let weights = Arc::new(Mutex::new(vec![0.0; BOOTSTRAP_SAMPLES]));
+    thread::scope(|scope| {
+        for t in 0..THREADS {
+            let weights = Arc::clone(&weights);
+            scope.spawn(move |_| {
+                let mut local = Vec::new();
+                for i in (t..BOOTSTRAP_SAMPLES).step_by(THREADS) {
+                    let (_, path) = evaluate(cam, world, inner, mlt_sampler::new(i as u64, sigma, large));
+                    local.push((i, importance(&path)));
+                }
+                let mut weights = weights.lock().unwrap();
+                for (i, w) in local {
+                    weights[i] = w;
+                }
+            });
+        }
+    })
+    .unwrap();
+    let weights = weights.lock().unwrap().clone();
+    let b = weights.iter().sum::<f64>() / BOOTSTRAP_SAMPLES as f64;
+    if b <= 0.0 {
+        return vec![Vec3::zero(); pixels];
+    }
+    let bootstrap = alias_table::new(&weights);
+
+    let total = m.mutations_per_pixel * pixels;
+    let bar = Camera::get_ProgressBar(1, CHAINS);
+    let film = Arc::new(Mutex::new(vec![Vec3::zero(); pixels]));
+    thread::scope(|scope| {
+        for t in 0..THREADS {
+            let (film, bootstrap, bar) = (Arc::clone(&film), &bootstrap, &bar);
+            scope.spawn(move |_| {
+                let mut local = vec![Vec3::zero(); pixels];
+                for chain in (t..CHAINS).step_by(THREADS) {
+                    // the chain's share of the mutations, the remainder to the first ones
+                    let steps = total / CHAINS + if chain < total % CHAINS { 1 } else { 0 };
+                    // replay the bootstrap path, then go on with the chain's own numbers
+                    let start = bootstrap.sample();
+                    let (mut sampler, mut current) = evaluate(cam, world, inner, mlt_sampler::new(start as u64, sigma, large));
+                    sampler.reseed((BOOTSTRAP_SAMPLES + chain) as u64);
+                    let mut i_current = importance(&current);
+                    for _ in 0..steps {
+                        sampler.start_iteration();
+                        let (next, proposed) = evaluate(cam, world, inner, sampler);
+                        sampler = next;
+                        let i_proposed = importance(&proposed);
+                        let accept = if i_current > 0.0 { (i_proposed / i_current).min(1.0).max(0.0) } else { 1.0 };
+                        // both states contribute by their expected share
+                        if i_proposed > 0.0 {
+                            for (pixel, c) in &proposed {
+                                local[*pixel] += *c * (accept / i_proposed);
+                            }
+                        }
+                        if i_current > 0.0 {
+                            for (pixel, c) in &current {
+                                local[*pixel] += *c * ((1.0 - accept) / i_current);
+                            }
+                        }
+                        if random_double() < accept {
+                            current = proposed;
+                            i_current = i_proposed;
+                            sampler.accept();
+                        } else {
+                            sampler.reject();
+                        }
+                    }
+                    bar.inc(1);
+                }
+                let mut film = film.lock().unwrap();
+                for (pixel, c) in film.iter_mut().zip(local) {
+                    *pixel += c;
+                }
+            });
+        }
+    })
+    .unwrap();
+    bar.finish();
+
+    // each pixel holds its share of total mutations, each weighted 1 / luminance
+    let scale = b * pixels as f64 / total as f64;
+    let film = film.lock().unwrap();
+    film.iter().map(|c| *c * scale).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reject_restores_samples() {
+        let mut sampler = mlt_sampler::new(7, 0.01, 0.3);
+        let initial: Vec<f64> = (0..8).map(|_| sampler.next()).collect();
+        for _ in 0..20 {
+            sampler.start_iteration();
+            let proposed: Vec<f64> = (0..8).map(|_| sampler.next()).collect();
+            assert!(proposed.iter().all(|u| (0.0..1.0).contains(u)));
+            sampler.reject();
+        }
+        // nothing was accepted, so the first state is still there
+        let x: Vec<f64> = sampler.x.iter().map(|x| x.value).collect();
+        assert_eq!(x, initial);
+    }
+
+    #[test]
+    fn test_with_sampler_replays_the_path() {
+        // the same numbers come back through random_double() after a rejected step
+        let sampler = mlt_sampler::new(3, 0.01, 0.3);
+        let (mut sampler, first) = with_sampler(sampler, || (0..5).map(|_| random_double()).collect::<Vec<f64>>());
+        sampler.start_iteration();
+        let (mut sampler, _) = with_sampler(sampler, || (0..5).map(|_| random_double()).collect::<Vec<f64>>());
+        sampler.reject();
+        let x: Vec<f64> = sampler.x.iter().map(|x| x.value).collect();
+        assert_eq!(x, first);
+        // and the thread's own generator afterwards
+        assert_ne!(random_double(), first[0]);
+    }
+}
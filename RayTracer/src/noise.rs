@@ -0,0 +1,280 @@
+use crate::vec3::Vec3;
+use crate::perlin::perlin;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+
+// 3D noise, roughly in [-1, 1] unless noted otherwise
+pub trait noise {
+    fn sample(&self, p: &Vec3) -> f64;
+}
+
+impl noise for perlin {
+    fn sample(&self, p: &Vec3) -> f64 {
+        self.noise(p)
+    }
+}
+
+// integer lattice hash, used by the noises that don't keep a permutation table
+fn hash(i: i64, j: i64, k: i64, seed: u64) -> u64 {
+    let mut h = seed
+        ^ (i as u64).wrapping_mul(0x9E3779B97F4A7C15)
+        ^ (j as u64).wrapping_mul(0xC2B2AE3D27D4EB4F)
+        ^ (k as u64).wrapping_mul(0x165667B19E3779F9);
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xFF51AFD7ED558CCD);
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xC4CEB9FE1A85EC53);
+    h ^= h >> 33;
+    h
+}
+
+fn hash_to_unit(h: u64) -> f64 {
+    (h >> 11) as f64 / (1u64 << 53) as f64
+}
+
+fn smoothstep(t: f64) -> f64 {
+    t * t * (3.0 - 2.0 * t)
+}
+
+fn lerp(a: f64, b: f64, t: f64) -> f64 {
+    a + (b - a) * t
+}
+
+pub struct value_noise {
+    pub seed: u64,
+}
+
+impl value_noise {
+    pub fn new(seed: u64) -> Self {
+        Self { seed }
+    }
+    fn lattice(&self, i: i64, j: i64, k: i64) -> f64 {
+        hash_to_unit(hash(i, j, k, self.seed)) * 2.0 - 1.0
+    }
+}
+
+impl noise for value_noise {
+    fn sample(&self, p: &Vec3) -> f64 {
+        let (i, j, k) = (p.x.floor() as i64, p.y.floor() as i64, p.z.floor() as i64);
+        let u = smoothstep(p.x - p.x.floor());
+        let v = smoothstep(p.y - p.y.floor());
+        let w = smoothstep(p.z - p.z.floor());
+        let x00 = lerp(self.lattice(i, j, k), self.lattice(i + 1, j, k), u);
+        let x10 = lerp(self.lattice(i, j + 1, k), self.lattice(i + 1, j + 1, k), u);
+        let x01 = lerp(self.lattice(i, j, k + 1), self.lattice(i + 1, j, k + 1), u);
+        let x11 = lerp(self.lattice(i, j + 1, k + 1), self.lattice(i + 1, j + 1, k + 1), u);
+        lerp(lerp(x00, x10, v), lerp(x01, x11, v), w)
+    }
+}
+
+const GRAD3: [[f64; 3]; 12] = [
+    [1.0, 1.0, 0.0], [-1.0, 1.0, 0.0], [1.0, -1.0, 0.0], [-1.0, -1.0, 0.0],
+    [1.0, 0.0, 1.0], [-1.0, 0.0, 1.0], [1.0, 0.0, -1.0], [-1.0, 0.0, -1.0],
+    [0.0, 1.0, 1.0], [0.0, -1.0, 1.0], [0.0, 1.0, -1.0], [0.0, -1.0, -1.0],
+];
+
+// Gustavson's 3D simplex noise
+pub struct simplex_noise {
+    perm: Vec<usize>,
+}
+
+impl simplex_noise {
+    pub fn new(seed: u64) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut p: Vec<usize> = (0..256).collect();
+        p.shuffle(&mut rng);
+        let perm = (0..512).map(|i| p[i & 255]).collect();
+        Self { perm }
+    }
+    fn corner(&self, ii: usize, jj: usize, kk: usize, x: f64, y: f64, z: f64) -> f64 {
+        let t = 0.6 - x * x - y * y - z * z;
+        if t < 0.0 {
+            return 0.0;
+        }
+        let g = GRAD3[self.perm[ii + self.perm[jj + self.perm[kk]]] % 12];
+        let t2 = t * t;
+        t2 * t2 * (g[0] * x + g[1] * y + g[2] * z)
+    }
+}
+
+impl noise for simplex_noise {
+    fn sample(&self, p: &Vec3) -> f64 {
+        let f3 = 1.0 / 3.0;
+        let g3 = 1.0 / 6.0;
+        // skew into the simplex cell
+        let s = (p.x + p.y + p.z) * f3;
+        let i = (p.x + s).floor();
+        let j = (p.y + s).floor();
+        let k = (p.z + s).floor();
+        let t = (i + j + k) * g3;
+        let x0 = p.x - (i - t);
+        let y0 = p.y - (j - t);
+        let z0 = p.z - (k - t);
+
+        // which of the six tetrahedra we are in
+        let (i1, j1, k1, i2, j2, k2) = if x0 >= y0 {
+            if y0 >= z0 { (1, 0, 0, 1, 1, 0) }
+            else if x0 >= z0 { (1, 0, 0, 1, 0, 1) }
+            else { (0, 0, 1, 1, 0, 1) }
+        } else {
+            if y0 < z0 { (0, 0, 1, 0, 1, 1) }
+            else if x0 < z0 { (0, 1, 0, 0, 1, 1) }
+            else { (0, 1, 0, 1, 1, 0) }
+        };
+
+        let x1 = x0 - i1 as f64 + g3;
+        let y1 = y0 - j1 as f64 + g3;
+        let z1 = z0 - k1 as f64 + g3;
+        let x2 = x0 - i2 as f64 + 2.0 * g3;
+        let y2 = y0 - j2 as f64 + 2.0 * g3;
+        let z2 = z0 - k2 as f64 + 2.0 * g3;
+        let x3 = x0 - 1.0 + 3.0 * g3;
+        let y3 = y0 - 1.0 + 3.0 * g3;
+        let z3 = z0 - 1.0 + 3.0 * g3;
+
+        let ii = (i as i64 & 255) as usize;
+        let jj = (j as i64 & 255) as usize;
+        let kk = (k as i64 & 255) as usize;
+        let n0 = self.corner(ii, jj, kk, x0, y0, z0);
+        let n1 = self.corner(ii + i1, jj + j1, kk + k1, x1, y1, z1);
+        let n2 = self.corner(ii + i2, jj + j2, kk + k2, x2, y2, z2);
+        let n3 = self.corner(ii + 1, jj + 1, kk + 1, x3, y3, z3);
+        32.0 * (n0 + n1 + n2 + n3)
+    }
+}
+
+// cellular noise, one jittered feature point per unit cell
+pub struct worley_noise {
+    pub seed: u64,
+    // 0 gives a regular grid, 1 fully random points
+    pub jitter: f64,
+}
+
+impl worley_noise {
+    pub fn new(seed: u64) -> Self {
+        Self { seed, jitter: 1.0 }
+    }
+    // distances to the closest and second closest feature points
+    pub fn cellular(&self, p: &Vec3) -> (f64, f64) {
+        let (i, j, k) = (p.x.floor() as i64, p.y.floor() as i64, p.z.floor() as i64);
+        let mut f1 = f64::INFINITY;
+        let mut f2 = f64::INFINITY;
+        for di in -1..=1 {
+            for dj in -1..=1 {
+                for dk in -1..=1 {
+                    let (ci, cj, ck) = (i + di, j + dj, k + dk);
+                    let h = hash(ci, cj, ck, self.seed);
+                    let offset = Vec3::new(
+                        hash_to_unit(h),
+                        hash_to_unit(h.rotate_left(21)),
+                        hash_to_unit(h.rotate_left(42)),
+                    );
+                    let feature = Vec3::new(ci as f64, cj as f64, ck as f64) + (offset * self.jitter + (1.0 - self.jitter) * 0.5);
+                    let d = (feature - *p).length();
+                    if d < f1 {
+                        f2 = f1;
+                        f1 = d;
+                    } else if d < f2 {
+                        f2 = d;
+                    }
+                }
+            }
+        }
+        (f1, f2)
+    }
+}
+
+impl noise for worley_noise {
+    // F1 distance mapped onto [-1, 1]; the closest point is never further than the far
+    // corner of p's own cell, sqrt(3) away. cellular() has the raw distances.
+    fn sample(&self, p: &Vec3) -> f64 {
+        (2.0 * self.cellular(p).0 / 3.0f64.sqrt() - 1.0).min(1.0)
+    }
+}
+
+// fractal brownian motion, normalized back to the range of the base noise
+pub fn fbm(n: &dyn noise, p: &Vec3, octaves: usize, lacunarity: f64, gain: f64) -> f64 {
+    let mut sum = 0.0;
+    let mut norm = 0.0;
+    let mut amplitude = 1.0;
+    let mut q = *p;
+    for _ in 0..octaves {
+        sum += n.sample(&q) * amplitude;
+        norm += amplitude;
+        amplitude *= gain;
+        q = q * lacunarity;
+    }
+    if norm > 0.0 { sum / norm } else { 0.0 }
+}
+
+// sum of |noise| octaves, the classic Perlin turbulence
+pub fn turbulence(n: &dyn noise, p: &Vec3, octaves: usize) -> f64 {
+    let mut sum = 0.0;
+    let mut weight = 1.0;
+    let mut q = *p;
+    for _ in 0..octaves {
+        sum += n.sample(&q).abs() * weight;
+        weight *= 0.5;
+        q = q * 2.0;
+    }
+    sum
+}
+
+// Musgrave's ridged multifractal, roughly in [0, 1]
+pub fn ridged_multifractal(n: &dyn noise, p: &Vec3, octaves: usize, lacunarity: f64, gain: f64, offset: f64) -> f64 {
+    let mut q = *p;
+    let mut signal = offset - n.sample(&q).abs();
+    signal *= signal;
+    let mut result = signal;
+    let mut amplitude = 1.0;
+    let mut norm = 1.0;
+    for _ in 1..octaves {
+        q = q * lacunarity;
+        let weight = (signal * gain).max(0.0).min(1.0);
+        signal = offset - n.sample(&q).abs();
+        signal *= signal * weight;
+        amplitude /= lacunarity;
+        result += signal * amplitude;
+        norm += amplitude;
+    }
+    result / (norm * offset * offset)
+}
+
+// offsets p by a noise valued vector field
+pub fn domain_warp(n: &dyn noise, p: &Vec3, strength: f64) -> Vec3 {
+    let wx = n.sample(&(*p + Vec3::new(5.2, 1.3, 2.8)));
+    let wy = n.sample(&(*p + Vec3::new(1.7, 9.2, 3.1)));
+    let wz = n.sample(&(*p + Vec3::new(8.3, 2.8, 4.7)));
+    *p + Vec3::new(wx, wy, wz) * strength
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seeded_noise_is_reproducible() {
+        let p = Vec3::new(1.3, -2.7, 0.45);
+        assert_eq!(perlin::new_with_seed(7).noise(&p), perlin::new_with_seed(7).noise(&p));
+        assert_eq!(simplex_noise::new(7).sample(&p), simplex_noise::new(7).sample(&p));
+        assert_ne!(simplex_noise::new(7).sample(&p), simplex_noise::new(8).sample(&p));
+    }
+
+    #[test]
+    fn test_noise_ranges() {
+        let simplex = simplex_noise::new(1);
+        let value = value_noise::new(1);
+        let cells = worley_noise::new(1);
+        for i in 0..1000 {
+            let p = Vec3::new(i as f64 * 0.173, i as f64 * -0.291, i as f64 * 0.057);
+            assert!(simplex.sample(&p).abs() <= 1.0);
+            assert!(value.sample(&p).abs() <= 1.0);
+            let (f1, f2) = cells.cellular(&p);
+            assert!(f1 >= 0.0 && f1 <= f2);
+            assert!(cells.sample(&p).abs() <= 1.0);
+            let r = ridged_multifractal(&simplex, &p, 5, 2.0, 2.0, 1.0);
+            assert!((0.0..=1.0).contains(&r));
+        }
+    }
+}
@@ -0,0 +1,29 @@
+use crate::vec3::Vec3;
+
+// orthonormal basis with w along the given direction
+pub struct onb {
+    pub axis: [Vec3; 3],
+}
+
+impl onb {
+    pub fn new(n: Vec3) -> Self {
+        let w = n.normalize();
+        let a = if w.x.abs() > 0.9 { Vec3::new(0.0, 1.0, 0.0) } else { Vec3::new(1.0, 0.0, 0.0) };
+        let v = Vec3::cross(w, a).normalize();
+        let u = Vec3::cross(w, v);
+        Self { axis: [u, v, w] }
+    }
+    pub fn u(&self) -> Vec3 {
+        self.axis[0]
+    }
+    pub fn v(&self) -> Vec3 {
+        self.axis[1]
+    }
+    pub fn w(&self) -> Vec3 {
+        self.axis[2]
+    }
+    // local coordinates to world
+    pub fn transform(&self, v: Vec3) -> Vec3 {
+        self.axis[0] * v.x + self.axis[1] * v.y + self.axis[2] * v.z
+    }
+}
@@ -1,5 +1,8 @@
 use crate::vec3::Vec3;
 use crate::raytracer;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
 
 // static const int point_count = 256;
 const point_count: i32 = 256;
@@ -27,6 +30,25 @@ impl perlin {
             randvec,
         }
     }
+    // same lattice for the same seed, so procedural looks are reproducible
+    pub fn new_with_seed(seed: u64) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut randvec = vec![Vec3::zero(); point_count as usize];
+        for i in 0..point_count {
+            randvec[i as usize] = Vec3::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0)).normalize();
+        }
+        let mut seeded_perm = || {
+            let mut p: Vec<i32> = (0..point_count).collect();
+            p.shuffle(&mut rng);
+            p
+        };
+        Self {
+            perm_x: seeded_perm(),
+            perm_y: seeded_perm(),
+            perm_z: seeded_perm(),
+            randvec,
+        }
+    }
 
     pub fn noise(&self, p: &Vec3) -> f64 {
         // let i = (4.0 * p.x) as i32 & 255;
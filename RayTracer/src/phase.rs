@@ -0,0 +1,132 @@
+use crate::vec3::Vec3;
+use crate::onb::onb;
+use crate::raytracer::{pi, random_double};
+
+// Angular distribution of light scattered in a medium. cos_theta is measured between
+// the propagation direction of the incoming ray and the scattered direction, so
+// forward scattering means cos_theta near 1.
+pub trait phase {
+    // density per steradian, integrates to 1 over the sphere
+    fn value(&self, cos_theta: f64) -> f64;
+    // cos_theta drawn proportionally to value
+    fn sample_cos_theta(&self) -> f64;
+
+    fn evaluate(&self, w_in: Vec3, w_out: Vec3) -> f64 {
+        self.value(w_in.normalize().dot(w_out.normalize()))
+    }
+    // a scattered direction for a ray travelling along w_in
+    fn sample(&self, w_in: Vec3) -> Vec3 {
+        let cos_theta = self.sample_cos_theta().max(-1.0).min(1.0);
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * pi * random_double();
+        let uvw = onb::new(w_in);
+        uvw.transform(Vec3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta))
+    }
+}
+
+pub struct isotropic_phase {}
+
+impl isotropic_phase {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+impl phase for isotropic_phase {
+    fn value(&self, cos_theta: f64) -> f64 {
+        1.0 / (4.0 * pi)
+    }
+    fn sample_cos_theta(&self) -> f64 {
+        1.0 - 2.0 * random_double()
+    }
+}
+
+// g in (-1, 1): > 0 scatters forward, < 0 backward, 0 is isotropic
+pub struct henyey_greenstein {
+    pub g: f64,
+}
+
+impl henyey_greenstein {
+    pub fn new(g: f64) -> Self {
+        Self { g: g.max(-0.999).min(0.999) }
+    }
+}
+
+impl phase for henyey_greenstein {
+    fn value(&self, cos_theta: f64) -> f64 {
+        let g = self.g;
+        let denom = 1.0 + g * g - 2.0 * g * cos_theta;
+        (1.0 - g * g) / (4.0 * pi * denom * denom.sqrt())
+    }
+    fn sample_cos_theta(&self) -> f64 {
+        let g = self.g;
+        let xi = random_double();
+        if g.abs() < 1e-3 {
+            return 1.0 - 2.0 * xi;
+        }
+        let s = (1.0 - g * g) / (1.0 - g + 2.0 * g * xi);
+        (1.0 + g * g - s * s) / (2.0 * g)
+    }
+}
+
+// weight * hg(g1) + (1 - weight) * hg(g2), usually a strong forward lobe plus a
+// weaker backward one as in clouds
+pub struct double_henyey_greenstein {
+    pub forward: henyey_greenstein,
+    pub backward: henyey_greenstein,
+    pub weight: f64,
+}
+
+impl double_henyey_greenstein {
+    pub fn new(g1: f64, g2: f64, weight: f64) -> Self {
+        Self { forward: henyey_greenstein::new(g1), backward: henyey_greenstein::new(g2), weight: weight.max(0.0).min(1.0) }
+    }
+}
+
+impl phase for double_henyey_greenstein {
+    fn value(&self, cos_theta: f64) -> f64 {
+        self.weight * self.forward.value(cos_theta) + (1.0 - self.weight) * self.backward.value(cos_theta)
+    }
+    fn sample_cos_theta(&self) -> f64 {
+        if random_double() < self.weight {
+            self.forward.sample_cos_theta()
+        } else {
+            self.backward.sample_cos_theta()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hg_normalized() {
+        for g in [-0.8, 0.0, 0.3, 0.9] {
+            let hg = henyey_greenstein::new(g);
+            // integrate over the sphere: 2 pi * integral over cos_theta
+            let n = 100000;
+            let mut sum = 0.0;
+            for i in 0..n {
+                let c = -1.0 + 2.0 * (i as f64 + 0.5) / n as f64;
+                sum += hg.value(c) * 2.0 / n as f64;
+            }
+            assert!((sum * 2.0 * pi - 1.0).abs() < 1e-3, "g = {}", g);
+        }
+    }
+
+    #[test]
+    fn test_sampling_mean_cosine_is_g() {
+        let w_in = Vec3::new(0.3, -0.5, 0.8).normalize();
+        let phases: [(Box<dyn phase>, f64); 3] = [
+            (Box::new(henyey_greenstein::new(0.7)), 0.7),
+            (Box::new(henyey_greenstein::new(-0.4)), -0.4),
+            (Box::new(double_henyey_greenstein::new(0.8, -0.3, 0.75)), 0.75 * 0.8 - 0.25 * 0.3),
+        ];
+        for (p, mean) in phases.iter() {
+            let n = 50000;
+            let sum: f64 = (0..n).map(|_| p.sample(w_in).dot(w_in)).sum();
+            assert!((sum / n as f64 - mean).abs() < 0.02);
+        }
+    }
+}
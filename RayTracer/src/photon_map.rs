@@ -0,0 +1,488 @@
+use crate::aabb::aabb;
+use crate::camera::Camera;
+use crate::hittable::{hit_record, Hittable};
+use crate::integrator::{integrator, path_tracer, splat};
+use crate::interval::Interval;
+use crate::light::light;
+use crate::light_sampler::alias_table;
+use crate::ray::Ray;
+use crate::raytracer::{pi, random_double};
+use crate::vec3::Vec3;
+use crossbeam::thread;
+use std::sync::Arc;
+
+// Caustic photon maps (Jensen 1996) with progressive radius reduction (Knaus and
+// Zwicker 2011). Photons leave the lights, bounce off mirrors and glass, and are kept
+// where they first reach a diffuse surface after at least one such bounce. Camera
+// paths then read that light from the map by density estimation, and drop the
+// emission they reach the same way so it isn't counted twice.
+//
+// Each pass is an independent map with a smaller radius than the one before; averaging
+// the passes converges to the sharp caustic, with the noise of the first few passes
+// spread over the rest.
+
+// how fast the radius shrinks, between 0 (not at all) and 1
+const ALPHA: f64 = 2.0 / 3.0;
+
+#[derive(Clone, Copy)]
+pub struct photon {
+    pub p: Vec3,
+    // the direction the photon was travelling in
+    pub direction: Vec3,
+    pub power: Vec3,
+}
+
+// A balanced kd-tree stored in place: the median of each range is its node.
+pub struct photon_map {
+    photons: Vec<photon>,
+    // split axis of the node at the same index
+    axes: Vec<u8>,
+    pub radius: f64,
+}
+
+fn build(photons: &mut [photon], axes: &mut [u8]) {
+    if photons.len() <= 1 {
+        return;
+    }
+    let (mut lo, mut hi) = (photons[0].p, photons[0].p);
+    for ph in photons.iter() {
+        for a in 0..3 {
+            lo[a] = lo[a].min(ph.p[a]);
+            hi[a] = hi[a].max(ph.p[a]);
+        }
+    }
+    let extent = hi - lo;
+    let axis = if extent.x > extent.y && extent.x > extent.z {
+        0
+    } else if extent.y > extent.z {
+        1
+    } else {
+        2
+    };
+    let mid = photons.len() / 2;
+    photons.select_nth_unstable_by(mid, |a, b| a.p[axis].total_cmp(&b.p[axis]));
+    axes[mid] = axis as u8;
+    let (left, right) = photons.split_at_mut(mid);
+    let (left_axes, right_axes) = axes.split_at_mut(mid);
+    build(left, left_axes);
+    build(&mut right[1..], &mut right_axes[1..]);
+}
+
+impl photon_map {
+    pub fn new(mut photons: Vec<photon>, radius: f64) -> Self {
+        let mut axes = vec![0; photons.len()];
+        build(&mut photons, &mut axes);
+        Self {
+            photons,
+            axes,
+            radius,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.photons.len()
+    }
+
+    // calls f with every photon within radius of p
+    pub fn for_each_near(&self, p: &Vec3, radius: f64, mut f: impl FnMut(&photon)) {
+        let mut stack = vec![(0, self.photons.len())];
+        while let Some((lo, hi)) = stack.pop() {
+            if lo >= hi {
+                continue;
+            }
+            let mid = (lo + hi) / 2;
+            let ph = &self.photons[mid];
+            if (ph.p - *p).squared_length() <= radius * radius {
+                f(ph);
+            }
+            let axis = self.axes[mid] as usize;
+            let d = p[axis] - ph.p[axis];
+            let (near, far) = if d < 0.0 {
+                ((lo, mid), (mid + 1, hi))
+            } else {
+                ((mid + 1, hi), (lo, mid))
+            };
+            if d * d <= radius * radius {
+                stack.push(far);
+            }
+            stack.push(near);
+        }
+    }
+
+    // radiance toward r_in from the photons around a diffuse hit
+    pub fn estimate(&self, r_in: &Ray, rec: &hit_record) -> Vec3 {
+        let mut sum = Vec3::zero();
+        self.for_each_near(&rec.p, self.radius, |ph| {
+            let toward_light = ph.direction.normalize() * -1.0;
+            // photons on the far side of the surface don't light this side
+            let cosine = rec.normal.dot(toward_light);
+            if cosine <= 1e-4 {
+                return;
+            }
+            let f = rec.mat.bsdf_cos(
+                r_in,
+                rec,
+                &Ray::new_with_time(rec.p, toward_light, r_in.time()),
+            );
+            sum += f * ph.power / cosine;
+        });
+        sum / (pi * self.radius * self.radius)
+    }
+
+    // One pass of count photons from lights in proportion to their power, gathered
+    // within the radius for pass number pass (from 0) of a run starting at radius.
+    pub fn trace(
+        world: &Arc<dyn Hittable + Send + Sync>,
+        lights: &[Arc<dyn light + Send + Sync>],
+        count: usize,
+        radius: f64,
+        pass: usize,
+        max_depth: usize,
+    ) -> Self {
+        let mut radius2 = radius * radius;
+        for i in 1..=pass {
+            radius2 *= (i as f64 + ALPHA) / (i as f64 + 1.0);
+        }
+        let mut photons = Vec::new();
+        if lights.is_empty() {
+            return Self::new(photons, radius2.sqrt());
+        }
+        let table = alias_table::new(
+            &lights
+                .iter()
+                .map(|l| l.bounds().map_or(0.0, |b| b.power))
+                .collect::<Vec<f64>>(),
+        );
+        for _ in 0..count {
+            let i = table.sample();
+            let Some(e) = lights[i].sample_emission() else {
+                continue;
+            };
+            if e.pdf_pos <= 0.0 || e.pdf_dir <= 0.0 {
+                continue;
+            }
+            let cosine = if e.n.near_zero() {
+                1.0
+            } else {
+                e.n.dot(e.direction.normalize()).abs()
+            };
+            let power =
+                e.radiance * (cosine / (table.pmf(i) * e.pdf_pos * e.pdf_dir * count as f64));
+            trace_photon(
+                world,
+                Ray::new_with_time(e.p, e.direction, random_double()),
+                power,
+                max_depth,
+                &mut photons,
+            );
+        }
+        Self::new(photons, radius2.sqrt())
+    }
+}
+
+// The path tracer with its caustics from photon maps: photon_passes maps of
+// photons_per_pass photons each, gathered within photon_radius at first and less in
+// later passes. prepare() traces them from every emitter in the world.
+pub struct photon_mapper {
+    pub photons_per_pass: usize,
+    pub photon_passes: usize,
+    pub photon_radius: f64,
+    pub path: path_tracer,
+    maps: Vec<photon_map>,
+}
+
+impl photon_mapper {
+    pub fn new() -> Self {
+        Self {
+            photons_per_pass: 200000,
+            photon_passes: 16,
+            photon_radius: 0.05,
+            path: path_tracer::new(),
+            maps: Vec::new(),
+        }
+    }
+}
+
+impl integrator for photon_mapper {
+    fn prepare(
+        &self,
+        cam: &Camera,
+        world: &Arc<dyn Hittable + Send + Sync>,
+    ) -> Option<Arc<dyn integrator + Send + Sync>> {
+        let lights = photon_lights(world, &cam.lights);
+        let (count, radius, depth) = (self.photons_per_pass, self.photon_radius, cam.max_depth);
+        let maps: Vec<photon_map> = thread::scope(|scope| {
+            let passes: Vec<_> = (0..self.photon_passes)
+                .map(|pass| {
+                    let lights = &lights;
+                    scope.spawn(move |_| {
+                        photon_map::trace(world, lights, count, radius, pass, depth)
+                    })
+                })
+                .collect();
+            passes
+                .into_iter()
+                .map(|pass| pass.join().unwrap())
+                .collect()
+        })
+        .unwrap();
+        let stored: usize = maps.iter().map(|m| m.len()).sum();
+        println!("{} caustic photons in {} passes", stored, maps.len());
+        Some(Arc::new(Self {
+            path: self.path.clone(),
+            maps,
+            ..*self
+        }))
+    }
+    fn li(
+        &self,
+        cam: &Camera,
+        r: &Ray,
+        world: &Arc<dyn Hittable + Send + Sync>,
+        splats: &mut Vec<splat>,
+    ) -> Vec3 {
+        self.path.trace(cam, r, world, &self.maps)
+    }
+}
+
+// Every emitter in the world plus the registered lights without geometry (points, spots).
+// Registered area lights are the same emitters as their primitives, matched by bounds,
+// and left out so they aren't traced twice. Lights at infinity shoot across the world's
+// bounds if they can (directional_light); environment maps and skies send no photons.
+pub fn photon_lights(
+    world: &Arc<dyn Hittable + Send + Sync>,
+    registered: &[Arc<dyn light + Send + Sync>],
+) -> Vec<Arc<dyn light + Send + Sync>> {
+    let mut lights = Vec::new();
+    world.emitters(&mut lights);
+    let found: Vec<aabb> = lights
+        .iter()
+        .filter_map(|l| l.bounds())
+        .map(|b| b.bounds)
+        .collect();
+    for l in registered {
+        let Some(b) = l.bounds() else {
+            lights.extend(l.in_scene(&world.bounding_box()));
+            continue;
+        };
+        if !found.iter().any(|f| same_box(f, &b.bounds)) {
+            lights.push(l.clone());
+        }
+    }
+    lights
+}
+
+fn same_box(a: &aabb, b: &aabb) -> bool {
+    let close =
+        |x: &Interval, y: &Interval| (x.min - y.min).abs() < 1e-9 && (x.max - y.max).abs() < 1e-9;
+    close(&a.x, &b.x) && close(&a.y, &b.y) && close(&a.z, &b.z)
+}
+
+// follows a photon through specular bounces, keeping it at the diffuse surfaces it meets
+fn trace_photon(
+    world: &Arc<dyn Hittable + Send + Sync>,
+    mut r: Ray,
+    mut power: Vec3,
+    max_depth: usize,
+    photons: &mut Vec<photon>,
+) {
+    for depth in 0..max_depth {
+        let mut rec = hit_record::new();
+        if !world.hit(&r, Interval::new(0.001, f64::INFINITY), &mut rec) {
+            return;
+        }
+        let mut attenuation = Vec3::zero();
+        let mut scattered = Ray::new(Vec3::zero(), Vec3::zero());
+        let mut pdf = 0.0;
+        if !rec
+            .mat
+            .scatter_with_pdf(&r, &rec, &mut attenuation, &mut scattered, &mut pdf)
+        {
+            return;
+        }
+        // direct light reaches diffuse surfaces well enough without photons
+        if depth > 0 && !rec.mat.is_volumetric() && rec.mat.has_smooth_lobe(&r, &rec) {
+            photons.push(photon {
+                p: rec.p,
+                direction: r.direction(),
+                power,
+            });
+        }
+        // on through the delta lobes of surfaces that have both
+        if pdf > 0.0 {
+            return;
+        }
+        power = power * attenuation;
+        if power.near_zero() {
+            return;
+        }
+        r = scattered;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_radius_query_matches_brute_force() {
+        let photons: Vec<photon> = (0..2000)
+            .map(|_| photon {
+                p: Vec3::random_with_range(-1.0, 1.0),
+                direction: Vec3::new(0.0, -1.0, 0.0),
+                power: Vec3::ones(),
+            })
+            .collect();
+        let map = photon_map::new(photons.clone(), 0.2);
+        for _ in 0..50 {
+            let p = Vec3::random_with_range(-1.0, 1.0);
+            let mut found = 0;
+            map.for_each_near(&p, 0.2, |_| found += 1);
+            let expected = photons
+                .iter()
+                .filter(|ph| (ph.p - p).squared_length() <= 0.04)
+                .count();
+            assert_eq!(found, expected);
+        }
+    }
+
+    #[test]
+    fn test_unregistered_emitters_cast_caustics() {
+        use crate::hittable_list::HittableList;
+        use crate::light::{point_light, quad_light};
+        use crate::material::{dielectric, diffuse_light, lambertian};
+        use crate::quad::Quad;
+        use crate::sphere::Sphere;
+        // a glass ball on a floor under a panel nobody registered
+        let mut world = HittableList::new();
+        world.add(Arc::new(Quad::new(
+            Vec3::new(-2.0, 0.0, -2.0),
+            Vec3::new(4.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 4.0),
+            Arc::new(lambertian::new(Vec3::new(0.7, 0.7, 0.7))),
+        )));
+        world.add(Arc::new(Sphere::new(
+            Vec3::new(0.0, 1.0, 0.0),
+            0.5,
+            Arc::new(dielectric::new(1.5)),
+        )));
+        let panel = Arc::new(diffuse_light::new_from_emit_color(Vec3::new(
+            10.0, 10.0, 10.0,
+        )));
+        world.add(Arc::new(Quad::new(
+            Vec3::new(-0.3, 3.0, -0.3),
+            Vec3::new(0.6, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 0.6),
+            panel.clone(),
+        )));
+        let world: Arc<dyn Hittable + Send + Sync> = Arc::new(world);
+        let lights = photon_lights(&world, &[]);
+        assert_eq!(lights.len(), 1);
+        assert!(photon_map::trace(&world, &lights, 2000, 0.05, 0, 8).len() > 0);
+        // registering the panel again doesn't double it, a point light is added
+        let again: Arc<dyn light + Send + Sync> = Arc::new(quad_light::new_from_material(
+            Vec3::new(-0.3, 3.0, -0.3),
+            Vec3::new(0.6, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 0.6),
+            &panel,
+        ));
+        let point: Arc<dyn light + Send + Sync> =
+            Arc::new(point_light::new(Vec3::new(1.0, 2.0, 0.0), Vec3::ones()));
+        assert_eq!(photon_lights(&world, &[again, point]).len(), 2);
+    }
+
+    #[test]
+    fn test_unregistered_caustics_match_path_tracer() {
+        use crate::hittable_list::HittableList;
+        use crate::material::{dielectric, diffuse_light, lambertian};
+        use crate::quad::Quad;
+        use crate::sphere::Sphere;
+        // the floor under a glass ball, lit by a panel nobody registered
+        let mut world = HittableList::new();
+        world.add(Arc::new(Quad::new(
+            Vec3::new(-3.0, 0.0, -3.0),
+            Vec3::new(6.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 6.0),
+            Arc::new(lambertian::new(Vec3::new(0.7, 0.7, 0.7))),
+        )));
+        world.add(Arc::new(Sphere::new(
+            Vec3::new(0.0, 1.0, 0.0),
+            0.5,
+            Arc::new(dielectric::new(1.5)),
+        )));
+        let panel = Arc::new(diffuse_light::new_from_emit_color(Vec3::new(4.0, 4.0, 4.0)));
+        world.add(Arc::new(Quad::new(
+            Vec3::new(-1.0, 2.5, -1.0),
+            Vec3::new(2.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 2.0),
+            panel,
+        )));
+        let world: Arc<dyn Hittable + Send + Sync> = Arc::new(world);
+        let mut cam = Camera::new();
+        cam.width = 8;
+        cam.height = 8;
+        cam.max_depth = 8;
+        cam.vfov = 30.0;
+        cam.lookfrom = Vec3::new(0.0, 1.0, 2.5);
+        cam.lookat = Vec3::new(0.0, 0.0, 0.0);
+        cam.initialize();
+        let mut photons = photon_mapper::new();
+        photons.photons_per_pass = 20000;
+        photons.photon_passes = 4;
+        let photons = photons.prepare(&cam, &world).unwrap();
+        let path = path_tracer::new();
+        let n = 40000;
+        let (mut p, mut u) = (0.0, 0.0);
+        for k in 0..n {
+            let (i, j) = (k % cam.width, (k / cam.width) % cam.height);
+            p += photons
+                .li(&cam, &cam.get_ray(i, j), &world, &mut Vec::new())
+                .y;
+            u += path.li(&cam, &cam.get_ray(i, j), &world, &mut Vec::new()).y;
+        }
+        let (p, u) = (p / n as f64, u / n as f64);
+        // counting the caustic twice would be some 40% over
+        assert!((p - u).abs() < 0.1 * u, "photons {} path {}", p, u);
+    }
+
+    #[test]
+    fn test_sunlight_through_glass() {
+        use crate::hittable_list::HittableList;
+        use crate::light::directional_light;
+        use crate::material::{dielectric, lambertian};
+        use crate::quad::Quad;
+        // a pane of glass over a floor, in sun from straight above
+        let mut world = HittableList::new();
+        world.add(Arc::new(Quad::new(
+            Vec3::new(-2.0, 0.0, -2.0),
+            Vec3::new(4.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 4.0),
+            Arc::new(lambertian::new(Vec3::new(0.7, 0.7, 0.7))),
+        )));
+        world.add(Arc::new(Quad::new(
+            Vec3::new(-1.0, 1.0, -1.0),
+            Vec3::new(2.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 2.0),
+            Arc::new(dielectric::new(1.5)),
+        )));
+        let world: Arc<dyn Hittable + Send + Sync> = Arc::new(world);
+        let sun: Arc<dyn light + Send + Sync> = Arc::new(directional_light::new(
+            Vec3::new(0.0, 1.0, 0.0),
+            Vec3::new(2.0, 2.0, 2.0),
+        ));
+        let lights = photon_lights(&world, &[sun]);
+        assert_eq!(lights.len(), 1);
+        let map = photon_map::trace(&world, &lights, 40000, 0.05, 0, 8);
+        // all that reaches the floor through the pane, which lets 96% through at normal incidence
+        assert!(map
+            .photons
+            .iter()
+            .all(|ph| ph.p.x.abs() <= 1.0 && ph.p.z.abs() <= 1.0));
+        let irradiance = map.photons.iter().map(|ph| ph.power.y).sum::<f64>() / 4.0;
+        assert!(
+            (irradiance - 2.0 * 0.96).abs() < 0.05 * 2.0,
+            "{}",
+            irradiance
+        );
+    }
+}
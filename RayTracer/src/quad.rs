@@ -1,11 +1,12 @@
 
 use crate::ray::Ray;
 use crate::vec3::Vec3;
-use crate::hittable::{hit_record, Hittable};
+use crate::hittable::{hit_record, Hittable, ALPHA_CUTOFF};
 use crate::hittable_list::HittableList;
 use crate::interval::Interval;
 use crate::material::Material;
 use crate::aabb::aabb;
+use crate::light::{light, quad_light};
 use std::rc::Rc;
 use std::sync::Arc;
 
@@ -82,6 +83,14 @@ impl Hittable for Quad {
         let alpha = self.w.dot(Vec3::cross(planar_hitpt_vector, self.v));
         // let beta = self.w.dot(self.u.cross(planar_hitpt_vector));
         let beta = self.w.dot(Vec3::cross(self.u, planar_hitpt_vector));
+        // outside the quad, or alpha-tested away, before is_interior writes into rec
+        let unit_interval = Interval::new(0.0, 1.0);
+        if !unit_interval.contains(alpha) || !unit_interval.contains(beta) {
+            return false;
+        }
+        if self.mat.alpha(alpha, beta, &intersection) < ALPHA_CUTOFF {
+            return false;
+        }
         // if !is_interior(alpha, beta, rec) {
         if !Quad::is_interior(alpha, beta, rec) {
             return false;
@@ -92,10 +101,50 @@ impl Hittable for Quad {
         rec.p = intersection;
         rec.mat = self.mat.clone();
         rec.set_face_normal(r, self.normal);
+        rec.tangent = self.u;
+        rec.bitangent = self.v;
         return true;
     }
 
     fn bounding_box(&self) -> aabb {
         return self.bbox;
     } 
-}  
\ No newline at end of file
+    fn emitters(&self, lights: &mut Vec<Arc<dyn light + Send + Sync>>) {
+        if let Some(mat) = self.mat.emitter() {
+            lights.push(Arc::new(quad_light::new_from_material(self.q, self.u, self.v, mat)));
+        }
+    }
+}  
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::lambertian;
+    use crate::texture::texture;
+
+    // opaque grey with the left half of uv space cut away
+    struct left_cut;
+    impl texture for left_cut {
+        fn value(&self, u: f64, v: f64, p: &Vec3) -> Vec3 {
+            Vec3::new(0.5, 0.5, 0.5)
+        }
+        fn alpha(&self, u: f64, v: f64, p: &Vec3) -> f64 {
+            if u < 0.5 { 0.0 } else { 1.0 }
+        }
+    }
+
+    #[test]
+    fn test_alpha_cutout() {
+        // a cut out card in front of a wall
+        let mut world = HittableList::new();
+        world.add(Arc::new(Quad::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0), Arc::new(lambertian::new_with_texture(Arc::new(left_cut))))));
+        world.add(Arc::new(Quad::new(Vec3::new(-1.0, -1.0, -1.0), Vec3::new(3.0, 0.0, 0.0), Vec3::new(0.0, 3.0, 0.0), Arc::new(lambertian::new(Vec3::ones())))));
+        let shoot = |x: f64| {
+            let mut rec = hit_record::new();
+            assert!(world.hit(&Ray::new(Vec3::new(x, 0.5, 1.0), Vec3::new(0.0, 0.0, -1.0)), Interval::new(0.001, f64::INFINITY), &mut rec));
+            rec.t
+        };
+        // through the cut out half to the wall, stopped by the rest
+        assert!((shoot(0.25) - 2.0).abs() < 1e-9);
+        assert!((shoot(0.75) - 1.0).abs() < 1e-9);
+    }
+}
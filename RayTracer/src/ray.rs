@@ -1,17 +1,22 @@
 pub use crate::vec3::Vec3;
 
+#[derive(Clone)]
 pub struct Ray {
     pub orig: Vec3,
     pub dir: Vec3,
     pub tm: f64,
+    // growth of the pixel footprint width per unit distance, 0 if the ray carries no footprint
+    pub spread: f64,
+    // footprint width at the origin, nonzero for rays continuing a path
+    pub width: f64,
 }
 
 impl Ray {
     pub fn new(orig: Vec3, dir: Vec3) -> Self {
-        Self { orig, dir , tm: 0.0}
+        Self { orig, dir , tm: 0.0, spread: 0.0, width: 0.0 }
     }
     pub fn new_with_time(orig: Vec3, dir: Vec3, tm: f64) -> Self {
-        Self { orig, dir, tm }
+        Self { orig, dir, tm, spread: 0.0, width: 0.0 }
     }
     pub fn origin(&self) -> Vec3 {
         self.orig
@@ -22,6 +27,16 @@ impl Ray {
     pub fn time(&self) -> f64 {
         self.tm
     }
+    // world space footprint width at t
+    pub fn footprint(&self, t: f64) -> f64 {
+        self.width + self.spread * t * self.dir.length()
+    }
+    // keeps widening the footprint of `from`, which reached this ray's origin with
+    // width `footprint`. Curvature and roughness are ignored, so it is a lower bound.
+    pub fn inherit_footprint(&mut self, from: &Ray, footprint: f64) {
+        self.width = footprint;
+        self.spread = from.spread;
+    }
     pub fn at(&self, t: f64) -> Vec3 {
         let res = Vec3::new(self.dir.x * t, self.dir.y * t, self.dir.z * t);
         res + self.orig
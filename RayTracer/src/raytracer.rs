@@ -1,15 +1,49 @@
 use rand;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::any::Any;
+use std::cell::RefCell;
 
 pub const infinity: f64 = f64::INFINITY;
+#[allow(clippy::approx_constant)]
 pub const pi: f64 = 3.1415926535897932385;
 
 pub fn degrees_to_radians(degrees: f64) -> f64 {
     degrees * pi / 180.0
 }
 
+// A stream of numbers in [0,1) that stands in for the thread's generator while
+// with_sampler() runs, so a whole path can be replayed and perturbed (mlt.rs).
+pub trait sampler: Any {
+    fn next(&mut self) -> f64;
+}
+
+enum source {
+    Own(StdRng),
+    Installed(Box<dyn sampler>),
+}
+
+thread_local! {
+    static SOURCE: RefCell<source> = RefCell::new(source::Own(StdRng::from_entropy()));
+}
+
 pub fn random_double() -> f64 {
     // Returns a random real in [0,1).
-    rand::random::<f64>()
+    SOURCE.with(|s| match &mut *s.borrow_mut() {
+        source::Own(rng) => rng.gen(),
+        source::Installed(s) => s.next(),
+    })
+}
+
+// Runs f with every random_double() on this thread taken from s, then hands s back.
+pub fn with_sampler<S: sampler, R>(s: S, f: impl FnOnce() -> R) -> (S, R) {
+    let previous = SOURCE.with(|x| x.replace(source::Installed(Box::new(s))));
+    let result = f();
+    let source::Installed(s) = SOURCE.with(|x| x.replace(previous)) else {
+        unreachable!()
+    };
+    let s: Box<dyn Any> = s;
+    (*s.downcast::<S>().unwrap(), result)
 }
 
 pub fn random_double_range(min: f64, max: f64) -> f64 {
@@ -0,0 +1,237 @@
+use crate::vec3::Vec3;
+use crate::color::{blackbody, xyz_to_linear_rgb};
+use crate::light::{light, light_sample, sample_cone};
+use crate::environment::{environment_map, local_to_world};
+use crate::raytracer::pi;
+
+// Preetham et al. 1999 daylight: the clear sky from a turbidity and the sun position,
+// plus the sun itself as a small disk. The sky is baked into an environment_map and
+// the sun is its own light, so both are importance sampled by the camera:
+//
+//     cam.environment = Some(Arc::new(sky.environment(512, 256)));
+//     cam.lights.push(Arc::new(sky.sun()));
+//
+// Radiance is in units of 1 / intensity kcd/m2, the sun's irradiance matches.
+pub struct preetham_sky {
+    // degrees above the horizon
+    pub sun_elevation: f64,
+    // degrees around up, 0 along +x of the environment frame
+    pub sun_azimuth: f64,
+    // 2 is very clear, 10 hazy
+    pub turbidity: f64,
+    // color of the ground below the horizon, lit by the sky and the sun
+    pub ground_albedo: Vec3,
+    pub intensity: f64,
+    // angular diameter of the sun disk in degrees
+    pub sun_size: f64,
+    // highest elevation of the sun, reached at noon by set_time_of_day
+    pub max_elevation: f64,
+    pub up: Vec3,
+}
+
+// Perez et al. sky luminance distribution, theta the zenith angle of the view and
+// gamma its angle to the sun
+fn perez(c: &[f64; 5], cos_theta: f64, gamma: f64) -> f64 {
+    (1.0 + c[0] * (c[1] / cos_theta.max(0.01)).exp()) * (1.0 + c[2] * (c[3] * gamma).exp() + c[4] * gamma.cos() * gamma.cos())
+}
+
+fn cubic(c: &[f64; 4], t: f64) -> f64 {
+    ((c[0] * t + c[1]) * t + c[2]) * t + c[3]
+}
+
+impl preetham_sky {
+    pub fn new(up: Vec3) -> Self {
+        Self {
+            sun_elevation: 45.0,
+            sun_azimuth: 0.0,
+            turbidity: 3.0,
+            ground_albedo: Vec3::new(0.2, 0.2, 0.2),
+            intensity: 0.1,
+            sun_size: 0.53,
+            max_elevation: 60.0,
+            up: up.normalize(),
+        }
+    }
+
+    // Moves the sun along a simple day from 6 to 18 o'clock, rising at azimuth 0
+    // and setting at 180. Hours outside the day put it below the horizon.
+    pub fn set_time_of_day(&mut self, hour: f64) {
+        let t = (hour - 6.0) / 12.0;
+        self.sun_elevation = self.max_elevation * (t * pi).sin();
+        self.sun_azimuth = 180.0 * t;
+    }
+
+    // sun direction in the environment frame, +y up
+    fn sun_local(&self) -> Vec3 {
+        let (elevation, azimuth) = (self.sun_elevation.to_radians(), self.sun_azimuth.to_radians());
+        Vec3::new(elevation.cos() * azimuth.cos(), elevation.sin(), elevation.cos() * azimuth.sin())
+    }
+
+    // the model is only fitted for the sun above the horizon; after sunset the sky
+    // fades out over 6 degrees of civil twilight
+    fn twilight(&self) -> f64 {
+        ((self.sun_elevation + 6.0) / 6.0).max(0.0).min(1.0)
+    }
+
+    // sky radiance without the sun disk and the ground, direction in the environment frame
+    fn sky_radiance(&self, direction: Vec3) -> Vec3 {
+        let t = self.turbidity;
+        let sun = self.sun_local();
+        let theta_s = (pi / 2.0 - self.sun_elevation.max(0.0).to_radians()).min(pi / 2.0 - 0.01);
+        let sun = Vec3::new(sun.x, theta_s.cos(), sun.z).normalize();
+
+        let chi = (4.0 / 9.0 - t / 120.0) * (pi - 2.0 * theta_s);
+        let zenith_y = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+        let zenith_x = t * t * cubic(&[0.00166, -0.00375, 0.00209, 0.0], theta_s)
+            + t * cubic(&[-0.02903, 0.06377, -0.03202, 0.00394], theta_s)
+            + cubic(&[0.11693, -0.21196, 0.06052, 0.25886], theta_s);
+        let zenith_yc = t * t * cubic(&[0.00275, -0.00610, 0.00317, 0.0], theta_s)
+            + t * cubic(&[-0.04214, 0.08970, -0.04153, 0.00516], theta_s)
+            + cubic(&[0.15346, -0.26756, 0.06670, 0.26688], theta_s);
+
+        let coeffs_y = [0.1787 * t - 1.4630, -0.3554 * t + 0.4275, -0.0227 * t + 5.3251, 0.1206 * t - 2.5771, -0.0670 * t + 0.3703];
+        let coeffs_x = [-0.0193 * t - 0.2592, -0.0665 * t + 0.0008, -0.0004 * t + 0.2125, -0.0641 * t - 0.8989, -0.0033 * t + 0.0452];
+        let coeffs_yc = [-0.0167 * t - 0.2608, -0.0950 * t + 0.0092, -0.0079 * t + 0.2102, -0.0441 * t - 1.6537, -0.0109 * t + 0.0529];
+
+        let cos_theta = direction.y.max(0.0);
+        let gamma = direction.dot(sun).max(-1.0).min(1.0).acos();
+        let relative = |c: &[f64; 5]| perez(c, cos_theta, gamma) / perez(c, 1.0, theta_s);
+        let luminance = (zenith_y * relative(&coeffs_y)).max(0.0);
+        let x = zenith_x * relative(&coeffs_x);
+        let y = zenith_yc * relative(&coeffs_yc);
+        if y <= 0.0 {
+            return Vec3::zero();
+        }
+        let xyz = Vec3::new(x / y * luminance, luminance, (1.0 - x - y) / y * luminance);
+        xyz_to_linear_rgb(xyz) * self.intensity * self.twilight()
+    }
+
+    // irradiance of the sun at normal incidence after the atmosphere, per channel
+    fn sun_irradiance(&self) -> Vec3 {
+        if self.sun_elevation <= 0.0 {
+            return Vec3::zero();
+        }
+        // Kasten and Young relative air mass
+        let zenith = 90.0 - self.sun_elevation;
+        let air_mass = 1.0 / (zenith.to_radians().cos() + 0.50572 * (96.07995 - zenith).powf(-1.6364));
+        // vertical optical depths at 680, 550 and 440 nm: rayleigh plus angstrom
+        // aerosols for the turbidity
+        let beta = 0.04608 * self.turbidity - 0.04586;
+        let rayleigh = Vec3::new(0.042, 0.097, 0.24);
+        let aerosol = Vec3::new(0.68f64.powf(-1.3), 0.55f64.powf(-1.3), 0.44f64.powf(-1.3)) * beta;
+        let tau = (rayleigh + aerosol) * air_mass;
+        let transmittance = Vec3::new((-tau.x).exp(), (-tau.y).exp(), (-tau.z).exp());
+        // 128 klux above the atmosphere
+        blackbody(5778.0) * transmittance * 128.0 * self.intensity
+    }
+
+    fn cos_sun_radius(&self) -> f64 {
+        (self.sun_size.to_radians() / 2.0).cos()
+    }
+
+    // diffuse ground lit by the upper hemisphere and the sun
+    fn ground_radiance(&self) -> Vec3 {
+        let (n_theta, n_phi) = (16, 32);
+        let (d_theta, d_phi) = (pi / 2.0 / n_theta as f64, 2.0 * pi / n_phi as f64);
+        let mut irradiance = Vec3::zero();
+        for i in 0..n_theta {
+            let theta = (i as f64 + 0.5) * d_theta;
+            for j in 0..n_phi {
+                let phi = (j as f64 + 0.5) * d_phi;
+                let d = Vec3::new(theta.sin() * phi.cos(), theta.cos(), theta.sin() * phi.sin());
+                irradiance += self.sky_radiance(d) * (theta.cos() * theta.sin() * d_theta * d_phi);
+            }
+        }
+        irradiance += self.sun_irradiance() * self.sun_local().y.max(0.0);
+        self.ground_albedo * irradiance / pi
+    }
+
+    // the sky and ground baked at width x height, oriented by up
+    pub fn environment(&self, width: usize, height: usize) -> environment_map {
+        let ground = self.ground_radiance();
+        let mut map = environment_map::new_from_fn(width, height, |d| if d.y >= 0.0 { self.sky_radiance(d) } else { ground });
+        map.up = self.up;
+        map
+    }
+
+    pub fn sun(&self) -> sun_light {
+        let cos_max = self.cos_sun_radius();
+        let solid_angle = 2.0 * pi * (1.0 - cos_max);
+        sun_light {
+            direction: local_to_world(self.up, &self.sun_local()).normalize(),
+            radiance: self.sun_irradiance() / solid_angle,
+            cos_max,
+        }
+    }
+}
+
+// A disk of constant radiance at infinity, seen within a cone around direction.
+pub struct sun_light {
+    // unit vector toward the sun
+    pub direction: Vec3,
+    pub radiance: Vec3,
+    // cosine of the angular radius
+    pub cos_max: f64,
+}
+
+impl light for sun_light {
+    fn sample(&self, p: &Vec3) -> Option<light_sample> {
+        if self.radiance.near_zero() {
+            return None;
+        }
+        Some(light_sample {
+            direction: sample_cone(self.direction, self.cos_max),
+            distance: f64::INFINITY,
+            radiance: self.radiance,
+            pdf: 1.0 / (2.0 * pi * (1.0 - self.cos_max)),
+            delta: false,
+        })
+    }
+    fn pdf(&self, p: &Vec3, direction: &Vec3) -> f64 {
+        if self.radiance.near_zero() || direction.normalize().dot(self.direction) < self.cos_max {
+            return 0.0;
+        }
+        1.0 / (2.0 * pi * (1.0 - self.cos_max))
+    }
+    fn background(&self, direction: &Vec3) -> Vec3 {
+        if direction.normalize().dot(self.direction) < self.cos_max {
+            return Vec3::zero();
+        }
+        self.radiance
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sky_colors() {
+        let mut sky = preetham_sky::new(Vec3::new(0.0, 1.0, 0.0));
+        sky.set_time_of_day(12.0);
+        // a clear noon zenith is blue, and the sun is much brighter than the sky
+        let zenith = sky.sky_radiance(Vec3::new(0.0, 1.0, 0.0));
+        assert!(zenith.z > zenith.x && zenith.x > 0.0);
+        let noon = sky.sun_irradiance();
+        // the setting sun is redder and dimmer
+        sky.set_time_of_day(17.8);
+        let dusk = sky.sun_irradiance();
+        assert!(dusk.x / dusk.z > noon.x / noon.z && dusk.y < noon.y);
+        sky.set_time_of_day(22.0);
+        assert!(sky.sun_irradiance().near_zero() && sky.sky_radiance(Vec3::new(0.0, 1.0, 0.0)).near_zero());
+    }
+
+    #[test]
+    fn test_sun_sampling_matches_disk() {
+        let mut sky = preetham_sky::new(Vec3::new(0.0, 0.0, 1.0));
+        sky.set_time_of_day(9.0);
+        let sun = sky.sun();
+        // with z up the sun sits above the xy plane
+        assert!(sun.direction.z > 0.0);
+        for _ in 0..1000 {
+            let s = sun.sample(&Vec3::zero()).unwrap();
+            assert!(s.direction.dot(sun.direction) >= sun.cos_max - 1e-12);
+            assert!((sun.pdf(&Vec3::zero(), &s.direction) - s.pdf).abs() < 1e-6 * s.pdf);
+        }
+    }
+}
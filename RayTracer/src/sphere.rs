@@ -1,11 +1,12 @@
 pub use crate::vec3::Vec3;
 pub use crate::ray::Ray;
-pub use crate::hittable::{hit_record, Hittable};
+pub use crate::hittable::{hit_record, Hittable, ALPHA_CUTOFF};
 pub use crate::interval::Interval;
 pub use crate::material::Material;
 pub use crate::aabb::aabb;
 use crate::raytracer;
 // pub use crate::raytracer;
+use crate::light::{light, sphere_light};
 use std::rc::Rc;
 use std::sync::Arc;
 
@@ -48,10 +49,14 @@ impl Sphere {
         }
     }
     pub fn get_sphere_uv(&self, p: Vec3, u: &mut f64, v: &mut f64) {
-        let theta = (-p.y).acos();
-        let phi = (-p.z).atan2(p.x) + raytracer::pi;
-        *u = phi / (2.0 * raytracer::pi);
-        *v = theta / raytracer::pi;
+        (*u, *v) = sphere_uv(p);
+    }
+    // dp/du and dp/dv of the (u, v) mapping above, for a point p on the unit sphere
+    pub fn get_sphere_tangents(&self, p: Vec3) -> (Vec3, Vec3) {
+        let sin_theta = (p.x * p.x + p.z * p.z).sqrt().max(1e-8);
+        let dpdu = Vec3::new(p.z, 0.0, -p.x) * (2.0 * raytracer::pi * self.radius);
+        let dpdv = Vec3::new(-p.x * p.y / sin_theta, sin_theta, -p.z * p.y / sin_theta) * (raytracer::pi * self.radius);
+        (dpdu, dpdv)
     }
     // pub fn new (center1: Vec3, center2: Vec3, radius: f64, mat: Arc<dyn Material>) -> Self {
     //     Self {
@@ -65,6 +70,19 @@ impl Sphere {
     pub fn sphere_center(&self, time: f64) -> Vec3 {
         return self.center1 + self.center_vec * time;
     }
+    // uv of the root at t, or None if it is outside ray_t or alpha-tested away
+    fn opaque_uv_at(&self, r: &Ray, ray_t: Interval, t: f64, center: Vec3) -> Option<(f64, f64)> {
+        if !ray_t.surrounds(t) {
+            return None;
+        }
+        let p = r.at(t);
+        let (mut u, mut v) = (0.0, 0.0);
+        self.get_sphere_uv((p - center) / self.radius, &mut u, &mut v);
+        if self.mat.alpha(u, v, &p) < ALPHA_CUTOFF {
+            return None;
+        }
+        Some((u, v))
+    }
 }
 
 impl Hittable for Sphere {
@@ -89,18 +107,22 @@ impl Hittable for Sphere {
         // if root <= 0.00005 {
         //     return false;
         // }
-        if !ray_t.surrounds(root) {
+        let mut uv = self.opaque_uv_at(r, ray_t, root, center);
+        if uv.is_none() {
             root = (h + sqrtd) / a;
-            if !ray_t.surrounds(root) {
-                return false;
-            }
+            uv = self.opaque_uv_at(r, ray_t, root, center);
         }
+        let Some((u, v)) = uv else {
+            return false;
+        };
         // println!("root2: {}", root);
         rec.t = root;
         rec.p = r.at(rec.t);
         let outward_normal = (rec.p - center) / self.radius;
         rec.set_face_normal(r, outward_normal);
-        self.get_sphere_uv(outward_normal, &mut rec.u, &mut rec.v);
+        rec.u = u;
+        rec.v = v;
+        (rec.tangent, rec.bitangent) = self.get_sphere_tangents(outward_normal);
         rec.mat = Arc::clone(&self.mat);
 
         // println!("{}",*rec.mat.as_ref());
@@ -112,4 +134,19 @@ impl Hittable for Sphere {
         // return bbox;
         return self.bbox;
     }
-}
\ No newline at end of file
+    // sphere_light has no motion, so moving emitters still need registering by hand
+    fn emitters(&self, lights: &mut Vec<Arc<dyn light + Send + Sync>>) {
+        if let Some(mat) = self.mat.emitter() {
+            if !self.is_moving {
+                lights.push(Arc::new(sphere_light::new_from_material(self.center1, self.radius, mat)));
+            }
+        }
+    }
+}
+
+// (u, v) of a point p on the unit sphere: u from the -x axis around y, v from -y up
+pub fn sphere_uv(p: Vec3) -> (f64, f64) {
+    let theta = (-p.y).acos();
+    let phi = (-p.z).atan2(p.x) + raytracer::pi;
+    (phi / (2.0 * raytracer::pi), theta / raytracer::pi)
+}
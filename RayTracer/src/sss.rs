@@ -0,0 +1,246 @@
+use crate::aabb::aabb;
+use crate::hittable::{hit_record, Hittable};
+use crate::interval::Interval;
+use crate::material::{dielectric, Material};
+use crate::phase::{henyey_greenstein, phase};
+use crate::ray::Ray;
+use crate::raytracer::random_double;
+use crate::vec3::Vec3;
+use std::sync::Arc;
+
+// Subsurface scattering by a random walk through the interior of a closed shape.
+// Light refracts in through a smooth dielectric boundary, takes exponential free
+// flights between scattering events until it reaches the boundary again, and
+// refracts out there (or is reflected back in). Coefficients are per unit length
+// and per color channel.
+pub struct subsurface {
+    pub sigma_a: Vec3,
+    pub sigma_s: Vec3,
+    pub refraction_index: f64,
+    pub phase: henyey_greenstein,
+    pub max_bounces: usize,
+    // set by subsurface_object; without it the material falls back to a diffuse surface
+    pub boundary: Option<Arc<dyn Hittable + Send + Sync>>,
+}
+
+impl subsurface {
+    pub fn new(sigma_a: Vec3, sigma_s: Vec3, ri: f64) -> Self {
+        Self {
+            sigma_a,
+            sigma_s,
+            refraction_index: ri,
+            phase: henyey_greenstein::new(0.0),
+            max_bounces: 256,
+            boundary: None,
+        }
+    }
+    // from the color the surface should roughly have and how far light travels
+    // inside per channel, e.g. skin scatters red much further than blue
+    pub fn new_from_mfp(albedo: Vec3, mean_free_path: Vec3, ri: f64) -> Self {
+        // single scattering albedo giving roughly `albedo` after many bounces,
+        // the fit used by Cycles' random walk
+        let invert = |a: f64| {
+            let a = a.max(0.0).min(0.999);
+            let x = 4.09712 + 4.20863 * a - (9.59217 + 41.6808 * a + 17.7126 * a * a).sqrt();
+            1.0 - x * x
+        };
+        let sigma_t = Vec3::new(
+            1.0 / mean_free_path.x,
+            1.0 / mean_free_path.y,
+            1.0 / mean_free_path.z,
+        );
+        let ss_albedo = Vec3::new(invert(albedo.x), invert(albedo.y), invert(albedo.z));
+        let sigma_s = sigma_t * ss_albedo;
+        Self::new(sigma_t - sigma_s, sigma_s, ri)
+    }
+    fn albedo(&self) -> Vec3 {
+        let sigma_t = self.sigma_a + self.sigma_s;
+        Vec3::new(
+            self.sigma_s.x / sigma_t.x,
+            self.sigma_s.y / sigma_t.y,
+            self.sigma_s.z / sigma_t.z,
+        )
+    }
+
+    // crosses the boundary at rec from either side, Fresnel decides reflection
+    fn fresnel_bounce(&self, unit_direction: Vec3, rec: &hit_record) -> (Vec3, bool) {
+        let ri = if rec.front_face {
+            1.0 / self.refraction_index
+        } else {
+            self.refraction_index
+        };
+        let cos_theta = (unit_direction * -1.0).dot(rec.normal).min(1.0);
+        let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+        if ri * sin_theta > 1.0 || dielectric::reflectance(cos_theta, ri) > random_double() {
+            (unit_direction.reflect(rec.normal), false)
+        } else {
+            (Vec3::refract(unit_direction, rec.normal, ri), true)
+        }
+    }
+
+    // walks from p along dir (inside the shape) until the path leaves; returns the
+    // outgoing ray and its per channel weight, or None if it was absorbed
+    fn walk(
+        &self,
+        boundary: &dyn Hittable,
+        mut p: Vec3,
+        mut dir: Vec3,
+        time: f64,
+    ) -> Option<(Ray, Vec3)> {
+        let sigma_t = self.sigma_a + self.sigma_s;
+        let mut weight = Vec3::ones();
+        // rays leaving the surface skip it, rays from scattering points inside
+        // may be arbitrarily close to it and must not
+        let mut t_min = 0.0001;
+        for _ in 0..self.max_bounces {
+            // distances are sampled by one channel, picked in proportion to its current
+            // weight, and weighted by the combined pdf of all three (spectral MIS)
+            let total = weight.x + weight.y + weight.z;
+            let prob = weight / total;
+            let xi = random_double();
+            let sigma = if xi < prob.x {
+                sigma_t.x
+            } else if xi < prob.x + prob.y {
+                sigma_t.y
+            } else {
+                sigma_t.z
+            };
+            let distance = -(1.0 - random_double()).ln() / sigma;
+
+            let r = Ray::new_with_time(p, dir, time);
+            let mut exit = hit_record::new();
+            if !boundary.hit(&r, Interval::new(t_min, f64::INFINITY), &mut exit) {
+                // the shape is not closed, treat the path as lost
+                return None;
+            }
+            let s = distance.min(exit.t);
+            let tr = Vec3::new(
+                (-sigma_t.x * s).exp(),
+                (-sigma_t.y * s).exp(),
+                (-sigma_t.z * s).exp(),
+            );
+            if exit.t <= distance {
+                // probability of flying past s under the channel mixture
+                weight = weight * tr / prob.dot(tr);
+                let (out, leaves) = self.fresnel_bounce(dir, &exit);
+                if leaves {
+                    return Some((Ray::new_with_time(exit.p, out, time), weight));
+                }
+                p = exit.p;
+                dir = out;
+                t_min = 0.0001;
+                continue;
+            }
+            weight = weight * self.sigma_s * tr / prob.dot(sigma_t * tr);
+            if weight.x.max(weight.y).max(weight.z) < 1e-4 {
+                return None;
+            }
+            p = p + dir * s;
+            dir = self.phase.sample(dir).normalize();
+            t_min = 0.0;
+        }
+        None
+    }
+}
+
+impl Material for subsurface {
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &hit_record,
+        attenuation: &mut Vec3,
+        scattered: &mut Ray,
+    ) -> bool {
+        let unit_direction = r_in.direction().normalize();
+        let Some(boundary) = &self.boundary else {
+            let mut direction = rec.normal + Vec3::random_unit_vector();
+            if direction.near_zero() {
+                direction = rec.normal;
+            }
+            *scattered = Ray::new_with_time(rec.p, direction, r_in.time());
+            *attenuation = self.albedo();
+            return true;
+        };
+        let (direction, crosses) = self.fresnel_bounce(unit_direction, rec);
+        // reflected off the outside, or a ray that started inside leaving
+        if !crosses || !rec.front_face {
+            *scattered = Ray::new_with_time(rec.p, direction, r_in.time());
+            *attenuation = Vec3::ones();
+            return true;
+        }
+        match self.walk(boundary.as_ref(), rec.p, direction.normalize(), r_in.time()) {
+            Some((out, weight)) => {
+                *scattered = out;
+                *attenuation = weight;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+// A closed shape filled with a subsurface material. The walk traces against the
+// shape itself, so wrap the shape after any translate / rotate_y.
+pub struct subsurface_object {
+    pub boundary: Arc<dyn Hittable + Send + Sync>,
+    pub mat: Arc<dyn Material + Send + Sync>,
+}
+
+impl subsurface_object {
+    pub fn new(boundary: Arc<dyn Hittable + Send + Sync>, mut mat: subsurface) -> Self {
+        mat.boundary = Some(boundary.clone());
+        Self {
+            boundary,
+            mat: Arc::new(mat),
+        }
+    }
+}
+
+impl Hittable for subsurface_object {
+    fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut hit_record) -> bool {
+        if !self.boundary.hit(r, ray_t, rec) {
+            return false;
+        }
+        rec.mat = self.mat.clone();
+        true
+    }
+    fn bounding_box(&self) -> aabb {
+        self.boundary.bounding_box()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::lambertian;
+    use crate::sphere::Sphere;
+
+    #[test]
+    fn test_walk_conserves_energy_without_absorption() {
+        // nothing absorbs, so every entering path must come back out with weight 1
+        let sphere = Arc::new(Sphere::new(
+            Vec3::zero(),
+            1.0,
+            Arc::new(lambertian::new(Vec3::ones())),
+        ));
+        let mut mat = subsurface::new(Vec3::zero(), Vec3::new(2.0, 5.0, 10.0), 1.3);
+        mat.max_bounces = 100000;
+        let object = subsurface_object::new(sphere, mat);
+        let r = Ray::new(Vec3::new(0.0, 0.0, -3.0), Vec3::new(0.0, 0.1, 1.0));
+        let mut rec = hit_record::new();
+        assert!(object.hit(&r, Interval::new(0.001, f64::INFINITY), &mut rec));
+        let n = 2000;
+        let mut total = Vec3::zero();
+        for _ in 0..n {
+            let mut attenuation = Vec3::zero();
+            let mut scattered = Ray::new(Vec3::zero(), Vec3::zero());
+            assert!(rec.mat.scatter(&r, &rec, &mut attenuation, &mut scattered));
+            // leaves from the surface, heading outward
+            assert!((scattered.origin().length() - 1.0).abs() < 1e-6);
+            assert!(scattered.direction().dot(scattered.origin()) > 0.0);
+            total += attenuation;
+        }
+        let mean = total / n as f64;
+        assert!((mean.x - 1.0).abs() < 0.08 && (mean.z - 1.0).abs() < 0.08);
+    }
+}
@@ -1,11 +1,29 @@
 use crate::raytracer::*;
 use crate::vec3::Vec3;
+use crate::hittable::hit_record;
 use crate::perlin::perlin;
+use crate::noise::*;
+use crate::color::srgb_to_linear;
+use crate::asset::asset_error;
+use std::path::Path;
+use std::sync::{LazyLock, OnceLock};
 use std::rc::Rc;
 use std::sync::Arc;
 
 pub trait texture {
     fn value(&self, u: f64, v: f64, p: &Vec3) -> Vec3;
+    // coverage in [0,1], textures without an alpha channel are fully opaque
+    fn alpha(&self, u: f64, v: f64, p: &Vec3) -> f64 {
+        1.0
+    }
+    // lookup with a uv space filter width, only prefiltered textures make use of it
+    fn value_filtered(&self, u: f64, v: f64, p: &Vec3, uv_width: f64) -> Vec3 {
+        self.value(u, v, p)
+    }
+    // full lookup at a surface hit, for textures that also need the normal
+    fn value_at(&self, rec: &hit_record) -> Vec3 {
+        self.value_filtered(rec.u, rec.v, &rec.p, rec.uv_footprint())
+    }
 }
 
 pub struct solid_color {
@@ -67,59 +85,387 @@ impl texture for checker_texture {
     }
 }
 
+// checker in texture space, so it sticks to the surface and shows the uv layout
+pub struct uv_checker_texture {
+    pub u_cells: f64,
+    pub v_cells: f64,
+    even: Arc<dyn texture + Send + Sync>,
+    odd: Arc<dyn texture + Send + Sync>,
+}
+
+impl uv_checker_texture {
+    pub fn new(u_cells: f64, v_cells: f64, even: Arc<dyn texture + Send + Sync>, odd: Arc<dyn texture + Send + Sync>) -> Self {
+        Self { u_cells, v_cells, even, odd }
+    }
+    pub fn new_from_colors(u_cells: f64, v_cells: f64, c1: Vec3, c2: Vec3) -> Self {
+        Self::new(u_cells, v_cells, Arc::new(solid_color::new(c1)), Arc::new(solid_color::new(c2)))
+    }
+}
+
+impl texture for uv_checker_texture {
+    fn value(&self, u: f64, v: f64, p: &Vec3) -> Vec3 {
+        let uinteger = (u * self.u_cells).floor() as i64;
+        let vinteger = (v * self.v_cells).floor() as i64;
+        if (uinteger + vinteger).rem_euclid(2) == 0 {
+            self.even.value(u, v, p)
+        } else {
+            self.odd.value(u, v, p)
+        }
+    }
+}
+
+// u in red, v in green, with grid lines every 1/cells and a blue mark past [0,1]
+pub struct uv_debug_texture {
+    pub cells: f64,
+    // line width as a fraction of a cell
+    pub line_width: f64,
+}
+
+impl uv_debug_texture {
+    pub fn new(cells: f64) -> Self {
+        Self { cells, line_width: 0.05 }
+    }
+}
+
+impl texture for uv_debug_texture {
+    fn value(&self, u: f64, v: f64, p: &Vec3) -> Vec3 {
+        let fu = (u * self.cells).rem_euclid(1.0);
+        let fv = (v * self.cells).rem_euclid(1.0);
+        let half = self.line_width * 0.5;
+        if fu < half || fu > 1.0 - half || fv < half || fv > 1.0 - half {
+            return Vec3::zero();
+        }
+        let outside = !(0.0..=1.0).contains(&u) || !(0.0..=1.0).contains(&v);
+        let wrapped_u = u.rem_euclid(1.0);
+        let wrapped_v = v.rem_euclid(1.0);
+        Vec3::new(wrapped_u, wrapped_v, if outside { 1.0 } else { 0.0 })
+    }
+}
+
+// shading normal mapped from [-1,1] to [0,1]
+pub struct normal_texture {}
+
+impl normal_texture {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+impl texture for normal_texture {
+    // no normal without a hit, show the "facing +z" color
+    fn value(&self, u: f64, v: f64, p: &Vec3) -> Vec3 {
+        Vec3::new(0.5, 0.5, 1.0)
+    }
+    fn value_at(&self, rec: &hit_record) -> Vec3 {
+        (rec.normal + Vec3::new(1.0, 1.0, 1.0)) * 0.5
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum texture_filter {
+    Nearest,
+    Bilinear,
+    // catmull-rom over 4x4 texels
+    Bicubic,
+    // bilinear on the two mip levels around the ray footprint
+    Trilinear,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum wrap_mode {
+    Repeat,
+    Mirror,
+    Clamp,
+    // outside [0,1] returns border / border_alpha
+    Border,
+}
+
+// texels of one image level: 8 bit as decoded, or float for .hdr/.exr files and mip levels
+pub enum texels {
+    Byte(Vec<[u8; 4]>),
+    Float(Vec<[f32; 4]>),
+}
+
+pub struct image_level {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: texels,
+    // byte texels hold srgb encoded color
+    pub srgb: bool,
+}
+
+impl image_level {
+    // linear rgba at (x, y)
+    pub fn texel(&self, x: usize, y: usize) -> [f64; 4] {
+        let i = x + y * self.width;
+        match &self.pixels {
+            texels::Byte(p) => {
+                let t = p[i];
+                let c = |v: u8| if self.srgb { SRGB_TO_LINEAR[v as usize] } else { v as f64 / 255.0 };
+                [c(t[0]), c(t[1]), c(t[2]), t[3] as f64 / 255.0]
+            }
+            texels::Float(p) => {
+                let t = p[i];
+                [t[0] as f64, t[1] as f64, t[2] as f64, t[3] as f64]
+            }
+        }
+    }
+    pub fn downsample(&self) -> Self {
+        let width = (self.width / 2).max(1);
+        let height = (self.height / 2).max(1);
+        let mut pixels = vec![[0.0f32; 4]; width * height];
+        for j in 0..height {
+            for i in 0..width {
+                let mut sum = [0.0f32; 4];
+                for (di, dj) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                    let x = (2 * i + di).min(self.width - 1);
+                    let y = (2 * j + dj).min(self.height - 1);
+                    let texel = self.texel(x, y);
+                    for c in 0..4 {
+                        sum[c] += texel[c] as f32 * 0.25;
+                    }
+                }
+                pixels[i + j * width] = sum;
+            }
+        }
+        Self { width, height, pixels: texels::Float(pixels), srgb: false }
+    }
+}
+
+static SRGB_TO_LINEAR: LazyLock<[f64; 256]> = LazyLock::new(|| std::array::from_fn(|i| srgb_to_linear(i as f64 / 255.0)));
+
+// decoded pixels of one image, shared between textures by the asset manager. The mip
+// chain is only built the first time a trilinear texture asks for it.
+pub struct image_data {
+    pub base: image_level,
+    mips: OnceLock<Vec<image_level>>,
+}
+
+impl image_data {
+    pub fn load(path: &Path, srgb: bool) -> Result<Self, asset_error> {
+        if !path.exists() {
+            return Err(asset_error::NotFound(path.to_path_buf()));
+        }
+        // image's .hdr decoder tone maps to 8 bits, so Radiance files are read as floats here
+        if path.extension().is_some_and(|e| e.eq_ignore_ascii_case("hdr")) {
+            return Self::load_hdr(path);
+        }
+        let img = match image::open(path) {
+            Ok(img) => img,
+            Err(e) => return Err(asset_error::Unreadable(path.to_path_buf(), e.to_string())),
+        };
+        // float images (.exr) are linear already and may go above 1
+        let is_float = matches!(img.color(), image::ColorType::Rgb32F | image::ColorType::Rgba32F);
+        let width = img.width() as usize;
+        let height = img.height() as usize;
+        let base = if is_float {
+            let pixels = img.to_rgba32f().pixels().map(|p| p.0).collect();
+            image_level { width, height, pixels: texels::Float(pixels), srgb: false }
+        } else {
+            let pixels = img.to_rgba8().pixels().map(|p| p.0).collect();
+            image_level { width, height, pixels: texels::Byte(pixels), srgb }
+        };
+        Ok(Self { base, mips: OnceLock::new() })
+    }
+    fn load_hdr(path: &Path) -> Result<Self, asset_error> {
+        let unreadable = |e: &dyn std::fmt::Display| asset_error::Unreadable(path.to_path_buf(), e.to_string());
+        let file = std::fs::File::open(path).map_err(|e| unreadable(&e))?;
+        let decoder = image::codecs::hdr::HdrDecoder::new(std::io::BufReader::new(file)).map_err(|e| unreadable(&e))?;
+        let meta = decoder.metadata();
+        let pixels = decoder.read_image_hdr().map_err(|e| unreadable(&e))?.into_iter().map(|p| [p.0[0], p.0[1], p.0[2], 1.0]).collect();
+        let base = image_level { width: meta.width as usize, height: meta.height as usize, pixels: texels::Float(pixels), srgb: false };
+        Ok(Self { base, mips: OnceLock::new() })
+    }
+    // levels below base, halving down to 1x1
+    pub fn mips(&self) -> &[image_level] {
+        self.mips.get_or_init(|| {
+            let mut levels: Vec<image_level> = Vec::new();
+            let mut last = &self.base;
+            while last.width > 1 || last.height > 1 {
+                levels.push(last.downsample());
+                last = levels.last().unwrap();
+            }
+            levels
+        })
+    }
+    // level 0 is base
+    pub fn level(&self, level: usize) -> &image_level {
+        if level == 0 { &self.base } else { &self.mips()[level - 1] }
+    }
+}
+
 pub struct image_texture {
-    data: Vec<u8>,
-    width: u32,
-    height: u32,
-    bytes_per_pixel: u32,
-    bytes_per_scanline: usize,
+    data: Arc<image_data>,
+    pub filter: texture_filter,
+    pub wrap: wrap_mode,
+    pub border: Vec3,
+    pub border_alpha: f64,
+    // uv -> rotate(uv * scale) + offset, rotation in degrees
+    pub uv_scale: (f64, f64),
+    pub uv_offset: (f64, f64),
+    pub uv_rotation: f64,
 }
 
 impl image_texture {
+    // a color image, linearized like asset_manager::texture but without the cache
     pub fn new(filename: &str) -> Self {
-
-        let bytes_per_pixel = 3;
-        let img = image::open(filename).unwrap().to_rgb8();
-
-        // println!("Image loaded: {}x{}", img.width(), img.height());
-        let width = img.width();
-        let height = img.height();
-        let bytes_per_scanline = (bytes_per_pixel * width) as usize; 
-        
+        let data = image_data::load(Path::new(filename), true).unwrap_or_else(|e| panic!("{}", e));
+        Self::new_from_data(Arc::new(data))
+    }
+    pub fn new_from_data(data: Arc<image_data>) -> Self {
         Self {
-            data: img.into_raw(),
-            width,
-            height,
-            bytes_per_pixel,
-            bytes_per_scanline,
+            data,
+            filter: texture_filter::Nearest,
+            wrap: wrap_mode::Clamp,
+            border: Vec3::zero(),
+            border_alpha: 1.0,
+            uv_scale: (1.0, 1.0),
+            uv_offset: (0.0, 0.0),
+            uv_rotation: 0.0,
+        }
+    }
+    // texels across and down the full resolution image
+    pub fn width(&self) -> usize {
+        self.data.base.width
+    }
+    pub fn height(&self) -> usize {
+        self.data.base.height
+    }
+    fn transform_uv(&self, u: f64, v: f64) -> (f64, f64) {
+        let mut u = u * self.uv_scale.0;
+        let mut v = v * self.uv_scale.1;
+        if self.uv_rotation != 0.0 {
+            let (sin, cos) = self.uv_rotation.to_radians().sin_cos();
+            (u, v) = (cos * u - sin * v, sin * u + cos * v);
+        }
+        (u + self.uv_offset.0, v + self.uv_offset.1)
+    }
+    fn wrap_coord(&self, i: i64, n: usize) -> Option<usize> {
+        if n == 0 {
+            return None;
+        }
+        let n = n as i64;
+        match self.wrap {
+            wrap_mode::Repeat => Some(i.rem_euclid(n) as usize),
+            wrap_mode::Mirror => {
+                let m = i.rem_euclid(2 * n);
+                Some(if m >= n { 2 * n - 1 - m } else { m } as usize)
+            }
+            wrap_mode::Clamp => Some(i.max(0).min(n - 1) as usize),
+            wrap_mode::Border => if i < 0 || i >= n { None } else { Some(i as usize) },
+        }
+    }
+    fn texel(&self, level: usize, i: i64, j: i64) -> [f64; 4] {
+        let img = self.data.level(level);
+        match (self.wrap_coord(i, img.width), self.wrap_coord(j, img.height)) {
+            (Some(x), Some(y)) => img.texel(x, y),
+            _ => [self.border.x, self.border.y, self.border.z, self.border_alpha],
+        }
+    }
+    // continuous texel coordinates, v is flipped so that v = 1 is the top row
+    fn texel_coords(&self, level: usize, u: f64, v: f64) -> (f64, f64) {
+        let img = self.data.level(level);
+        (u * img.width as f64, (1.0 - v) * img.height as f64)
+    }
+    fn sample_nearest(&self, level: usize, u: f64, v: f64) -> [f64; 4] {
+        let (x, y) = self.texel_coords(level, u, v);
+        self.texel(level, x.floor() as i64, y.floor() as i64)
+    }
+    fn sample_bilinear(&self, level: usize, u: f64, v: f64) -> [f64; 4] {
+        let (x, y) = self.texel_coords(level, u, v);
+        let (x, y) = (x - 0.5, y - 0.5);
+        let (i, j) = (x.floor() as i64, y.floor() as i64);
+        let (fx, fy) = (x - x.floor(), y - y.floor());
+        let c00 = self.texel(level, i, j);
+        let c10 = self.texel(level, i + 1, j);
+        let c01 = self.texel(level, i, j + 1);
+        let c11 = self.texel(level, i + 1, j + 1);
+        let mut res = [0.0; 4];
+        for c in 0..4 {
+            let top = c00[c] * (1.0 - fx) + c10[c] * fx;
+            let bottom = c01[c] * (1.0 - fx) + c11[c] * fx;
+            res[c] = top * (1.0 - fy) + bottom * fy;
+        }
+        res
+    }
+    fn catmull_rom_weights(t: f64) -> [f64; 4] {
+        let t2 = t * t;
+        let t3 = t2 * t;
+        [
+            0.5 * (-t3 + 2.0 * t2 - t),
+            0.5 * (3.0 * t3 - 5.0 * t2 + 2.0),
+            0.5 * (-3.0 * t3 + 4.0 * t2 + t),
+            0.5 * (t3 - t2),
+        ]
+    }
+    fn sample_bicubic(&self, level: usize, u: f64, v: f64) -> [f64; 4] {
+        let (x, y) = self.texel_coords(level, u, v);
+        let (x, y) = (x - 0.5, y - 0.5);
+        let (i, j) = (x.floor() as i64, y.floor() as i64);
+        let wx = Self::catmull_rom_weights(x - x.floor());
+        let wy = Self::catmull_rom_weights(y - y.floor());
+        let mut res = [0.0; 4];
+        for dj in 0..4 {
+            for di in 0..4 {
+                let t = self.texel(level, i + di as i64 - 1, j + dj as i64 - 1);
+                for c in 0..4 {
+                    res[c] += t[c] * wx[di] * wy[dj];
+                }
+            }
+        }
+        // catmull-rom overshoots around sharp edges
+        for c in res.iter_mut() {
+            *c = c.max(0.0).min(1.0);
+        }
+        res
+    }
+    fn sample_trilinear(&self, u: f64, v: f64, uv_width: f64) -> [f64; 4] {
+        let base = &self.data.base;
+        let texels = uv_width * self.uv_scale.0.abs().max(self.uv_scale.1.abs()) * base.width.max(base.height) as f64;
+        if texels <= 1.0 {
+            return self.sample_bilinear(0, u, v);
+        }
+        let levels = self.data.mips().len() + 1;
+        let lod = texels.log2().min((levels - 1) as f64);
+        let level = lod.floor() as usize;
+        let f = lod - lod.floor();
+        let c0 = self.sample_bilinear(level, u, v);
+        if level + 1 >= levels {
+            return c0;
+        }
+        let c1 = self.sample_bilinear(level + 1, u, v);
+        let mut res = [0.0; 4];
+        for c in 0..4 {
+            res[c] = c0[c] * (1.0 - f) + c1[c] * f;
+        }
+        res
+    }
+    pub fn sample(&self, u: f64, v: f64, uv_width: f64) -> [f64; 4] {
+        let (u, v) = self.transform_uv(u, v);
+        match self.filter {
+            texture_filter::Nearest => self.sample_nearest(0, u, v),
+            texture_filter::Bilinear => self.sample_bilinear(0, u, v),
+            texture_filter::Bicubic => self.sample_bicubic(0, u, v),
+            texture_filter::Trilinear => self.sample_trilinear(u, v, uv_width),
         }
-
     }
 }
 impl texture for image_texture {
     fn value (&self, u: f64, v: f64, p: &Vec3) -> Vec3 {
-        if self.data.is_empty() {
+        self.value_filtered(u, v, p, 0.0)
+    }
+    fn value_filtered(&self, u: f64, v: f64, p: &Vec3, uv_width: f64) -> Vec3 {
+        if self.data.base.width == 0 || self.data.base.height == 0 {
             return Vec3::new(0.0, 1.0, 1.0);
         }
-        // println!("u: {}, v: {}", u, v);
-        let u2 = u;
-        let v2 = 1.0 - v;
-        let mut i = (u2 * self.width as f64) as usize;
-        let mut j = (v2 * self.height as f64) as usize;
-        if i >= self.width as usize {
-            i = self.width as usize - 1;
-        }
-        if j >= self.height as usize {
-            j = self.height as usize - 1;
+        let c = self.sample(u, v, uv_width);
+        Vec3::new(c[0], c[1], c[2])
+    }
+    fn alpha(&self, u: f64, v: f64, p: &Vec3) -> f64 {
+        if self.data.base.width == 0 || self.data.base.height == 0 {
+            return 1.0;
         }
-        let color_scale = 1.0 / 255.0;
-        let pixel_index = self.bytes_per_pixel as usize * i + self.bytes_per_scanline * j;
-        // println!("i: {}, j: {}, pixel_index: {}", i, j, pixel_index);
-        let r = self.data[pixel_index] as f64 * color_scale;
-        let g = self.data[pixel_index + 1] as f64 * color_scale;
-        let b = self.data[pixel_index + 2] as f64 * color_scale;
-        // println!("r: {}, g: {}, b: {}", r, g, b);
-        Vec3::new(r, g, b)
+        self.sample(u, v, 0.0)[3]
     }
 }
 
@@ -150,4 +496,265 @@ impl texture for noise_texture {
         // Vec3::new(1.0, 1.0, 1.0) * self.noise.turb(p, 7)
         Vec3::new(0.5, 0.5, 0.5) * (1.0 + (self.scale * p.z + 10.0 * self.noise.turb(p, 7)).sin())
     }
-}
\ No newline at end of file
+}
+fn mix(a: Vec3, b: Vec3, t: f64) -> Vec3 {
+    a * (1.0 - t) + b * t
+}
+
+pub struct marble_texture {
+    pub noise: Arc<dyn noise + Send + Sync>,
+    // frequency of the veins along axis
+    pub scale: f64,
+    pub turbulence: f64,
+    pub octaves: usize,
+    pub axis: Vec3,
+    pub base: Vec3,
+    pub vein: Vec3,
+}
+
+impl marble_texture {
+    // defaults reproduce noise_texture with scale 4
+    pub fn new(seed: u64) -> Self {
+        Self {
+            noise: Arc::new(perlin::new_with_seed(seed)),
+            scale: 4.0,
+            turbulence: 10.0,
+            octaves: 7,
+            axis: Vec3::new(0.0, 0.0, 1.0),
+            base: Vec3::new(1.0, 1.0, 1.0),
+            vein: Vec3::zero(),
+        }
+    }
+}
+
+impl texture for marble_texture {
+    fn value(&self, u: f64, v: f64, p: &Vec3) -> Vec3 {
+        let phase = self.scale * p.dot(self.axis) + self.turbulence * turbulence(self.noise.as_ref(), p, self.octaves);
+        mix(self.vein, self.base, 0.5 * (1.0 + phase.sin()))
+    }
+}
+
+pub struct wood_texture {
+    pub noise: Arc<dyn noise + Send + Sync>,
+    // rings per unit distance from the y axis
+    pub ring_frequency: f64,
+    // how far the noise pushes the rings around
+    pub grain: f64,
+    pub grain_scale: f64,
+    pub light: Vec3,
+    pub dark: Vec3,
+}
+
+impl wood_texture {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            noise: Arc::new(perlin::new_with_seed(seed)),
+            ring_frequency: 6.0,
+            grain: 0.6,
+            grain_scale: 1.5,
+            light: Vec3::new(0.75, 0.55, 0.33),
+            dark: Vec3::new(0.45, 0.26, 0.12),
+        }
+    }
+}
+
+impl texture for wood_texture {
+    fn value(&self, u: f64, v: f64, p: &Vec3) -> Vec3 {
+        let d = (p.x * p.x + p.z * p.z).sqrt();
+        let rings = d * self.ring_frequency + self.grain * fbm(self.noise.as_ref(), &(*p * self.grain_scale), 4, 2.0, 0.5);
+        // sharp dark edge at the end of each ring
+        let t = (rings - rings.floor()).powf(3.0);
+        mix(self.light, self.dark, t)
+    }
+}
+
+pub struct granite_texture {
+    pub cells: worley_noise,
+    pub speckle: Arc<dyn noise + Send + Sync>,
+    pub scale: f64,
+    pub base: Vec3,
+    pub dark: Vec3,
+    pub fleck: Vec3,
+    // fraction of the surface covered by light flecks
+    pub fleck_amount: f64,
+}
+
+impl granite_texture {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            cells: worley_noise::new(seed),
+            speckle: Arc::new(simplex_noise::new(seed.wrapping_add(1))),
+            scale: 8.0,
+            base: Vec3::new(0.55, 0.5, 0.48),
+            dark: Vec3::new(0.12, 0.11, 0.11),
+            fleck: Vec3::new(0.9, 0.88, 0.85),
+            fleck_amount: 0.15,
+        }
+    }
+}
+
+impl texture for granite_texture {
+    fn value(&self, u: f64, v: f64, p: &Vec3) -> Vec3 {
+        let q = *p * self.scale;
+        let (f1, f2) = self.cells.cellular(&q);
+        let speck = fbm(self.speckle.as_ref(), &(q * 4.0), 4, 2.0, 0.5);
+        let t = ((f2 - f1) * 2.0 + 0.3 * speck).max(0.0).min(1.0);
+        let color = mix(self.dark, self.base, t);
+        if speck > 1.0 - 2.0 * self.fleck_amount {
+            return self.fleck;
+        }
+        color
+    }
+}
+
+pub struct clouds_texture {
+    pub noise: Arc<dyn noise + Send + Sync>,
+    pub scale: f64,
+    pub octaves: usize,
+    // 0 gives clear sky, 1 overcast
+    pub coverage: f64,
+    pub sharpness: f64,
+    pub warp: f64,
+    pub sky: Vec3,
+    pub cloud: Vec3,
+}
+
+impl clouds_texture {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            noise: Arc::new(simplex_noise::new(seed)),
+            scale: 0.5,
+            octaves: 6,
+            coverage: 0.5,
+            sharpness: 3.0,
+            warp: 0.8,
+            sky: Vec3::new(0.3, 0.5, 0.9),
+            cloud: Vec3::new(1.0, 1.0, 1.0),
+        }
+    }
+}
+
+impl texture for clouds_texture {
+    fn value(&self, u: f64, v: f64, p: &Vec3) -> Vec3 {
+        let q = domain_warp(self.noise.as_ref(), &(*p * self.scale), self.warp);
+        let density = 0.5 * (1.0 + fbm(self.noise.as_ref(), &q, self.octaves, 2.0, 0.5));
+        let t = ((density - (1.0 - self.coverage)) * self.sharpness + 0.5).max(0.0).min(1.0);
+        mix(self.sky, self.cloud, t)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn float_image(width: usize, height: usize, f: impl Fn(usize, usize) -> f32) -> image_texture {
+        let pixels = (0..width * height)
+            .map(|i| {
+                let c = f(i % width, i / width);
+                [c, c, c, 1.0]
+            })
+            .collect();
+        let base = image_level { width, height, pixels: texels::Float(pixels), srgb: false };
+        image_texture::new_from_data(Arc::new(image_data { base, mips: OnceLock::new() }))
+    }
+
+    #[test]
+    fn test_wrap_coord() {
+        let mut tex = float_image(4, 4, |_, _| 0.0);
+        let wrapped = |tex: &image_texture| [-5, -1, 0, 3, 4, 9].map(|i| tex.wrap_coord(i, 4));
+        tex.wrap = wrap_mode::Repeat;
+        assert_eq!(wrapped(&tex), [Some(3), Some(3), Some(0), Some(3), Some(0), Some(1)]);
+        tex.wrap = wrap_mode::Mirror;
+        assert_eq!(wrapped(&tex), [Some(3), Some(0), Some(0), Some(3), Some(3), Some(1)]);
+        tex.wrap = wrap_mode::Clamp;
+        assert_eq!(wrapped(&tex), [Some(0), Some(0), Some(0), Some(3), Some(3), Some(3)]);
+        tex.wrap = wrap_mode::Border;
+        assert_eq!(wrapped(&tex), [None, None, Some(0), Some(3), None, None]);
+        assert_eq!(tex.wrap_coord(0, 0), None);
+    }
+
+    #[test]
+    fn test_bilinear_at_texel_centres() {
+        let mut tex = float_image(5, 3, |x, y| (x * 3 + y * 7) as f32 / 32.0);
+        tex.filter = texture_filter::Bilinear;
+        for y in 0..3 {
+            for x in 0..5 {
+                let (u, v) = ((x as f64 + 0.5) / 5.0, 1.0 - (y as f64 + 0.5) / 3.0);
+                assert!((tex.sample(u, v, 0.0)[0] - (x * 3 + y * 7) as f64 / 32.0).abs() < 1e-6);
+            }
+        }
+    }
+
+    #[test]
+    fn test_bicubic_constant_stays_in_range() {
+        for (c, wrap) in [(0.7, wrap_mode::Repeat), (1.0, wrap_mode::Clamp), (0.0, wrap_mode::Mirror)] {
+            let mut tex = float_image(4, 4, |_, _| c);
+            tex.filter = texture_filter::Bicubic;
+            tex.wrap = wrap;
+            for _ in 0..200 {
+                let s = tex.sample(random_double() * 1.4 - 0.2, random_double() * 1.4 - 0.2, 0.0);
+                assert!((s[0] - c as f64).abs() < 1e-6, "{} from {}", s[0], c);
+            }
+        }
+    }
+
+    #[test]
+    fn test_mip_levels() {
+        // 5x3 halves to 2x1 and then 1x1, dropping the odd column and row
+        let tex = float_image(5, 3, |x, y| (x + 5 * y) as f32);
+        let data = &tex.data;
+        assert_eq!(data.mips().len(), 2);
+        let level = data.level(1);
+        assert_eq!((level.width, level.height), (2, 1));
+        assert_eq!(level.texel(0, 0)[0], (0.0 + 1.0 + 5.0 + 6.0) / 4.0);
+        assert_eq!(level.texel(1, 0)[0], (2.0 + 3.0 + 7.0 + 8.0) / 4.0);
+        assert_eq!((data.level(2).width, data.level(2).height), (1, 1));
+        // a single column keeps a width of 1, its 2x2 boxes repeat the edge texel
+        let column = float_image(1, 4, |_, y| y as f32);
+        let level = column.data.level(1);
+        assert_eq!((level.width, level.height), (1, 2));
+        assert_eq!(level.texel(0, 1)[0], 2.5);
+        // an empty image has no levels and shows its error color
+        let empty = float_image(0, 0, |_, _| 0.0);
+        assert!(empty.data.mips().is_empty());
+        assert_eq!(empty.value(0.5, 0.5, &Vec3::zero()), Vec3::new(0.0, 1.0, 1.0));
+        assert_eq!(empty.alpha(0.5, 0.5, &Vec3::zero()), 1.0);
+    }
+
+    #[test]
+    fn test_trilinear_level_from_footprint() {
+        // a checker whose mip levels are all flat grey
+        let mut tex = float_image(8, 8, |x, y| ((x + y) % 2) as f32);
+        tex.filter = texture_filter::Trilinear;
+        // the centre of texel (1, 0), which is white
+        let (u, v) = (1.5 / 8.0, 1.0 - 0.5 / 8.0);
+        assert!((tex.sample(u, v, 0.0)[0] - 1.0).abs() < 1e-6);
+        assert!((tex.sample(u, v, 1.0 / 8.0)[0] - 1.0).abs() < 1e-6);
+        // a footprint of sqrt(2) texels is halfway to level 1
+        assert!((tex.sample(u, v, 2f64.sqrt() / 8.0)[0] - 0.75).abs() < 1e-6);
+        assert!((tex.sample(u, v, 2.0 / 8.0)[0] - 0.5).abs() < 1e-6);
+        // past the smallest level it stays there
+        assert!((tex.sample(u, v, 100.0)[0] - 0.5).abs() < 1e-6);
+        // tiling shrinks texels in uv, so the same footprint covers more of them
+        tex.uv_scale = (2.0, 2.0);
+        assert!((tex.sample(u / 2.0, v / 2.0 + 0.5, 1.0 / 8.0)[0] - 0.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_uv_mapping() {
+        let mut tex = float_image(2, 2, |_, _| 0.0);
+        tex.uv_scale = (2.0, 3.0);
+        tex.uv_offset = (0.1, 0.2);
+        let (u, v) = tex.transform_uv(0.1, 0.2);
+        assert!((u - 0.3).abs() < 1e-12 && (v - 0.8).abs() < 1e-12);
+        // scaled, then turned a quarter counterclockwise, then offset
+        tex.uv_rotation = 90.0;
+        let (u, v) = tex.transform_uv(0.1, 0.2);
+        assert!((u - (-0.6 + 0.1)).abs() < 1e-12 && (v - (0.2 + 0.2)).abs() < 1e-12);
+        // the mapping decides which texel is read
+        let mut quadrants = float_image(2, 2, |x, y| (x + 2 * y) as f32);
+        assert_eq!(quadrants.sample(0.25, 0.75, 0.0)[0], 0.0);
+        quadrants.uv_offset = (0.5, 0.0);
+        assert_eq!(quadrants.sample(0.25, 0.75, 0.0)[0], 1.0);
+    }
+}
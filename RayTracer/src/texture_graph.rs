@@ -0,0 +1,702 @@
+use crate::asset::asset_manager;
+use crate::color::luminance;
+use crate::hittable::hit_record;
+use crate::texture::*;
+use crate::vec3::Vec3;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+// Texture nodes that only combine other textures, plus a small text format to
+// wire them up from a scene file without writing new Rust types.
+
+// lets nodes implement only value_at while still answering plain uv lookups
+fn rec_at(u: f64, v: f64, p: &Vec3) -> hit_record {
+    let mut rec = hit_record::new();
+    rec.u = u;
+    rec.v = v;
+    rec.p = *p;
+    rec
+}
+
+pub struct add_texture {
+    pub a: Arc<dyn texture + Send + Sync>,
+    pub b: Arc<dyn texture + Send + Sync>,
+}
+impl texture for add_texture {
+    fn value(&self, u: f64, v: f64, p: &Vec3) -> Vec3 {
+        self.value_at(&rec_at(u, v, p))
+    }
+    fn value_at(&self, rec: &hit_record) -> Vec3 {
+        self.a.value_at(rec) + self.b.value_at(rec)
+    }
+}
+
+pub struct multiply_texture {
+    pub a: Arc<dyn texture + Send + Sync>,
+    pub b: Arc<dyn texture + Send + Sync>,
+}
+impl texture for multiply_texture {
+    fn value(&self, u: f64, v: f64, p: &Vec3) -> Vec3 {
+        self.value_at(&rec_at(u, v, p))
+    }
+    fn value_at(&self, rec: &hit_record) -> Vec3 {
+        self.a.value_at(rec) * self.b.value_at(rec)
+    }
+}
+
+// a where the mask is 0, b where it is 1
+pub struct lerp_texture {
+    pub a: Arc<dyn texture + Send + Sync>,
+    pub b: Arc<dyn texture + Send + Sync>,
+    pub mask: Arc<dyn texture + Send + Sync>,
+}
+impl texture for lerp_texture {
+    fn value(&self, u: f64, v: f64, p: &Vec3) -> Vec3 {
+        self.value_at(&rec_at(u, v, p))
+    }
+    fn value_at(&self, rec: &hit_record) -> Vec3 {
+        let t = luminance(self.mask.value_at(rec)).max(0.0).min(1.0);
+        self.a.value_at(rec) * (1.0 - t) + self.b.value_at(rec) * t
+    }
+}
+
+// maps the luminance of input through piecewise linear color stops
+pub struct color_ramp {
+    pub input: Arc<dyn texture + Send + Sync>,
+    // sorted by position
+    pub stops: Vec<(f64, Vec3)>,
+}
+impl color_ramp {
+    pub fn new(input: Arc<dyn texture + Send + Sync>, mut stops: Vec<(f64, Vec3)>) -> Self {
+        stops.sort_by(|a, b| a.0.total_cmp(&b.0));
+        Self { input, stops }
+    }
+    pub fn lookup(&self, t: f64) -> Vec3 {
+        if self.stops.is_empty() {
+            return Vec3::zero();
+        }
+        let first = self.stops[0];
+        if t <= first.0 {
+            return first.1;
+        }
+        for pair in self.stops.windows(2) {
+            let (p0, c0) = pair[0];
+            let (p1, c1) = pair[1];
+            if t <= p1 {
+                let f = if p1 > p0 { (t - p0) / (p1 - p0) } else { 1.0 };
+                return c0 * (1.0 - f) + c1 * f;
+            }
+        }
+        self.stops[self.stops.len() - 1].1
+    }
+}
+impl texture for color_ramp {
+    fn value(&self, u: f64, v: f64, p: &Vec3) -> Vec3 {
+        self.value_at(&rec_at(u, v, p))
+    }
+    fn value_at(&self, rec: &hit_record) -> Vec3 {
+        self.lookup(luminance(self.input.value_at(rec)))
+    }
+}
+
+// uv -> rotate(uv * scale) + offset before the input lookup, rotation in degrees
+pub struct uv_transform_texture {
+    pub input: Arc<dyn texture + Send + Sync>,
+    pub scale: (f64, f64),
+    pub offset: (f64, f64),
+    pub rotation: f64,
+}
+impl texture for uv_transform_texture {
+    fn value(&self, u: f64, v: f64, p: &Vec3) -> Vec3 {
+        self.value_at(&rec_at(u, v, p))
+    }
+    fn value_at(&self, rec: &hit_record) -> Vec3 {
+        let mut u = rec.u * self.scale.0;
+        let mut v = rec.v * self.scale.1;
+        if self.rotation != 0.0 {
+            let (sin, cos) = self.rotation.to_radians().sin_cos();
+            (u, v) = (cos * u - sin * v, sin * u + cos * v);
+        }
+        let mut moved = rec.clone();
+        moved.u = u + self.offset.0;
+        moved.v = v + self.offset.1;
+        moved.tangent = rec.tangent / self.scale.0;
+        moved.bitangent = rec.bitangent / self.scale.1;
+        self.input.value_at(&moved)
+    }
+}
+
+// p -> p * scale + offset before the input lookup
+pub struct position_transform_texture {
+    pub input: Arc<dyn texture + Send + Sync>,
+    pub scale: Vec3,
+    pub offset: Vec3,
+}
+impl texture for position_transform_texture {
+    fn value(&self, u: f64, v: f64, p: &Vec3) -> Vec3 {
+        self.value_at(&rec_at(u, v, p))
+    }
+    fn value_at(&self, rec: &hit_record) -> Vec3 {
+        let mut moved = rec.clone();
+        moved.p = rec.p * self.scale + self.offset;
+        self.input.value_at(&moved)
+    }
+}
+
+// projects a uv texture along the three axes and blends by the normal,
+// for surfaces without a usable uv layout
+pub struct triplanar_texture {
+    pub input: Arc<dyn texture + Send + Sync>,
+    pub scale: f64,
+    // higher is a harder transition between the projections
+    pub sharpness: f64,
+}
+impl texture for triplanar_texture {
+    fn value(&self, u: f64, v: f64, p: &Vec3) -> Vec3 {
+        self.value_at(&rec_at(u, v, p))
+    }
+    fn value_at(&self, rec: &hit_record) -> Vec3 {
+        let n = rec.normal;
+        let mut w = Vec3::new(
+            n.x.abs().powf(self.sharpness),
+            n.y.abs().powf(self.sharpness),
+            n.z.abs().powf(self.sharpness),
+        );
+        let sum = w.x + w.y + w.z;
+        if sum <= 0.0 {
+            // no normal (e.g. plain uv lookups), fall back to the top projection
+            w = Vec3::new(0.0, 1.0, 0.0);
+        } else {
+            w = w / sum;
+        }
+        let q = rec.p * self.scale;
+        let mut res = Vec3::zero();
+        if w.x > 0.0 {
+            res += self.input.value(q.z, q.y, &rec.p) * w.x;
+        }
+        if w.y > 0.0 {
+            res += self.input.value(q.x, q.z, &rec.p) * w.y;
+        }
+        if w.z > 0.0 {
+            res += self.input.value(q.x, q.y, &rec.p) * w.z;
+        }
+        res
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum texture_channel {
+    R,
+    G,
+    B,
+    A,
+    Luminance,
+}
+
+// one channel of input as a gray texture
+pub struct channel_texture {
+    pub input: Arc<dyn texture + Send + Sync>,
+    pub channel: texture_channel,
+}
+impl texture for channel_texture {
+    fn value(&self, u: f64, v: f64, p: &Vec3) -> Vec3 {
+        self.value_at(&rec_at(u, v, p))
+    }
+    fn value_at(&self, rec: &hit_record) -> Vec3 {
+        let x = match self.channel {
+            texture_channel::A => self.input.alpha(rec.u, rec.v, &rec.p),
+            texture_channel::R => self.input.value_at(rec).x,
+            texture_channel::G => self.input.value_at(rec).y,
+            texture_channel::B => self.input.value_at(rec).z,
+            texture_channel::Luminance => luminance(self.input.value_at(rec)),
+        };
+        Vec3::new(x, x, x)
+    }
+}
+
+// Scene file format: one `name = expression` per line, `#` starts a comment.
+// Expressions are numbers, "strings", earlier names, or calls such as
+//   ground = lerp(image("grass.png"), rgb(0.4, 0.3, 0.2), channel(noise(4), luminance))
+// image() and data_image() may name a wrap mode (repeat, mirror, clamp, border) and a
+// filter (nearest, bilinear, bicubic, trilinear) after the path, e.g. image("a.png", repeat).
+// Bare numbers are gray solid colors wherever a texture is expected.
+
+#[derive(Clone, PartialEq, Debug)]
+enum token {
+    Ident(String),
+    Number(f64),
+    Text(String),
+    LParen,
+    RParen,
+    Comma,
+}
+
+fn tokenize(src: &str) -> Result<Vec<token>, String> {
+    let chars: Vec<char> = src.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c == '(' {
+            tokens.push(token::LParen);
+            i += 1;
+        } else if c == ')' {
+            tokens.push(token::RParen);
+            i += 1;
+        } else if c == ',' {
+            tokens.push(token::Comma);
+            i += 1;
+        } else if c == '"' {
+            let start = i + 1;
+            let mut end = start;
+            while end < chars.len() && chars[end] != '"' {
+                end += 1;
+            }
+            if end >= chars.len() {
+                return Err("unterminated string".to_string());
+            }
+            tokens.push(token::Text(chars[start..end].iter().collect()));
+            i = end + 1;
+        } else if c.is_ascii_digit() || c == '-' || c == '.' {
+            let start = i;
+            i += 1;
+            while i < chars.len()
+                && (chars[i].is_ascii_digit()
+                    || chars[i] == '.'
+                    || chars[i] == 'e'
+                    || (chars[i] == '-' && chars[i - 1] == 'e'))
+            {
+                i += 1;
+            }
+            let text: String = chars[start..i].iter().collect();
+            match text.parse::<f64>() {
+                Ok(x) => tokens.push(token::Number(x)),
+                Err(_) => return Err(format!("bad number '{}'", text)),
+            }
+        } else if c.is_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            tokens.push(token::Ident(chars[start..i].iter().collect()));
+        } else {
+            return Err(format!("unexpected character '{}'", c));
+        }
+    }
+    Ok(tokens)
+}
+
+// line up to a # that isn't inside a quoted path
+fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            '#' if !quoted => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+enum node_value {
+    Number(f64),
+    Word(String),
+    Text(String),
+    Texture(Arc<dyn texture + Send + Sync>),
+}
+
+pub struct texture_graph<'a> {
+    pub assets: &'a asset_manager,
+    pub textures: HashMap<String, Arc<dyn texture + Send + Sync>>,
+}
+
+impl<'a> texture_graph<'a> {
+    pub fn new(assets: &'a asset_manager) -> Self {
+        Self {
+            assets,
+            textures: HashMap::new(),
+        }
+    }
+    // parses a whole scene file; image paths resolve through assets
+    pub fn parse_file(&mut self, path: &str) -> Result<(), String> {
+        let src = match std::fs::read_to_string(path) {
+            Ok(src) => src,
+            Err(e) => return Err(format!("{}: {}", path, e)),
+        };
+        self.parse(&src).map_err(|e| format!("{}: {}", path, e))
+    }
+    pub fn parse(&mut self, src: &str) -> Result<(), String> {
+        for (line_no, line) in src.lines().enumerate() {
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+            let Some((name, expr)) = line.split_once('=') else {
+                return Err(format!(
+                    "line {}: expected `name = expression`",
+                    line_no + 1
+                ));
+            };
+            let tex = self
+                .parse_expression(expr)
+                .map_err(|e| format!("line {}: {}", line_no + 1, e))?;
+            self.textures.insert(name.trim().to_string(), tex);
+        }
+        Ok(())
+    }
+    pub fn parse_expression(&self, src: &str) -> Result<Arc<dyn texture + Send + Sync>, String> {
+        let tokens = tokenize(src)?;
+        let mut pos = 0;
+        let value = self.parse_value(&tokens, &mut pos)?;
+        if pos != tokens.len() {
+            return Err("trailing input after expression".to_string());
+        }
+        self.to_texture(value)
+    }
+    pub fn get(&self, name: &str) -> Option<Arc<dyn texture + Send + Sync>> {
+        self.textures.get(name).cloned()
+    }
+
+    fn parse_value(&self, tokens: &[token], pos: &mut usize) -> Result<node_value, String> {
+        let Some(tok) = tokens.get(*pos) else {
+            return Err("unexpected end of expression".to_string());
+        };
+        *pos += 1;
+        match tok {
+            token::Number(x) => Ok(node_value::Number(*x)),
+            token::Text(s) => Ok(node_value::Text(s.clone())),
+            token::Ident(name) => {
+                if tokens.get(*pos) != Some(&token::LParen) {
+                    return Ok(node_value::Word(name.clone()));
+                }
+                *pos += 1;
+                let mut args = Vec::new();
+                if tokens.get(*pos) == Some(&token::RParen) {
+                    *pos += 1;
+                } else {
+                    loop {
+                        args.push(self.parse_value(tokens, pos)?);
+                        match tokens.get(*pos) {
+                            Some(token::Comma) => *pos += 1,
+                            Some(token::RParen) => {
+                                *pos += 1;
+                                break;
+                            }
+                            _ => return Err(format!("expected ',' or ')' in {}(...)", name)),
+                        }
+                    }
+                }
+                self.build(name, args).map(node_value::Texture)
+            }
+            _ => Err("expected a value".to_string()),
+        }
+    }
+
+    fn to_texture(&self, value: node_value) -> Result<Arc<dyn texture + Send + Sync>, String> {
+        match value {
+            node_value::Texture(t) => Ok(t),
+            node_value::Number(x) => Ok(Arc::new(solid_color::new(Vec3::new(x, x, x)))),
+            node_value::Word(name) => self.get(&name).ok_or(format!("unknown texture '{}'", name)),
+            node_value::Text(s) => Err(format!("expected a texture, found \"{}\"", s)),
+        }
+    }
+
+    fn build(
+        &self,
+        name: &str,
+        args: Vec<node_value>,
+    ) -> Result<Arc<dyn texture + Send + Sync>, String> {
+        let argc = args.len();
+        let expect = |n: usize| -> Result<(), String> {
+            if argc == n {
+                Ok(())
+            } else {
+                Err(format!("{} takes {} arguments, got {}", name, n, argc))
+            }
+        };
+        let num = |v: &node_value| -> Result<f64, String> {
+            match v {
+                node_value::Number(x) => Ok(*x),
+                _ => Err(format!("{} expects a number", name)),
+            }
+        };
+        let mut args = args.into_iter();
+        let mut next = || args.next().unwrap();
+        let tex: Arc<dyn texture + Send + Sync> = match name {
+            "rgb" => {
+                expect(3)?;
+                Arc::new(solid_color::new(Vec3::new(
+                    num(&next())?,
+                    num(&next())?,
+                    num(&next())?,
+                )))
+            }
+            "image" | "data_image" => {
+                if argc < 1 || argc > 3 {
+                    return Err(format!(
+                        "{} takes a path and optional wrap and filter, got {} arguments",
+                        name, argc
+                    ));
+                }
+                let node_value::Text(path) = next() else {
+                    return Err(format!("{} expects a \"path\"", name));
+                };
+                let data = self
+                    .assets
+                    .load_image(&path, name == "image")
+                    .map_err(|e| e.to_string())?;
+                // each node samples the shared pixels its own way
+                let mut image = image_texture::new_from_data(data);
+                for _ in 1..argc {
+                    let node_value::Word(w) = next() else {
+                        return Err(format!(
+                            "{} expects wrap and filter names after the path",
+                            name
+                        ));
+                    };
+                    match w.as_str() {
+                        "repeat" => image.wrap = wrap_mode::Repeat,
+                        "mirror" => image.wrap = wrap_mode::Mirror,
+                        "clamp" => image.wrap = wrap_mode::Clamp,
+                        "border" => image.wrap = wrap_mode::Border,
+                        "nearest" => image.filter = texture_filter::Nearest,
+                        "bilinear" => image.filter = texture_filter::Bilinear,
+                        "bicubic" => image.filter = texture_filter::Bicubic,
+                        "trilinear" => image.filter = texture_filter::Trilinear,
+                        _ => return Err(format!("unknown wrap or filter '{}'", w)),
+                    }
+                }
+                Arc::new(image)
+            }
+            "checker" => {
+                expect(3)?;
+                let scale = num(&next())?;
+                Arc::new(checker_texture::new(
+                    scale,
+                    self.to_texture(next())?,
+                    self.to_texture(next())?,
+                ))
+            }
+            "uv_checker" => {
+                expect(4)?;
+                let u_cells = num(&next())?;
+                let v_cells = num(&next())?;
+                Arc::new(uv_checker_texture::new(
+                    u_cells,
+                    v_cells,
+                    self.to_texture(next())?,
+                    self.to_texture(next())?,
+                ))
+            }
+            "uv_grid" => {
+                expect(1)?;
+                Arc::new(uv_debug_texture::new(num(&next())?))
+            }
+            "normals" => {
+                expect(0)?;
+                Arc::new(normal_texture::new())
+            }
+            "noise" => {
+                expect(1)?;
+                Arc::new(noise_texture::new_with_scale(num(&next())?))
+            }
+            "marble" | "wood" | "granite" | "clouds" => {
+                expect(1)?;
+                let seed = num(&next())? as u64;
+                match name {
+                    "marble" => Arc::new(marble_texture::new(seed)),
+                    "wood" => Arc::new(wood_texture::new(seed)),
+                    "granite" => Arc::new(granite_texture::new(seed)),
+                    _ => Arc::new(clouds_texture::new(seed)),
+                }
+            }
+            "add" | "multiply" => {
+                expect(2)?;
+                let a = self.to_texture(next())?;
+                let b = self.to_texture(next())?;
+                if name == "add" {
+                    Arc::new(add_texture { a, b })
+                } else {
+                    Arc::new(multiply_texture { a, b })
+                }
+            }
+            "lerp" => {
+                expect(3)?;
+                Arc::new(lerp_texture {
+                    a: self.to_texture(next())?,
+                    b: self.to_texture(next())?,
+                    mask: self.to_texture(next())?,
+                })
+            }
+            "ramp" => {
+                if argc < 3 || argc % 2 == 0 {
+                    return Err("ramp takes an input and position, color pairs".to_string());
+                }
+                let input = self.to_texture(next())?;
+                let mut stops = Vec::new();
+                for _ in 0..(argc - 1) / 2 {
+                    let position = num(&next())?;
+                    if !position.is_finite() {
+                        return Err(format!("ramp position {} is not a finite number", position));
+                    }
+                    let color = self.to_texture(next())?.value(0.0, 0.0, &Vec3::zero());
+                    stops.push((position, color));
+                }
+                Arc::new(color_ramp::new(input, stops))
+            }
+            "uv_transform" => {
+                expect(6)?;
+                let input = self.to_texture(next())?;
+                let scale = (num(&next())?, num(&next())?);
+                let offset = (num(&next())?, num(&next())?);
+                Arc::new(uv_transform_texture {
+                    input,
+                    scale,
+                    offset,
+                    rotation: num(&next())?,
+                })
+            }
+            "position_transform" => {
+                expect(3)?;
+                let input = self.to_texture(next())?;
+                let s = num(&next())?;
+                let o = num(&next())?;
+                Arc::new(position_transform_texture {
+                    input,
+                    scale: Vec3::new(s, s, s),
+                    offset: Vec3::new(o, o, o),
+                })
+            }
+            "triplanar" => {
+                expect(3)?;
+                Arc::new(triplanar_texture {
+                    input: self.to_texture(next())?,
+                    scale: num(&next())?,
+                    sharpness: num(&next())?,
+                })
+            }
+            "channel" => {
+                expect(2)?;
+                let input = self.to_texture(next())?;
+                let channel = match next() {
+                    node_value::Word(w) => match w.as_str() {
+                        "r" => texture_channel::R,
+                        "g" => texture_channel::G,
+                        "b" => texture_channel::B,
+                        "a" => texture_channel::A,
+                        "luminance" => texture_channel::Luminance,
+                        _ => return Err(format!("unknown channel '{}'", w)),
+                    },
+                    _ => return Err("channel expects r, g, b, a or luminance".to_string()),
+                };
+                Arc::new(channel_texture { input, channel })
+            }
+            _ => return Err(format!("unknown node '{}'", name)),
+        };
+        Ok(tex)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_graph() {
+        let assets = asset_manager::new();
+        let mut graph = texture_graph::new(&assets);
+        graph
+            .parse(
+                "
+            # two gray levels mixed half way
+            dark = rgb(0.2, 0.2, 0.2)
+            mixed = lerp(dark, 1, 0.5)
+            ramped = ramp(mixed, 0, rgb(0, 0, 0), 1, rgb(1, 0, 0))
+        ",
+            )
+            .unwrap();
+        let c = graph.get("mixed").unwrap().value(0.0, 0.0, &Vec3::zero());
+        assert!((c.x - 0.6).abs() < 1e-9);
+        let r = graph.get("ramped").unwrap().value(0.0, 0.0, &Vec3::zero());
+        assert!((r.x - 0.6).abs() < 1e-9 && r.y == 0.0);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let assets = asset_manager::new();
+        let mut graph = texture_graph::new(&assets);
+        assert!(graph.parse("a = lerp(1, 2)").is_err());
+        assert!(graph.parse("a = missing_name").is_err());
+        assert!(graph.parse("a = image(\"no_such_file.png\")").is_err());
+        assert!(graph
+            .parse("a = ramp(1, 1e999, rgb(0, 0, 0), 1, rgb(1, 1, 1))")
+            .is_err());
+    }
+
+    #[test]
+    fn test_ramp_sorts_nan_stops() {
+        let ramp = color_ramp::new(
+            Arc::new(solid_color::new(Vec3::zero())),
+            vec![
+                (1.0, Vec3::ones()),
+                (f64::NAN, Vec3::zero()),
+                (0.0, Vec3::zero()),
+            ],
+        );
+        assert_eq!(ramp.stops[0].0, 0.0);
+    }
+
+    #[test]
+    fn test_hash_inside_quotes() {
+        assert_eq!(
+            strip_comment("a = image(\"#1.png\") # note"),
+            "a = image(\"#1.png\") "
+        );
+    }
+
+    #[test]
+    fn test_scene_file() {
+        let path = "scenes/graph_textures.graph";
+        let assets = asset_manager::for_scene_file(path);
+        let mut graph = texture_graph::new(&assets);
+        graph.parse_file(path).unwrap();
+        for name in ["ground", "stone", "meadow", "timber"] {
+            assert!(graph.get(name).is_some());
+        }
+        assert_eq!(assets.cached_images(), 1);
+        // the grass tiles instead of smearing its edge texels, unlike a plain image()
+        let grass = graph.get("grass").unwrap();
+        let plain = graph
+            .parse_expression("uv_transform(image(\"../grass.png\"), 4, 4, 0, 0, 0)")
+            .unwrap();
+        let p = Vec3::zero();
+        assert!((grass.value(0.3, 0.45, &p) - grass.value(0.05, 0.2, &p)).near_zero());
+        assert!(!(grass.value(0.3, 0.45, &p) - plain.value(0.3, 0.45, &p)).near_zero());
+        assert_eq!(assets.cached_images(), 1);
+    }
+
+    #[test]
+    fn test_image_wrap_and_filter() {
+        let assets = asset_manager::new();
+        let graph = texture_graph::new(&assets);
+        let clamped = graph.parse_expression("image(\"leg_front.png\")").unwrap();
+        let repeated = graph
+            .parse_expression("image(\"leg_front.png\", repeat, bilinear)")
+            .unwrap();
+        let p = Vec3::zero();
+        assert_eq!(repeated.value(1.25, 0.5, &p), repeated.value(0.25, 0.5, &p));
+        assert_eq!(
+            clamped.value(1.25, 0.5, &p),
+            clamped.value(0.99999, 0.5, &p)
+        );
+        assert!(graph
+            .parse_expression("image(\"leg_front.png\", sideways)")
+            .is_err());
+        assert!(graph
+            .parse_expression("image(\"leg_front.png\", 1)")
+            .is_err());
+    }
+}
@@ -0,0 +1,172 @@
+use crate::ray::Ray;
+use crate::vec3::Vec3;
+use crate::hittable::{hit_record, Hittable, ALPHA_CUTOFF};
+use crate::interval::Interval;
+use crate::material::Material;
+use crate::aabb::aabb;
+use crate::light::{light, triangle_light};
+use std::sync::Arc;
+
+pub struct Triangle {
+    pub q: Vec3,
+    pub u: Vec3,
+    pub v: Vec3,
+    pub w: Vec3,
+    pub uv: [(f64, f64); 3],
+    pub mat: Arc<dyn Material + Send + Sync>,
+    pub bbox: aabb,
+    pub normal: Vec3,
+    pub d: f64,
+    pub tangent: Vec3,
+    pub bitangent: Vec3,
+}
+
+impl Triangle {
+    pub fn new(a: Vec3, b: Vec3, c: Vec3, mat: Arc<dyn Material + Send + Sync>) -> Self {
+        Self::new_with_uv(a, b, c, [(0.0, 0.0), (1.0, 0.0), (0.0, 1.0)], mat)
+    }
+    pub fn new_with_uv(a: Vec3, b: Vec3, c: Vec3, uv: [(f64, f64); 3], mat: Arc<dyn Material + Send + Sync>) -> Self {
+        let u = b - a;
+        let v = c - a;
+        let n = Vec3::cross(u, v);
+        let normal = n.normalize();
+
+        // solve [u v] = [dpdu dpdv] * [[du1 du2] [dv1 dv2]] for the tangent frame
+        let du1 = uv[1].0 - uv[0].0;
+        let dv1 = uv[1].1 - uv[0].1;
+        let du2 = uv[2].0 - uv[0].0;
+        let dv2 = uv[2].1 - uv[0].1;
+        let det = du1 * dv2 - du2 * dv1;
+        let (tangent, bitangent) = if det.abs() < 1e-12 {
+            (u, v)
+        } else {
+            let inv_det = 1.0 / det;
+            ((u * dv2 - v * dv1) * inv_det, (v * du1 - u * du2) * inv_det)
+        };
+
+        let bbox = aabb::new_from_aabbs(&aabb::new_from_points(a, b), &aabb::new_from_points(a, c));
+        Self {
+            q: a,
+            u,
+            v,
+            w: n / n.dot(n),
+            uv,
+            mat,
+            bbox,
+            normal,
+            d: normal.dot(a),
+            tangent,
+            bitangent,
+        }
+    }
+}
+
+impl Hittable for Triangle {
+    fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut hit_record) -> bool {
+        let denom = self.normal.dot(r.direction());
+        if denom.abs() < 1e-8 {
+            return false;
+        }
+
+        let t = (self.d - self.normal.dot(r.origin())) / denom;
+        if !ray_t.contains(t) {
+            return false;
+        }
+        let intersection = r.at(t);
+        let planar_hitpt_vector = intersection - self.q;
+        let alpha = self.w.dot(Vec3::cross(planar_hitpt_vector, self.v));
+        let beta = self.w.dot(Vec3::cross(self.u, planar_hitpt_vector));
+        if alpha < 0.0 || beta < 0.0 || alpha + beta > 1.0 {
+            return false;
+        }
+
+        let gamma = 1.0 - alpha - beta;
+        let u = gamma * self.uv[0].0 + alpha * self.uv[1].0 + beta * self.uv[2].0;
+        let v = gamma * self.uv[0].1 + alpha * self.uv[1].1 + beta * self.uv[2].1;
+        if self.mat.alpha(u, v, &intersection) < ALPHA_CUTOFF {
+            return false;
+        }
+
+        rec.u = u;
+        rec.v = v;
+        rec.t = t;
+        rec.p = intersection;
+        rec.mat = self.mat.clone();
+        rec.set_face_normal(r, self.normal);
+        rec.tangent = self.tangent;
+        rec.bitangent = self.bitangent;
+        return true;
+    }
+
+    fn bounding_box(&self) -> aabb {
+        return self.bbox;
+    }
+    fn emitters(&self, lights: &mut Vec<Arc<dyn light + Send + Sync>>) {
+        if let Some(mat) = self.mat.emitter() {
+            lights.push(Arc::new(triangle_light::new_from_material(self.q, self.q + self.u, self.q + self.v, self.uv, mat)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::lambertian;
+
+    fn shoot(tri: &Triangle, x: f64, y: f64, from_below: bool) -> Option<hit_record> {
+        let (z, dz) = if from_below { (-1.0, 1.0) } else { (1.0, -1.0) };
+        let mut rec = hit_record::new();
+        let r = Ray::new(Vec3::new(x, y, z), Vec3::new(0.0, 0.0, dz));
+        if tri.hit(&r, Interval::new(0.001, f64::INFINITY), &mut rec) { Some(rec) } else { None }
+    }
+
+    fn triangle() -> Triangle {
+        let uv = [(0.1, 0.2), (0.9, 0.3), (0.4, 0.8)];
+        Triangle::new_with_uv(Vec3::new(0.0, 0.0, 0.0), Vec3::new(2.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0), uv, Arc::new(lambertian::new(Vec3::ones())))
+    }
+
+    #[test]
+    fn test_edges_and_back_face() {
+        let tri = triangle();
+        // on the edges counts, just past them doesn't
+        for (x, y, inside) in [(1.0, 0.0, true), (1.0, -1e-6, false), (0.0, 0.5, true), (-1e-6, 0.5, false), (1.0, 0.5, true), (1.0 + 1e-6, 0.5, false)] {
+            assert_eq!(shoot(&tri, x, y, false).is_some(), inside, "({}, {})", x, y);
+        }
+        let front = shoot(&tri, 0.5, 0.25, false).unwrap();
+        assert!(front.front_face && (front.normal - Vec3::new(0.0, 0.0, 1.0)).near_zero());
+        let back = shoot(&tri, 0.5, 0.25, true).unwrap();
+        assert!(!back.front_face && (back.normal - Vec3::new(0.0, 0.0, -1.0)).near_zero());
+        // edge on rays miss
+        let mut rec = hit_record::new();
+        assert!(!tri.hit(&Ray::new(Vec3::new(-1.0, 0.25, 0.0), Vec3::new(1.0, 0.0, 0.0)), Interval::new(0.001, f64::INFINITY), &mut rec));
+    }
+
+    #[test]
+    fn test_uv_at_vertices() {
+        let tri = triangle();
+        for (k, (x, y)) in [(0.0, 0.0), (2.0, 0.0), (0.0, 1.0)].into_iter().enumerate() {
+            let rec = shoot(&tri, x, y, false).unwrap();
+            assert!((rec.u - tri.uv[k].0).abs() < 1e-12 && (rec.v - tri.uv[k].1).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn test_tangent_frame() {
+        let tri = triangle();
+        let rec = shoot(&tri, 0.5, 0.25, false).unwrap();
+        assert!(rec.tangent.dot(tri.normal).abs() < 1e-12 && rec.bitangent.dot(tri.normal).abs() < 1e-12);
+        // stepping along the tangent moves u alone, along the bitangent v alone
+        let h = 0.01;
+        let along = |d: Vec3| {
+            let p = rec.p + d * h;
+            shoot(&tri, p.x, p.y, false).unwrap()
+        };
+        let du = along(rec.tangent);
+        assert!((du.u - rec.u - h).abs() < 1e-9 && (du.v - rec.v).abs() < 1e-9);
+        let dv = along(rec.bitangent);
+        assert!((dv.u - rec.u).abs() < 1e-9 && (dv.v - rec.v - h).abs() < 1e-9);
+        // without a uv mapping to follow, the edges stand in
+        let flat = Triangle::new_with_uv(tri.q, tri.q + tri.u, tri.q + tri.v, [(0.5, 0.5); 3], tri.mat.clone());
+        assert!((flat.tangent - flat.u).near_zero() && (flat.bitangent - flat.v).near_zero());
+    }
+}
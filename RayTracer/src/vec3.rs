@@ -2,7 +2,7 @@ use std::ops::{Add, AddAssign};
 use std::ops::Sub;
 use std::ops::Mul;
 use std::ops::Div;
-use crate::raytracer::random_double_range;
+use crate::raytracer::{random_double, random_double_range};
 use std::ops::{Index, IndexMut};
 
 #[derive(Clone, Debug, PartialEq, Copy)]
@@ -45,10 +45,10 @@ impl Vec3 {
         Self::new(other[0], other[1], other[2])
     }
     pub fn random() -> Self {
-        Self::new(rand::random::<f64>(), rand::random::<f64>(), rand::random::<f64>())
+        Self::new(random_double(), random_double(), random_double())
     }
     pub fn random_with_range(min: f64, max: f64) -> Self {
-        Self::new(rand::random::<f64>()*(max-min)+min, rand::random::<f64>()*(max-min)+min, rand::random::<f64>()*(max-min)+min)
+        Self::new(random_double_range(min, max), random_double_range(min, max), random_double_range(min, max))
     }
     pub fn random_in_unit_sphere() -> Self {
         loop {